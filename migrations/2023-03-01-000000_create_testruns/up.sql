@@ -0,0 +1,17 @@
+CREATE TABLE testruns (
+    id TEXT NOT NULL PRIMARY KEY,
+    language TEXT NOT NULL,
+    status TEXT NOT NULL,
+    files TEXT NOT NULL
+);
+
+CREATE TABLE tests (
+    testrun_id TEXT NOT NULL REFERENCES testruns (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    status TEXT NOT NULL,
+    message TEXT NOT NULL,
+    actual_output TEXT NOT NULL,
+    expected_output TEXT NOT NULL,
+    PRIMARY KEY (testrun_id, position)
+);
@@ -0,0 +1,5 @@
+DROP INDEX testruns_challenge_id;
+DROP INDEX testruns_user_id;
+
+ALTER TABLE testruns DROP COLUMN challenge_id;
+ALTER TABLE testruns DROP COLUMN user_id;
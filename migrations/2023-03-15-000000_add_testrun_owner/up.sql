@@ -0,0 +1,5 @@
+ALTER TABLE testruns ADD COLUMN user_id TEXT;
+ALTER TABLE testruns ADD COLUMN challenge_id TEXT;
+
+CREATE INDEX testruns_user_id ON testruns (user_id, id);
+CREATE INDEX testruns_challenge_id ON testruns (challenge_id, id);
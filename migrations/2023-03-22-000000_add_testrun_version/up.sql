@@ -0,0 +1 @@
+ALTER TABLE testruns ADD COLUMN version BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+DROP INDEX testruns_expires_at;
+ALTER TABLE testruns DROP COLUMN expires_at;
@@ -0,0 +1,3 @@
+ALTER TABLE testruns ADD COLUMN expires_at BIGINT;
+
+CREATE INDEX testruns_expires_at ON testruns (expires_at);
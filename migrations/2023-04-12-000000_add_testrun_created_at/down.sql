@@ -0,0 +1 @@
+ALTER TABLE testruns DROP COLUMN created_at;
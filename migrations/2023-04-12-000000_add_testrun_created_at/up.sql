@@ -0,0 +1 @@
+ALTER TABLE testruns ADD COLUMN created_at TEXT;
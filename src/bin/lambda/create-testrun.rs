@@ -0,0 +1,38 @@
+use lambda_http::{service_fn, Request};
+use testrunner::{
+    entrypoints::{api::ApiConfig, lambda::apigateway::create_testrun},
+    utils::*,
+};
+
+type E = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+#[tokio::main]
+async fn main() -> Result<(), E> {
+    // Initialize logger
+    setup_tracing();
+
+    // Initialize store
+    let store = get_store().await;
+
+    // Initialize rate limiter
+    let limiter = get_rate_limiter().await;
+
+    // Initialize authenticator
+    let auth = get_authenticator();
+
+    // Read the submission settings
+    let config = ApiConfig::from_env();
+
+    // Run the Lambda function
+    lambda_http::run(service_fn(|event: Request| {
+        create_testrun(
+            store.as_ref(),
+            limiter.as_ref(),
+            &config,
+            auth.as_ref(),
+            event,
+        )
+    }))
+    .await?;
+    Ok(())
+}
@@ -1,7 +1,7 @@
 use lambda_runtime::{service_fn, LambdaEvent};
 use testrunner::{
     entrypoints::lambda::dynamodb::{model::DynamoDBEvent, parse_events},
-    utils::{get_event_bus, setup_tracing},
+    utils::{get_blob_store, get_event_bus, setup_tracing},
 };
 
 #[tokio::main]
@@ -12,10 +12,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>
     // Initialize event bus
     let event_bus = get_event_bus().await;
 
+    // Initialize blob store, to read back spilled payloads
+    let blob_store = get_blob_store().await;
+
     // Run the Lambda function
     lambda_runtime::run(service_fn(|event: LambdaEvent<DynamoDBEvent>| {
         let (event, ctx) = event.into_parts();
-        parse_events(&event_bus, event, ctx)
+        parse_events(event_bus.as_ref(), blob_store.as_deref(), event, ctx)
     }))
     .await?;
 
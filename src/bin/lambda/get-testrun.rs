@@ -11,7 +11,13 @@ async fn main() -> Result<(), E> {
     // Initialize store
     let store = get_store().await;
 
+    // Initialize authenticator
+    let auth = get_authenticator();
+
     // Run the Lambda function
-    lambda_http::run(service_fn(|event: Request| get_testrun(&store, event))).await?;
+    lambda_http::run(service_fn(|event: Request| {
+        get_testrun(store.as_ref(), auth.as_ref(), event)
+    }))
+    .await?;
     Ok(())
 }
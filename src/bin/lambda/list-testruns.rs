@@ -0,0 +1,23 @@
+use lambda_http::{service_fn, Request};
+use testrunner::{entrypoints::lambda::apigateway::list_testruns, utils::*};
+
+type E = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+#[tokio::main]
+async fn main() -> Result<(), E> {
+    // Initialize logger
+    setup_tracing();
+
+    // Initialize store
+    let store = get_store().await;
+
+    // Initialize authenticator
+    let auth = get_authenticator();
+
+    // Run the Lambda function
+    lambda_http::run(service_fn(|event: Request| {
+        list_testruns(store.as_ref(), auth.as_ref(), event)
+    }))
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use std::{net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
+use testrunner::{
+    entrypoints::{
+        api::ApiConfig,
+        server::{purge_periodically, serve, shutdown_signal, Services, DEFAULT_MAX_BODY_SIZE},
+    },
+    utils::*,
+};
+
+type E = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+#[tokio::main]
+async fn main() -> Result<(), E> {
+    // Initialize logger
+    setup_tracing();
+
+    // Initialize store
+    let store: Arc<dyn testrunner::store::Store> = Arc::from(get_store().await);
+
+    // Initialize authenticator and rate limiter
+    let services = Services {
+        store: store.clone(),
+        auth: Arc::from(get_authenticator()),
+        limiter: Arc::from(get_rate_limiter().await),
+        config: ApiConfig::from_env(),
+        runner_token: std::env::var("RUNNER_TOKEN").ok(),
+        max_body_size: env_var("MAX_BODY_SIZE").unwrap_or(DEFAULT_MAX_BODY_SIZE),
+    };
+
+    // Purge expired testruns in the background
+    let purge_interval = env_var("PURGE_INTERVAL_SECS").unwrap_or(3600);
+    if purge_interval == 0 {
+        panic!("Unsupported PURGE_INTERVAL_SECS: {}", purge_interval);
+    }
+    tokio::spawn(purge_periodically(
+        store,
+        Duration::from_secs(purge_interval),
+    ));
+
+    // Run the HTTP server
+    let addr = env_var("BIND_ADDRESS").unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 3000)));
+    serve(services, addr, shutdown_signal()).await?;
+
+    Ok(())
+}
+
+/// Parse the environment variable `name`, if set
+fn env_var<T: FromStr>(name: &str) -> Option<T> {
+    let value = std::env::var(name).ok()?;
+    Some(
+        value
+            .parse()
+            .unwrap_or_else(|_| panic!("Unsupported {}: {}", name, value)),
+    )
+}
@@ -0,0 +1,269 @@
+//! # Idempotency keys
+//!
+//! A client can send an `Idempotency-Key` header with a write. The response is
+//! recorded with a hash of the request, so that a retry with the same key gets
+//! the same response back without writing again.
+
+use crate::{
+    error::Error,
+    store::{IdempotencyRecord, StoreIdempotency},
+};
+use chrono::{DateTime, Duration, Utc};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+/// Longest accepted `Idempotency-Key`
+pub const MAX_KEY_LENGTH: usize = 255;
+
+/// How long a response is replayed for, by default
+pub fn default_window() -> Duration {
+    Duration::hours(24)
+}
+
+/// How long a response is replayed for
+///
+/// `IDEMPOTENCY_WINDOW_HOURS` overrides the default of 24 hours, and must be a
+/// number of hours when set.
+pub fn window_from_env() -> Duration {
+    match std::env::var("IDEMPOTENCY_WINDOW_HOURS") {
+        Ok(value) => {
+            let hours: u32 = value
+                .parse()
+                .unwrap_or_else(|_| panic!("Unsupported IDEMPOTENCY_WINDOW_HOURS: {}", value));
+            Duration::hours(i64::from(hours))
+        }
+        Err(_) => default_window(),
+    }
+}
+
+/// Hash identifying a request, to tell a retry from a different request
+/// reusing the same key
+pub fn request_hash(method: &str, path: &str, content_type: Option<&str>, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    for part in [
+        method.as_bytes(),
+        path.as_bytes(),
+        content_type.unwrap_or("").as_bytes(),
+    ] {
+        // Length-prefixed so that parts cannot run into each other
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part);
+    }
+    hasher.update(body);
+
+    format!("{:x}", hasher.finalize())
+}
+
+/// How long a key stays reserved for a request being handled
+///
+/// A request that never completes, such as one whose process crashed, only
+/// blocks retries until then.
+pub fn reservation_timeout() -> Duration {
+    Duration::minutes(1)
+}
+
+/// Outcome of reserving an idempotency key
+#[derive(Debug, PartialEq)]
+pub enum Lookup {
+    /// The key wasn't used yet and is now reserved, the request should be
+    /// handled
+    New,
+    /// The same request was already handled, with this response
+    Replay(IdempotencyRecord),
+    /// The same request is still being handled
+    InProgress,
+    /// The key was already used for a different request
+    Mismatch,
+}
+
+/// Reserve a key for a request, or look up the request already holding it
+///
+/// The reservation is a conditional write, so of concurrent requests with the
+/// same key only one gets `Lookup::New`. Once handled, the response must be
+/// recorded with `complete`, or the key given up with `release`.
+pub async fn reserve(
+    store: &dyn StoreIdempotency,
+    key: &str,
+    request_hash: &str,
+    now: DateTime<Utc>,
+) -> Result<Lookup, Error> {
+    let reservation = IdempotencyRecord::in_progress(
+        key.to_owned(),
+        request_hash.to_owned(),
+        now + reservation_timeout(),
+    );
+    match store.put_idempotency(&reservation).await {
+        Ok(()) => return Ok(Lookup::New),
+        Err(Error::ConflictError(_)) => (),
+        Err(err) => return Err(err),
+    }
+
+    Ok(match store.get_idempotency(key, now).await? {
+        Some(record) if record.request_hash != request_hash => Lookup::Mismatch,
+        Some(record) if !record.is_in_progress() => Lookup::Replay(record),
+        // Also released or expired since, the client can retry
+        _ => Lookup::InProgress,
+    })
+}
+
+/// Record the response to a request that reserved its key
+///
+/// The reservation may have expired and been taken by a concurrent request
+/// in the meantime, in which case that one is kept.
+pub async fn complete(
+    store: &dyn StoreIdempotency,
+    record: &IdempotencyRecord,
+) -> Result<(), Error> {
+    match store.update_idempotency(record).await {
+        Err(Error::ConflictError(_)) => {
+            warn!("Idempotency key '{}' was taken concurrently", record.key);
+            Ok(())
+        }
+        res => res,
+    }
+}
+
+/// Give up the key reserved by a request, so that it can be retried
+pub async fn release(
+    store: &dyn StoreIdempotency,
+    key: &str,
+    request_hash: &str,
+) -> Result<(), Error> {
+    store.delete_idempotency(key, request_hash).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use chrono::TimeZone;
+    use std::collections::BTreeMap;
+
+    fn get_record(request_hash: &str) -> IdempotencyRecord {
+        IdempotencyRecord {
+            key: "key".to_owned(),
+            request_hash: request_hash.to_owned(),
+            status: 201,
+            headers: BTreeMap::new(),
+            body: "{}".to_owned(),
+            expires_at: Utc.timestamp_opt(4_000_000_000, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn request_hash_parts() {
+        let hash = request_hash("PUT", "/1", Some("application/json"), b"{}");
+
+        assert_eq!(
+            hash,
+            request_hash("PUT", "/1", Some("application/json"), b"{}")
+        );
+        assert_ne!(
+            hash,
+            request_hash("PUT", "/2", Some("application/json"), b"{}")
+        );
+        assert_ne!(hash, request_hash("PUT", "/1", None, b"{}"));
+        assert_ne!(
+            hash,
+            request_hash("PUT", "/1", Some("application/json"), b"[]")
+        );
+        // Moving bytes from one part to the next changes the hash
+        assert_ne!(
+            request_hash("PUT", "/1", Some("a"), b"b"),
+            request_hash("PUT", "/1", Some("ab"), b"")
+        );
+    }
+
+    #[tokio::test]
+    async fn reserve_outcomes() {
+        let store = MemoryStore::new();
+        let now = Utc::now();
+
+        // GIVEN a key that wasn't used yet
+        assert_eq!(
+            reserve(&store, "key", "hash", now).await.unwrap(),
+            Lookup::New
+        );
+
+        // WHEN its request is still being handled
+        // THEN the same request waits, and others are refused
+        assert_eq!(
+            reserve(&store, "key", "hash", now).await.unwrap(),
+            Lookup::InProgress
+        );
+        assert_eq!(
+            reserve(&store, "key", "other", now).await.unwrap(),
+            Lookup::Mismatch
+        );
+
+        // WHEN a response is recorded for it
+        complete(&store, &get_record("hash")).await.unwrap();
+
+        // THEN only the same request gets it back
+        assert_eq!(
+            reserve(&store, "key", "hash", now).await.unwrap(),
+            Lookup::Replay(get_record("hash"))
+        );
+        assert_eq!(
+            reserve(&store, "key", "other", now).await.unwrap(),
+            Lookup::Mismatch
+        );
+    }
+
+    #[tokio::test]
+    async fn reserve_concurrent() {
+        // GIVEN concurrent requests with the same key
+        let store = MemoryStore::new();
+        let now = Utc::now();
+
+        // WHEN they all reserve it
+        let lookups =
+            futures::future::join_all((0..10).map(|_| reserve(&store, "key", "hash", now))).await;
+
+        // THEN only one of them gets to handle its request
+        let lookups = lookups.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(
+            lookups
+                .iter()
+                .filter(|lookup| **lookup == Lookup::New)
+                .count(),
+            1
+        );
+        assert_eq!(
+            lookups
+                .iter()
+                .filter(|lookup| **lookup == Lookup::InProgress)
+                .count(),
+            9
+        );
+    }
+
+    #[tokio::test]
+    async fn release_reservation() {
+        let store = MemoryStore::new();
+        let now = Utc::now();
+        reserve(&store, "key", "hash", now).await.unwrap();
+
+        release(&store, "key", "hash").await.unwrap();
+
+        assert_eq!(
+            reserve(&store, "key", "hash", now).await.unwrap(),
+            Lookup::New
+        );
+    }
+
+    #[tokio::test]
+    async fn complete_taken() {
+        // GIVEN a key taken by another request once its reservation expired
+        let store = MemoryStore::new();
+        let now = Utc::now();
+        reserve(&store, "key", "other", now).await.unwrap();
+
+        // WHEN the first request records its response
+        complete(&store, &get_record("hash")).await.unwrap();
+
+        // THEN the other request keeps the key
+        let stored = store.get_idempotency("key", now).await.unwrap();
+        assert_eq!(stored.unwrap().request_hash, "other");
+    }
+}
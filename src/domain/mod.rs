@@ -1,10 +1,16 @@
-use crate::{error::Error, events::EventBus, model::Event};
+use crate::{
+    error::Error,
+    events::{EventBus, SendResult},
+    model::Event,
+};
 
+pub mod idempotency;
+pub mod ratelimit;
 pub mod testrun;
 
 pub async fn send_events(
     event_bus: &dyn EventBus<E = Event>,
     events: &[Event],
-) -> Result<(), Error> {
+) -> Result<SendResult, Error> {
     event_bus.send_events(events).await
 }
@@ -0,0 +1,256 @@
+//! # Submission limits
+//!
+//! Submissions are throttled with a token bucket per user and per client IP,
+//! and counted against a daily quota per user and challenge.
+
+use crate::{
+    error::Error,
+    ratelimit::{RateLimiter, TokenBucket},
+};
+use chrono::{DateTime, Duration, Utc};
+use std::net::IpAddr;
+use tracing::warn;
+
+/// Limits applied to submissions, all disabled by default
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SubmissionPolicy {
+    /// Bucket of each user
+    pub per_user: Option<TokenBucket>,
+    /// Bucket of each client IP
+    pub per_ip: Option<TokenBucket>,
+    /// Submissions a user can make to a challenge each UTC day
+    pub daily_quota: Option<u32>,
+}
+
+impl SubmissionPolicy {
+    /// Read the policy from the environment
+    ///
+    /// `USER_RATE_LIMIT_PER_MINUTE` and `IP_RATE_LIMIT_PER_MINUTE` enable the
+    /// buckets, and `DAILY_SUBMISSION_QUOTA` the quota, when set to a positive
+    /// number. Zero leaves them disabled.
+    pub fn from_env() -> Self {
+        let var = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .map(|value| {
+                    value
+                        .parse::<u32>()
+                        .unwrap_or_else(|_| panic!("Unsupported {}: {}", name, value))
+                })
+                .filter(|value| *value > 0)
+        };
+
+        SubmissionPolicy {
+            per_user: var("USER_RATE_LIMIT_PER_MINUTE").map(TokenBucket::per_minute),
+            per_ip: var("IP_RATE_LIMIT_PER_MINUTE").map(TokenBucket::per_minute),
+            daily_quota: var("DAILY_SUBMISSION_QUOTA"),
+        }
+    }
+}
+
+/// Whether a submission can go through
+#[derive(Debug, PartialEq, Eq)]
+pub enum Admission {
+    Allowed,
+    /// Too many submissions in a short time, retry after the delay
+    Throttled(Duration),
+    /// The daily quota is used up, retry after the delay
+    QuotaExceeded(Duration),
+}
+
+/// Take a token from the buckets of a submission
+///
+/// The user bucket only applies to submissions with a user, and is only
+/// taken from once the IP bucket let the submission through, so that users
+/// behind a busy IP keep their own tokens. This only needs the caller, so
+/// that submissions can be throttled before being parsed.
+pub async fn throttle(
+    limiter: &dyn RateLimiter,
+    policy: &SubmissionPolicy,
+    user_id: Option<&str>,
+    client_ip: Option<IpAddr>,
+    now: DateTime<Utc>,
+) -> Result<Admission, Error> {
+    let buckets = [
+        (policy.per_ip, client_ip.map(|ip| format!("ip/{}", ip))),
+        (
+            policy.per_user,
+            user_id.map(|user_id| format!("user/{}", user_id)),
+        ),
+    ];
+    for (bucket, key) in buckets {
+        if let (Some(bucket), Some(key)) = (bucket, key) {
+            if let Some(wait) = limiter.take(&key, &bucket, now).await? {
+                warn!("Throttled submissions of '{}'", key);
+                return Ok(Admission::Throttled(wait));
+            }
+        }
+    }
+
+    Ok(Admission::Allowed)
+}
+
+/// Count a submission against the daily quota of its user and challenge
+///
+/// Only submissions with both a user and a challenge are counted, and only
+/// valid submissions should be, so that rejected ones don't use it up.
+pub async fn count_quota(
+    limiter: &dyn RateLimiter,
+    policy: &SubmissionPolicy,
+    user_id: Option<&str>,
+    challenge_id: Option<&str>,
+    now: DateTime<Utc>,
+) -> Result<Admission, Error> {
+    if let (Some(limit), Some(key)) = (policy.daily_quota, quota_key(user_id, challenge_id, now)) {
+        let resets_at = now
+            .date_naive()
+            .succ_opt()
+            .and_then(|tomorrow| tomorrow.and_hms_opt(0, 0, 0))
+            .map(|midnight| midnight.and_utc())
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        if !limiter.count(&key, limit, resets_at).await? {
+            warn!("Quota '{}' is used up", key);
+            return Ok(Admission::QuotaExceeded(resets_at - now));
+        }
+    }
+
+    Ok(Admission::Allowed)
+}
+
+/// Give back the use of the daily quota counted at `now` for a submission
+/// that could not be stored
+pub async fn refund_quota(
+    limiter: &dyn RateLimiter,
+    policy: &SubmissionPolicy,
+    user_id: Option<&str>,
+    challenge_id: Option<&str>,
+    now: DateTime<Utc>,
+) -> Result<(), Error> {
+    match (policy.daily_quota, quota_key(user_id, challenge_id, now)) {
+        (Some(_), Some(key)) => limiter.uncount(&key).await,
+        _ => Ok(()),
+    }
+}
+
+/// Key of the daily quota of a user and challenge, if both are known
+fn quota_key(
+    user_id: Option<&str>,
+    challenge_id: Option<&str>,
+    now: DateTime<Utc>,
+) -> Option<String> {
+    Some(format!(
+        "quota/{}/{}/{}",
+        user_id?,
+        challenge_id?,
+        now.date_naive()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ratelimit::MemoryRateLimiter;
+
+    fn get_policy() -> SubmissionPolicy {
+        SubmissionPolicy {
+            per_user: Some(TokenBucket::per_minute(1)),
+            per_ip: Some(TokenBucket::per_minute(2)),
+            daily_quota: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn throttle_buckets() {
+        let limiter = MemoryRateLimiter::new();
+        let ip = Some("192.0.2.1".parse().unwrap());
+        let other_ip = Some("192.0.2.2".parse().unwrap());
+        let now = Utc::now();
+
+        // GIVEN two users who used the bucket of their IP
+        let first = throttle(&limiter, &get_policy(), Some("user"), ip, now).await;
+        let other = throttle(&limiter, &get_policy(), Some("other"), ip, now).await;
+        assert_eq!(first.unwrap(), Admission::Allowed);
+        assert_eq!(other.unwrap(), Admission::Allowed);
+
+        // WHEN a third user submits from the same IP, then from another one
+        let busy_ip = throttle(&limiter, &get_policy(), Some("third"), ip, now).await;
+        let third = throttle(&limiter, &get_policy(), Some("third"), other_ip, now).await;
+        let user = throttle(&limiter, &get_policy(), Some("user"), other_ip, now).await;
+
+        // THEN the IP bucket is shared, without spending the token of the
+        // throttled user, and each user waits for their own bucket
+        assert_eq!(
+            busy_ip.unwrap(),
+            Admission::Throttled(Duration::seconds(30))
+        );
+        assert_eq!(third.unwrap(), Admission::Allowed);
+        assert_eq!(user.unwrap(), Admission::Throttled(Duration::minutes(1)));
+    }
+
+    #[tokio::test]
+    async fn count_daily_quota() {
+        let limiter = MemoryRateLimiter::new();
+        let policy = SubmissionPolicy {
+            daily_quota: Some(1),
+            ..Default::default()
+        };
+        let now = Utc::now();
+        let admit = |user_id, challenge_id| {
+            count_quota(&limiter, &policy, Some(user_id), Some(challenge_id), now)
+        };
+
+        assert_eq!(
+            admit("user", "challenge").await.unwrap(),
+            Admission::Allowed
+        );
+        assert_eq!(admit("user", "other").await.unwrap(), Admission::Allowed);
+        match admit("user", "challenge").await.unwrap() {
+            Admission::QuotaExceeded(wait) => assert!(wait <= Duration::days(1)),
+            admission => panic!("Unexpected admission: {:?}", admission),
+        }
+    }
+
+    #[tokio::test]
+    async fn refund_daily_quota() {
+        let limiter = MemoryRateLimiter::new();
+        let policy = SubmissionPolicy {
+            daily_quota: Some(1),
+            ..Default::default()
+        };
+        let now = Utc::now();
+
+        // GIVEN a user who used their quota on a submission that failed
+        let first = count_quota(&limiter, &policy, Some("user"), Some("challenge"), now).await;
+        assert_eq!(first.unwrap(), Admission::Allowed);
+
+        // WHEN the use is given back
+        refund_quota(&limiter, &policy, Some("user"), Some("challenge"), now)
+            .await
+            .unwrap();
+
+        // THEN they can submit again
+        let retry = count_quota(&limiter, &policy, Some("user"), Some("challenge"), now).await;
+        assert_eq!(retry.unwrap(), Admission::Allowed);
+    }
+
+    #[tokio::test]
+    async fn limits_disabled() {
+        let limiter = MemoryRateLimiter::new();
+        let policy = SubmissionPolicy::default();
+        let ip = Some("192.0.2.1".parse().unwrap());
+
+        for _ in 0..10 {
+            let res = throttle(&limiter, &policy, Some("user"), ip, Utc::now()).await;
+            assert_eq!(res.unwrap(), Admission::Allowed);
+            let res = count_quota(
+                &limiter,
+                &policy,
+                Some("user"),
+                Some("challenge"),
+                Utc::now(),
+            )
+            .await;
+            assert_eq!(res.unwrap(), Admission::Allowed);
+        }
+    }
+}
@@ -1,17 +1,544 @@
 use crate::{
     error::Error,
-    model::TestRun,
-    store::{StoreDelete, StoreGet, StorePut},
+    model::{Test, TestRun, TestRunStatus},
+    store::{Store, StoreGet, StorePurge, StoreQuery, TestRunPage, TestRunQuery},
 };
+use chrono::Utc;
+use uuid::Uuid;
+
+/// Number of testruns returned when the query doesn't set a limit
+pub const DEFAULT_LIMIT: usize = 20;
+/// Maximum number of testruns returned in a single page
+pub const MAX_LIMIT: usize = 100;
 
 pub async fn get_testrun(store: &dyn StoreGet, id: &str) -> Result<Option<TestRun>, Error> {
     store.get(id).await
 }
 
-pub async fn put_testrun(store: &dyn StorePut, testrun: &TestRun) -> Result<(), Error> {
-    store.put(testrun).await
+/// Precondition on the stored version of a testrun, from an `If-Match` header
+#[derive(Clone, Debug, PartialEq)]
+pub enum IfMatch {
+    /// The testrun must exist, whatever its version
+    Any,
+    /// The testrun must exist at one of these versions
+    Versions(Vec<u64>),
+}
+
+impl IfMatch {
+    /// Whether the precondition holds for the stored version, if any
+    pub fn matches(&self, version: Option<u64>) -> bool {
+        match (self, version) {
+            (_, None) => false,
+            (IfMatch::Any, Some(_)) => true,
+            (IfMatch::Versions(versions), Some(version)) => versions.contains(&version),
+        }
+    }
+}
+
+/// Create or update a testrun
+///
+/// The status change from the stored testrun, if any, must be allowed by
+/// `can_transition`, otherwise a client error is returned and nothing is
+/// written. The version is always set from the stored testrun, so a
+/// concurrent write between the read and the write results in a conflict
+/// error rather than a lost update.
+///
+/// Returns the testrun as stored, with its new version.
+pub async fn put_testrun(
+    store: &dyn Store,
+    testrun: &TestRun,
+    if_match: Option<&IfMatch>,
+) -> Result<TestRun, Error> {
+    let testrun = prepare_put(store, testrun, if_match).await?;
+    store.put(&testrun).await?;
+
+    Ok(testrun)
 }
 
-pub async fn delete_testrun(store: &dyn StoreDelete, id: &str) -> Result<(), Error> {
-    store.delete(id).await
+/// Validate a put against the stored testrun, see `put_testrun`
+///
+/// Returns the testrun to write, with its new version, without writing it.
+pub async fn prepare_put(
+    store: &dyn Store,
+    testrun: &TestRun,
+    if_match: Option<&IfMatch>,
+) -> Result<TestRun, Error> {
+    let current = store.get(&testrun.id).await?;
+
+    if let Some(if_match) = if_match {
+        if !if_match.matches(current.as_ref().map(|t| t.version)) {
+            return Err(Error::PreconditionError("TestRun version does not match"));
+        }
+    }
+
+    if !can_transition(current.as_ref().map(|t| t.status), testrun.status) {
+        return Err(Error::ClientError("Invalid status transition"));
+    }
+
+    let testrun = TestRun {
+        version: current.as_ref().map_or(0, |t| t.version) + 1,
+        created_at: match current {
+            Some(current) => current.created_at,
+            None => Some(Utc::now()),
+        },
+        ..testrun.clone()
+    };
+
+    Ok(testrun)
+}
+
+/// Create a testrun under a new id
+///
+/// The id is a UUIDv7, so ids are unique without coordination and sort by
+/// creation time. Fields owned by the server are reset: the testrun starts
+/// queued, without test results, at version 1.
+///
+/// Returns the testrun as stored.
+pub async fn create_testrun(store: &dyn Store, testrun: &TestRun) -> Result<TestRun, Error> {
+    let testrun = TestRun {
+        id: Uuid::now_v7().to_string(),
+        status: TestRunStatus::Queued,
+        tests: Vec::new(),
+        version: 1,
+        created_at: Some(Utc::now()),
+        ..testrun.clone()
+    };
+    store.put(&testrun).await?;
+
+    Ok(testrun)
+}
+
+/// List testruns matching a query
+///
+/// A limit of zero is replaced by `DEFAULT_LIMIT`, and larger limits are
+/// capped to `MAX_LIMIT`.
+pub async fn list_testruns(
+    store: &dyn StoreQuery,
+    query: &TestRunQuery,
+) -> Result<TestRunPage, Error> {
+    let limit = match query.limit {
+        0 => DEFAULT_LIMIT,
+        limit => limit.min(MAX_LIMIT),
+    };
+
+    store
+        .query(&TestRunQuery {
+            limit,
+            ..query.clone()
+        })
+        .await
+}
+
+/// Record the status and test results of a testrun, as reported by a runner
+///
+/// The submission itself is kept as stored. The status change must be
+/// allowed by `can_transition`, and `if_match` is checked as in
+/// `put_testrun`.
+///
+/// Returns the updated testrun, or `None` if it didn't exist.
+pub async fn put_results(
+    store: &dyn Store,
+    id: &str,
+    status: TestRunStatus,
+    tests: Vec<Test>,
+    if_match: Option<&IfMatch>,
+) -> Result<Option<TestRun>, Error> {
+    let current = match store.get(id).await? {
+        Some(current) => current,
+        None => return Ok(None),
+    };
+
+    if let Some(if_match) = if_match {
+        if !if_match.matches(Some(current.version)) {
+            return Err(Error::PreconditionError("TestRun version does not match"));
+        }
+    }
+
+    if !can_transition(Some(current.status), status) {
+        return Err(Error::ClientError("Invalid status transition"));
+    }
+
+    let updated = TestRun {
+        status,
+        tests,
+        version: current.version + 1,
+        ..current
+    };
+    store.put(&updated).await?;
+
+    Ok(Some(updated))
+}
+
+/// Delete a testrun
+///
+/// Returns the deleted testrun, or `None` if it didn't exist.
+pub async fn delete_testrun(store: &dyn Store, id: &str) -> Result<Option<TestRun>, Error> {
+    let current = store.get(id).await?;
+    if current.is_some() {
+        store.delete(id).await?;
+    }
+
+    Ok(current)
+}
+
+/// Cancel a queued or running testrun
+///
+/// Cancelling a cancelled testrun again succeeds without writing anything,
+/// but a testrun that reached another final status cannot be cancelled.
+///
+/// Returns the cancelled testrun, or `None` if it didn't exist.
+pub async fn cancel_testrun(store: &dyn Store, id: &str) -> Result<Option<TestRun>, Error> {
+    let current = match store.get(id).await? {
+        Some(current) => current,
+        None => return Ok(None),
+    };
+
+    if current.status == TestRunStatus::Cancelled {
+        return Ok(Some(current));
+    }
+    if !can_transition(Some(current.status), TestRunStatus::Cancelled) {
+        return Err(Error::ClientError("TestRun is already finished"));
+    }
+
+    let cancelled = TestRun {
+        status: TestRunStatus::Cancelled,
+        version: current.version + 1,
+        ..current
+    };
+    store.put(&cancelled).await?;
+
+    Ok(Some(cancelled))
+}
+
+/// Delete testruns past their retention, returning how many were deleted
+pub async fn purge_testruns(store: &dyn StorePurge) -> Result<usize, Error> {
+    store.purge(Utc::now()).await
+}
+
+/// Check whether a testrun may move from one status to another
+///
+/// A testrun that does not exist yet (`from` is `None`) must start as queued.
+/// Writing the same status again is always allowed so that updates stay
+/// idempotent, but a final status can never be left.
+pub fn can_transition(from: Option<TestRunStatus>, to: TestRunStatus) -> bool {
+    use TestRunStatus::*;
+
+    match (from, to) {
+        (None, Queued) => true,
+        (None, _) => false,
+        (Some(from), to) if from == to => true,
+        (Some(Queued), Running | Errored | TimedOut | Cancelled) => true,
+        (Some(Running), Passed | Failed | Errored | TimedOut | Cancelled) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::conformance::get_testrun_with_status;
+    use TestRunStatus::*;
+
+    #[tokio::test]
+    async fn list_limits() {
+        use crate::store::{MemoryStore, StorePut};
+
+        // GIVEN a store with more testruns than the maximum page size
+        let store = MemoryStore::new();
+        for i in 0..MAX_LIMIT + 1 {
+            store
+                .put(&TestRun {
+                    id: format!("{:03}", i),
+                    ..get_testrun_with_status(Queued)
+                })
+                .await
+                .unwrap();
+        }
+
+        // WHEN listing without a limit, or with a limit over the maximum
+        let default = list_testruns(&store, &TestRunQuery::default())
+            .await
+            .unwrap();
+        let capped = list_testruns(
+            &store,
+            &TestRunQuery {
+                limit: 1000,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        // THEN the page sizes are bounded
+        assert_eq!(default.testruns.len(), DEFAULT_LIMIT);
+        assert_eq!(capped.testruns.len(), MAX_LIMIT);
+        assert!(capped.cursor.is_some());
+    }
+
+    #[tokio::test]
+    async fn put_increments_version() {
+        let store = crate::store::MemoryStore::new();
+
+        let created = put_testrun(&store, &get_testrun_with_status(Queued), None)
+            .await
+            .unwrap();
+        let updated = put_testrun(&store, &get_testrun_with_status(Running), None)
+            .await
+            .unwrap();
+
+        assert_eq!(created.version, 1);
+        assert_eq!(updated.version, 2);
+        assert_eq!(store.get("1").await.unwrap(), Some(updated));
+    }
+
+    #[tokio::test]
+    async fn put_keeps_created_at() {
+        let store = crate::store::MemoryStore::new();
+
+        let created = put_testrun(&store, &get_testrun_with_status(Queued), None)
+            .await
+            .unwrap();
+        let updated = put_testrun(
+            &store,
+            &TestRun {
+                created_at: Some(Utc::now()),
+                ..get_testrun_with_status(Running)
+            },
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert!(created.created_at.is_some());
+        assert_eq!(updated.created_at, created.created_at);
+    }
+
+    #[tokio::test]
+    async fn create_stamps_server_fields() {
+        let store = crate::store::MemoryStore::new();
+
+        // GIVEN a submission with results already filled in
+        let submitted = TestRun {
+            tests: vec![Default::default()],
+            version: 7,
+            ..get_testrun_with_status(Passed)
+        };
+
+        // WHEN it is created twice
+        let first = create_testrun(&store, &submitted).await.unwrap();
+        let second = create_testrun(&store, &submitted).await.unwrap();
+
+        // THEN each gets its own id and starts queued
+        assert_ne!(first.id, submitted.id);
+        assert_ne!(first.id, second.id);
+        assert_eq!(first.status, Queued);
+        assert!(first.tests.is_empty());
+        assert_eq!(first.version, 1);
+        assert!(first.created_at.is_some());
+        assert_eq!(first.language, submitted.language);
+        assert_eq!(store.get(&first.id).await.unwrap(), Some(first));
+    }
+
+    #[tokio::test]
+    async fn results_keep_submission() {
+        let store = crate::store::MemoryStore::new();
+        let submitted = put_testrun(&store, &get_testrun_with_status(Queued), None)
+            .await
+            .unwrap();
+
+        // WHEN a runner reports results
+        let tests = vec![Test {
+            name: "test_hello".to_owned(),
+            ..Default::default()
+        }];
+        let updated = put_results(&store, "1", Errored, tests.clone(), None)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // THEN only the status and tests change
+        assert_eq!(
+            updated,
+            TestRun {
+                status: Errored,
+                tests,
+                version: 2,
+                ..submitted
+            }
+        );
+        assert_eq!(store.get("1").await.unwrap(), Some(updated));
+    }
+
+    #[tokio::test]
+    async fn results_checks() {
+        let store = crate::store::MemoryStore::new();
+
+        // GIVEN a missing testrun
+        let res = put_results(&store, "1", Running, vec![], None).await;
+        assert_eq!(res.unwrap(), None);
+
+        // WHEN the testrun is queued
+        put_testrun(&store, &get_testrun_with_status(Queued), None)
+            .await
+            .unwrap();
+
+        // THEN the status must follow the lifecycle, and If-Match must hold
+        let res = put_results(&store, "1", Passed, vec![], None).await;
+        assert!(matches!(res, Err(Error::ClientError(_))));
+        let stale = Some(IfMatch::Versions(vec![2]));
+        let res = put_results(&store, "1", Running, vec![], stale.as_ref()).await;
+        assert!(matches!(res, Err(Error::PreconditionError(_))));
+        let current = Some(IfMatch::Versions(vec![1]));
+        let res = put_results(&store, "1", Running, vec![], current.as_ref()).await;
+        assert_eq!(res.unwrap().unwrap().version, 2);
+    }
+
+    #[tokio::test]
+    async fn put_if_match() {
+        let store = crate::store::MemoryStore::new();
+
+        // GIVEN an If-Match precondition on a testrun that doesn't exist yet
+        let res = put_testrun(
+            &store,
+            &get_testrun_with_status(Queued),
+            Some(&IfMatch::Any),
+        )
+        .await;
+        assert!(matches!(res, Err(Error::PreconditionError(_))));
+
+        // WHEN the testrun exists at version 1
+        put_testrun(&store, &get_testrun_with_status(Queued), None)
+            .await
+            .unwrap();
+
+        // THEN only a matching version is accepted
+        let res = put_testrun(
+            &store,
+            &get_testrun_with_status(Running),
+            Some(&IfMatch::Versions(vec![2])),
+        )
+        .await;
+        assert!(matches!(res, Err(Error::PreconditionError(_))));
+        let res = put_testrun(
+            &store,
+            &get_testrun_with_status(Running),
+            Some(&IfMatch::Versions(vec![1])),
+        )
+        .await;
+        assert_eq!(res.unwrap().version, 2);
+    }
+
+    #[test]
+    fn transition_create() {
+        assert!(can_transition(None, Queued));
+        assert!(!can_transition(None, Running));
+        assert!(!can_transition(None, Passed));
+    }
+
+    #[test]
+    fn transition_queued() {
+        assert!(can_transition(Some(Queued), Queued));
+        assert!(can_transition(Some(Queued), Running));
+        assert!(can_transition(Some(Queued), Cancelled));
+        assert!(!can_transition(Some(Queued), Passed));
+        assert!(!can_transition(Some(Queued), Failed));
+    }
+
+    #[test]
+    fn transition_running() {
+        assert!(can_transition(Some(Running), Passed));
+        assert!(can_transition(Some(Running), Failed));
+        assert!(can_transition(Some(Running), TimedOut));
+        assert!(!can_transition(Some(Running), Queued));
+    }
+
+    #[test]
+    fn transition_final() {
+        for from in [Passed, Failed, Errored, TimedOut, Cancelled] {
+            assert!(from.is_final());
+            assert!(can_transition(Some(from), from));
+            assert!(!can_transition(Some(from), Queued));
+            assert!(!can_transition(Some(from), Running));
+        }
+        assert!(!can_transition(Some(Passed), Failed));
+    }
+
+    #[tokio::test]
+    async fn delete_existing() {
+        let store = crate::store::MemoryStore::new();
+        let created = put_testrun(&store, &get_testrun_with_status(Queued), None)
+            .await
+            .unwrap();
+
+        let deleted = delete_testrun(&store, "1").await.unwrap();
+
+        assert_eq!(deleted, Some(created));
+        assert_eq!(store.get("1").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn delete_missing() {
+        let store = crate::store::MemoryStore::new();
+
+        assert_eq!(delete_testrun(&store, "1").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn cancel_pending() {
+        for status in [Queued, Running] {
+            // GIVEN a queued or running testrun
+            let store = crate::store::MemoryStore::new();
+            put_testrun(&store, &get_testrun_with_status(Queued), None)
+                .await
+                .unwrap();
+            if status == Running {
+                put_testrun(&store, &get_testrun_with_status(Running), None)
+                    .await
+                    .unwrap();
+            }
+
+            // WHEN cancelling it
+            let cancelled = cancel_testrun(&store, "1").await.unwrap().unwrap();
+
+            // THEN it is stored as cancelled with a new version
+            assert_eq!(cancelled.status, Cancelled);
+            assert_eq!(store.get("1").await.unwrap(), Some(cancelled));
+        }
+    }
+
+    #[tokio::test]
+    async fn cancel_twice() {
+        let store = crate::store::MemoryStore::new();
+        put_testrun(&store, &get_testrun_with_status(Queued), None)
+            .await
+            .unwrap();
+
+        let first = cancel_testrun(&store, "1").await.unwrap().unwrap();
+        let second = cancel_testrun(&store, "1").await.unwrap().unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(second.version, 2);
+    }
+
+    #[tokio::test]
+    async fn cancel_finished() {
+        let store = crate::store::MemoryStore::new();
+        put_testrun(&store, &get_testrun_with_status(Queued), None)
+            .await
+            .unwrap();
+        put_testrun(&store, &get_testrun_with_status(Errored), None)
+            .await
+            .unwrap();
+
+        let res = cancel_testrun(&store, "1").await;
+
+        assert!(matches!(res, Err(Error::ClientError(_))));
+    }
+
+    #[tokio::test]
+    async fn cancel_missing() {
+        let store = crate::store::MemoryStore::new();
+
+        assert_eq!(cancel_testrun(&store, "1").await.unwrap(), None);
+    }
 }
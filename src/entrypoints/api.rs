@@ -0,0 +1,1113 @@
+//! # HTTP API handlers
+//!
+//! Handlers shared by the API Gateway and standalone server entrypoints.
+//! Entrypoints extract the path parameters and body from their own request
+//! type and pass them in; handlers return a JSON response.
+
+use crate::{
+    domain::{
+        self,
+        idempotency::{self, Lookup},
+        ratelimit::{Admission, SubmissionPolicy},
+        testrun::IfMatch,
+    },
+    entrypoints::auth::Caller,
+    error::Error,
+    model::{FileEntry, Test, TestRun, TestRunStatus},
+    ratelimit::RateLimiter,
+    store::{self, IdempotencyRecord, TestRunQuery},
+    utils::{
+        archive::{self, ArchiveLimits, Files},
+        multipart,
+    },
+};
+use chrono::{DateTime, Duration, Utc};
+use http::{HeaderMap, Response, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+use std::{collections::HashMap, future::Future, net::IpAddr};
+use tracing::{error, info, instrument, warn};
+
+/// Settings of the submission handlers
+///
+/// Read once at startup and shared by all requests.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ApiConfig {
+    /// Limits applied to submissions
+    pub policy: SubmissionPolicy,
+    /// Limits applied to uploaded archives
+    pub archive_limits: ArchiveLimits,
+    /// How long responses are replayed for an `Idempotency-Key`
+    pub idempotency_window: Duration,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        ApiConfig {
+            policy: SubmissionPolicy::default(),
+            archive_limits: ArchiveLimits::default(),
+            idempotency_window: idempotency::default_window(),
+        }
+    }
+}
+
+impl ApiConfig {
+    /// Read the settings from the environment
+    ///
+    /// See `SubmissionPolicy::from_env`, `ArchiveLimits::from_env` and
+    /// `idempotency::window_from_env`.
+    pub fn from_env() -> Self {
+        ApiConfig {
+            policy: SubmissionPolicy::from_env(),
+            archive_limits: ArchiveLimits::from_env(),
+            idempotency_window: idempotency::window_from_env(),
+        }
+    }
+}
+
+/// Request to a submission handler
+#[derive(Clone, Copy)]
+pub struct Submission<'a> {
+    pub caller: &'a Caller,
+    /// Client IP of the request, if known
+    pub client_ip: Option<IpAddr>,
+    pub headers: &'a HeaderMap,
+    pub body: &'a [u8],
+}
+
+/// 401 Unauthorized response for a caller that failed to authenticate
+pub fn unauthorized(err: Error) -> Response<String> {
+    warn!("Rejected caller: {}", err);
+    let message = match err {
+        Error::AuthError(msg) => msg,
+        _ => "Authentication failed",
+    };
+    let mut res = response(
+        StatusCode::UNAUTHORIZED,
+        json!({ "message": message }).to_string(),
+    );
+    res.headers_mut().insert(
+        http::header::WWW_AUTHENTICATE,
+        http::HeaderValue::from_static("Bearer"),
+    );
+    res
+}
+
+/// Get a TestRun
+///
+/// Users can only get their own testruns.
+#[instrument(skip(store))]
+pub async fn get_testrun(
+    store: &dyn store::StoreGet,
+    caller: &Caller,
+    id: &str,
+) -> Response<String> {
+    info!("Fetching Test Run #{}", id);
+    let testrun = domain::testrun::get_testrun(store, id).await;
+
+    match testrun {
+        // TestRun belongs to another user
+        Ok(Some(testrun)) if !caller.can_access(testrun.user_id.as_deref()) => forbidden(id),
+        // TestRun exists
+        Ok(Some(testrun)) => with_etag(
+            response(StatusCode::OK, json!(testrun).to_string()),
+            testrun.version,
+        ),
+        // TestRun doesn't exist
+        Ok(None) => {
+            warn!("TestRun not found: {}", id);
+            response(
+                StatusCode::NOT_FOUND,
+                json!({"message": "TestRun not found"}).to_string(),
+            )
+        }
+        // Error
+        Err(err) => {
+            error!("Error fetching testrun: {}", err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Error fetching testrun"}).to_string(),
+            )
+        }
+    }
+}
+
+/// List TestRuns
+///
+/// Supported query string parameters are `user`, `challenge`, `language`,
+/// `status`, `limit` and `cursor`. Users only list their own testruns.
+///
+/// Testruns are listed as summaries, without their files and test results.
+#[instrument(skip(store))]
+pub async fn list_testruns(
+    store: &dyn store::StoreQuery,
+    caller: &Caller,
+    params: &HashMap<String, String>,
+) -> Response<String> {
+    let mut query = match parse_query(params) {
+        Ok(query) => query,
+        Err(msg) => {
+            warn!("Invalid query parameters: {}", msg);
+            return response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": msg }).to_string(),
+            );
+        }
+    };
+
+    if let Caller::User(user_id) = caller {
+        if query.user_id.as_ref().is_some_and(|user| user != user_id) {
+            warn!("User {} listing testruns of another user", user_id);
+            return response(
+                StatusCode::FORBIDDEN,
+                json!({"message": "Cannot list testruns of another user"}).to_string(),
+            );
+        }
+        query.user_id = Some(user_id.clone());
+    }
+
+    match domain::testrun::list_testruns(store, &query).await {
+        Ok(page) => response(
+            StatusCode::OK,
+            json!({"testruns": page.testruns, "cursor": page.cursor}).to_string(),
+        ),
+        // Invalid cursor
+        Err(Error::ClientError(msg)) => {
+            warn!("Rejected query: {}", msg);
+            response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": msg }).to_string(),
+            )
+        }
+        Err(err) => {
+            error!("Error listing testruns: {}", err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Error listing testruns"}).to_string(),
+            )
+        }
+    }
+}
+
+/// Parse the query string parameters of `list_testruns`
+fn parse_query(params: &HashMap<String, String>) -> Result<TestRunQuery, &'static str> {
+    let status = params
+        .get("status")
+        .map(|status| status.parse())
+        .transpose()
+        .map_err(|_| "Invalid 'status' parameter")?;
+    let limit = params
+        .get("limit")
+        .map(|limit| limit.parse())
+        .transpose()
+        .map_err(|_| "Invalid 'limit' parameter")?;
+
+    Ok(TestRunQuery {
+        user_id: params.get("user").cloned(),
+        challenge_id: params.get("challenge").cloned(),
+        language: params.get("language").cloned(),
+        status,
+        limit: limit.unwrap_or_default(),
+        cursor: params.get("cursor").cloned(),
+    })
+}
+
+/// JSON body of a put or create request
+///
+/// Only holds the fields a client may write. The status and test results
+/// are written by runners through `put_results`, and are ignored here.
+///
+/// The files can either be sent as the `files` map, or as a URL-safe base64
+/// `archive` that is expanded into it.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TestRunSubmission {
+    /// Must match the path of a put request, if set
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    user_id: Option<String>,
+    #[serde(default)]
+    challenge_id: Option<String>,
+    #[serde(default)]
+    files: HashMap<String, FileEntry>,
+    language: String,
+    archive: Option<String>,
+}
+
+impl TestRunSubmission {
+    /// Queued testrun for the submission, with an empty id if none was set
+    fn into_testrun(self) -> TestRun {
+        TestRun {
+            id: self.id.unwrap_or_default(),
+            user_id: self.user_id,
+            challenge_id: self.challenge_id,
+            files: self.files,
+            language: self.language,
+            status: TestRunStatus::Queued,
+            tests: Vec::new(),
+            version: 0,
+            created_at: None,
+        }
+    }
+}
+
+/// JSON body of a results request
+#[derive(Deserialize)]
+struct TestRunResults {
+    status: TestRunStatus,
+    #[serde(default)]
+    tests: Vec<Test>,
+}
+
+/// Put a TestRun
+///
+/// The body is either a JSON testrun, or a `multipart/form-data` form with
+/// the JSON testrun in a `testrun` field and its files either in an
+/// `archive` field or in one `files` field per file.
+///
+/// Retries sent with the same `Idempotency-Key` get the original response
+/// back, see `idempotent`.
+///
+/// Users are stamped as the owner of the testrun, and cannot overwrite the
+/// testruns of other users.
+///
+/// Submissions are limited according to `config.policy`, see
+/// `check_throttle` and `check_quota`.
+#[instrument(
+    skip(store, limiter, config, submission),
+    fields(caller = ?submission.caller, client_ip = ?submission.client_ip)
+)]
+pub async fn put_testrun(
+    store: &dyn store::Store,
+    limiter: &dyn RateLimiter,
+    config: &ApiConfig,
+    submission: Submission<'_>,
+    id: &str,
+) -> Response<String> {
+    let path = format!("/{}", id);
+    let handler = handle_put(store, limiter, config, submission, id);
+
+    idempotent(store, config, "PUT", &path, submission, handler).await
+}
+
+/// Handle a put request, see `put_testrun`
+async fn handle_put(
+    store: &dyn store::Store,
+    limiter: &dyn RateLimiter,
+    config: &ApiConfig,
+    submission: Submission<'_>,
+    id: &str,
+) -> Response<String> {
+    let Submission {
+        caller,
+        client_ip,
+        headers,
+        body,
+    } = submission;
+    if let Err(res) = check_throttle(limiter, &config.policy, caller, client_ip).await {
+        return res;
+    }
+
+    let testrun = match parse_submission(headers, body, &config.archive_limits) {
+        Ok(testrun) => testrun,
+        Err(msg) => {
+            warn!("Rejected request body: {}", msg);
+            return response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": msg }).to_string(),
+            );
+        }
+    };
+
+    info!(
+        "Parsed testrun '{}' in {} with {} files",
+        id,
+        testrun.language,
+        testrun.files.len()
+    );
+
+    if !testrun.id.is_empty() && testrun.id != id {
+        warn!(
+            "TestRun ID in path ({}) does not match ID in body ({})",
+            id, testrun.id
+        );
+        return response(
+            StatusCode::BAD_REQUEST,
+            json!({"message": "TestRun ID in path does not match ID in body"}).to_string(),
+        );
+    }
+
+    if let Err(res) = check_owner(store, caller, id).await {
+        return res;
+    }
+
+    let testrun = TestRun {
+        id: id.to_owned(),
+        ..with_owner(testrun, caller)
+    };
+
+    // Put testrun, counting it against the quota once validated, and giving
+    // the quota back if it can't be stored
+    let if_match = headers
+        .get(http::header::IF_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .map(parse_if_match);
+    let now = Utc::now();
+    let res = match domain::testrun::prepare_put(store, &testrun, if_match.as_ref()).await {
+        Ok(prepared) => {
+            if let Err(res) = check_quota(limiter, &config.policy, caller, &prepared, now).await {
+                return res;
+            }
+            let res = store.put(&prepared).await;
+            if res.is_err() {
+                refund_quota(limiter, &config.policy, &prepared, now).await;
+            }
+            res.map(|()| prepared)
+        }
+        Err(err) => Err(err),
+    };
+
+    // Return response
+    //
+    // If the put was successful, we return a 201 Created. If the testrun was
+    // rejected, we return a 400 Bad Request, or a 409 Conflict / 412
+    // Precondition Failed if the stored version changed. Otherwise, we return
+    // a 500 Internal Server Error.
+    match res {
+        // Testrun created
+        Ok(stored) => {
+            info!("Queued testrun {:?}", stored.id);
+            with_etag(
+                response(
+                    StatusCode::CREATED,
+                    json!({"message": "Testrun queued"}).to_string(),
+                ),
+                stored.version,
+            )
+        }
+        // Testrun changed since it was read
+        Err(Error::ConflictError(msg)) => {
+            warn!("Conflict writing testrun {}: {}", testrun.id, msg);
+            response(StatusCode::CONFLICT, json!({ "message": msg }).to_string())
+        }
+        // If-Match doesn't hold
+        Err(Error::PreconditionError(msg)) => {
+            warn!("Precondition failed for testrun {}: {}", testrun.id, msg);
+            response(
+                StatusCode::PRECONDITION_FAILED,
+                json!({ "message": msg }).to_string(),
+            )
+        }
+        // Testrun rejected
+        Err(Error::ClientError(msg)) => {
+            warn!("Rejected testrun {}: {}", testrun.id, msg);
+            response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": msg }).to_string(),
+            )
+        }
+        // Error creating testrun
+        Err(err) => {
+            error!("Failed to create testrun {}: {}", testrun.id, err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Failed to create testrun"}).to_string(),
+            )
+        }
+    }
+}
+
+/// Create a TestRun under a server-generated id
+///
+/// Takes the same body as `put_testrun`, but any id in it is ignored.
+/// `collection` is the path the request was sent to, and the `Location` of
+/// the new testrun is built from it.
+///
+/// Retries sent with the same `Idempotency-Key` get the original response
+/// back instead of creating another testrun.
+///
+/// Users are stamped as the owner of the testrun. Submissions are limited as
+/// for `put_testrun`.
+#[instrument(
+    skip(store, limiter, config, submission),
+    fields(caller = ?submission.caller, client_ip = ?submission.client_ip)
+)]
+pub async fn create_testrun(
+    store: &dyn store::Store,
+    limiter: &dyn RateLimiter,
+    config: &ApiConfig,
+    submission: Submission<'_>,
+    collection: &str,
+) -> Response<String> {
+    let handler = handle_create(store, limiter, config, submission, collection);
+
+    idempotent(store, config, "POST", collection, submission, handler).await
+}
+
+/// Handle a create request, see `create_testrun`
+async fn handle_create(
+    store: &dyn store::Store,
+    limiter: &dyn RateLimiter,
+    config: &ApiConfig,
+    submission: Submission<'_>,
+    collection: &str,
+) -> Response<String> {
+    let Submission {
+        caller,
+        client_ip,
+        headers,
+        body,
+    } = submission;
+    if let Err(res) = check_throttle(limiter, &config.policy, caller, client_ip).await {
+        return res;
+    }
+
+    let testrun = match parse_submission(headers, body, &config.archive_limits) {
+        Ok(testrun) => with_owner(testrun, caller),
+        Err(msg) => {
+            warn!("Rejected request body: {}", msg);
+            return response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": msg }).to_string(),
+            );
+        }
+    };
+    let now = Utc::now();
+    if let Err(res) = check_quota(limiter, &config.policy, caller, &testrun, now).await {
+        return res;
+    }
+
+    let res = domain::testrun::create_testrun(store, &testrun).await;
+    if res.is_err() {
+        refund_quota(limiter, &config.policy, &testrun, now).await;
+    }
+
+    match res {
+        Ok(stored) => {
+            info!("Queued testrun {:?}", stored.id);
+            let location = format!("{}/{}", collection.trim_end_matches('/'), stored.id);
+            let mut res = with_etag(
+                response(StatusCode::CREATED, json!(stored).to_string()),
+                stored.version,
+            );
+            res.headers_mut().insert(
+                http::header::LOCATION,
+                http::HeaderValue::from_str(&location).unwrap(),
+            );
+            res
+        }
+        Err(err) => {
+            error!("Failed to create testrun: {}", err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Failed to create testrun"}).to_string(),
+            )
+        }
+    }
+}
+
+/// Put the status and test results of a TestRun
+///
+/// Only meant for runners: entrypoints must make sure the caller is one.
+/// The body is a JSON object with the new `status` and the `tests`.
+#[instrument(skip(store, headers, body))]
+pub async fn put_results(
+    store: &dyn store::Store,
+    id: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Response<String> {
+    let results: TestRunResults = match serde_json::from_slice(body) {
+        Ok(results) => results,
+        Err(err) => {
+            warn!("Failed to parse results: {}", err);
+            return response(
+                StatusCode::BAD_REQUEST,
+                json!({"message": "Failed to parse results from request body"}).to_string(),
+            );
+        }
+    };
+
+    let if_match = headers
+        .get(http::header::IF_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .map(parse_if_match);
+    let res =
+        domain::testrun::put_results(store, id, results.status, results.tests, if_match.as_ref())
+            .await;
+
+    match res {
+        Ok(Some(testrun)) => {
+            info!("Recorded results of testrun {}: {}", id, testrun.status);
+            with_etag(
+                response(StatusCode::OK, json!(testrun).to_string()),
+                testrun.version,
+            )
+        }
+        Ok(None) => {
+            warn!("TestRun not found: {}", id);
+            response(
+                StatusCode::NOT_FOUND,
+                json!({"message": "TestRun not found"}).to_string(),
+            )
+        }
+        Err(Error::ConflictError(msg)) => {
+            warn!("Conflict writing results of testrun {}: {}", id, msg);
+            response(StatusCode::CONFLICT, json!({ "message": msg }).to_string())
+        }
+        Err(Error::PreconditionError(msg)) => {
+            warn!("Precondition failed for testrun {}: {}", id, msg);
+            response(
+                StatusCode::PRECONDITION_FAILED,
+                json!({ "message": msg }).to_string(),
+            )
+        }
+        Err(Error::ClientError(msg)) => {
+            warn!("Rejected results of testrun {}: {}", id, msg);
+            response(
+                StatusCode::BAD_REQUEST,
+                json!({ "message": msg }).to_string(),
+            )
+        }
+        Err(err) => {
+            error!("Failed to write results of testrun {}: {}", id, err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Failed to write results"}).to_string(),
+            )
+        }
+    }
+}
+
+/// Delete a TestRun
+///
+/// Users can only delete their own testruns.
+#[instrument(skip(store))]
+pub async fn delete_testrun(
+    store: &dyn store::Store,
+    caller: &Caller,
+    id: &str,
+) -> Response<String> {
+    info!("Deleting Test Run #{}", id);
+    if let Err(res) = check_owner(store, caller, id).await {
+        return res;
+    }
+
+    match domain::testrun::delete_testrun(store, id).await {
+        Ok(Some(_)) => response(
+            StatusCode::OK,
+            json!({"message": "Testrun deleted"}).to_string(),
+        ),
+        Ok(None) => {
+            warn!("TestRun not found: {}", id);
+            response(
+                StatusCode::NOT_FOUND,
+                json!({"message": "TestRun not found"}).to_string(),
+            )
+        }
+        Err(err) => {
+            error!("Failed to delete testrun {}: {}", id, err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Failed to delete testrun"}).to_string(),
+            )
+        }
+    }
+}
+
+/// Cancel a queued or running TestRun
+///
+/// Returns the cancelled testrun. Runners learn about the cancellation from
+/// the `TestRunCancelled` event sent by the stream. Users can only cancel
+/// their own testruns.
+#[instrument(skip(store))]
+pub async fn cancel_testrun(
+    store: &dyn store::Store,
+    caller: &Caller,
+    id: &str,
+) -> Response<String> {
+    info!("Cancelling Test Run #{}", id);
+    if let Err(res) = check_owner(store, caller, id).await {
+        return res;
+    }
+
+    match domain::testrun::cancel_testrun(store, id).await {
+        Ok(Some(testrun)) => with_etag(
+            response(StatusCode::OK, json!(testrun).to_string()),
+            testrun.version,
+        ),
+        Ok(None) => {
+            warn!("TestRun not found: {}", id);
+            response(
+                StatusCode::NOT_FOUND,
+                json!({"message": "TestRun not found"}).to_string(),
+            )
+        }
+        // Already finished, or changed while cancelling
+        Err(Error::ClientError(msg)) | Err(Error::ConflictError(msg)) => {
+            warn!("Cannot cancel testrun {}: {}", id, msg);
+            response(StatusCode::CONFLICT, json!({ "message": msg }).to_string())
+        }
+        Err(err) => {
+            error!("Failed to cancel testrun {}: {}", id, err);
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Failed to cancel testrun"}).to_string(),
+            )
+        }
+    }
+}
+
+/// Make sure a testrun, if it exists, can be accessed by the caller
+///
+/// On failure, returns the response to send back. Anonymous callers don't
+/// need to read the testrun.
+async fn check_owner(
+    store: &dyn store::StoreGet,
+    caller: &Caller,
+    id: &str,
+) -> Result<(), Response<String>> {
+    if *caller == Caller::Anonymous {
+        return Ok(());
+    }
+
+    match store.get(id).await {
+        Ok(Some(testrun)) if !caller.can_access(testrun.user_id.as_deref()) => Err(forbidden(id)),
+        Ok(_) => Ok(()),
+        Err(err) => {
+            error!("Error fetching testrun {}: {}", id, err);
+            Err(response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Error fetching testrun"}).to_string(),
+            ))
+        }
+    }
+}
+
+/// Take a token from the buckets of the caller and its client IP
+///
+/// Runs before the body is parsed, so that throttled clients cost little.
+/// Anonymous callers have no user bucket until their submitted `userId` is
+/// known, see `check_quota`.
+async fn check_throttle(
+    limiter: &dyn RateLimiter,
+    policy: &SubmissionPolicy,
+    caller: &Caller,
+    client_ip: Option<IpAddr>,
+) -> Result<(), Response<String>> {
+    let user_id = match caller {
+        Caller::Anonymous => None,
+        Caller::User(user_id) => Some(user_id.as_str()),
+    };
+    let admission =
+        domain::ratelimit::throttle(limiter, policy, user_id, client_ip, Utc::now()).await;
+
+    too_many_requests(admission).map_or(Ok(()), Err)
+}
+
+/// Count a valid submission against the daily quota of its user at `now`
+///
+/// The bucket of the submitted `userId` of anonymous callers is taken first.
+/// Submissions that can't be stored afterwards give the quota back with
+/// `refund_quota`.
+async fn check_quota(
+    limiter: &dyn RateLimiter,
+    policy: &SubmissionPolicy,
+    caller: &Caller,
+    testrun: &TestRun,
+    now: DateTime<Utc>,
+) -> Result<(), Response<String>> {
+    let user_id = testrun.user_id.as_deref();
+    if let (Caller::Anonymous, Some(_)) = (caller, user_id) {
+        let admission = domain::ratelimit::throttle(limiter, policy, user_id, None, now).await;
+        if let Some(res) = too_many_requests(admission) {
+            return Err(res);
+        }
+    }
+
+    let admission = domain::ratelimit::count_quota(
+        limiter,
+        policy,
+        user_id,
+        testrun.challenge_id.as_deref(),
+        now,
+    )
+    .await;
+
+    too_many_requests(admission).map_or(Ok(()), Err)
+}
+
+/// Give back the quota counted by `check_quota` at `now`
+///
+/// Failures are only logged, as the submission failed already.
+async fn refund_quota(
+    limiter: &dyn RateLimiter,
+    policy: &SubmissionPolicy,
+    testrun: &TestRun,
+    now: DateTime<Utc>,
+) {
+    let res = domain::ratelimit::refund_quota(
+        limiter,
+        policy,
+        testrun.user_id.as_deref(),
+        testrun.challenge_id.as_deref(),
+        now,
+    )
+    .await;
+    if let Err(err) = res {
+        error!(
+            "Failed to give back the quota of testrun {}: {}",
+            testrun.id, err
+        );
+    }
+}
+
+/// Response to send back for a refused admission, if any
+///
+/// That is a `429 Too Many Requests`, with a `Retry-After` header.
+fn too_many_requests(admission: Result<Admission, Error>) -> Option<Response<String>> {
+    let (message, retry_after) = match admission {
+        Ok(Admission::Allowed) => return None,
+        Ok(Admission::Throttled(wait)) => ("Too many submissions", wait),
+        Ok(Admission::QuotaExceeded(wait)) => {
+            ("Daily submission quota exceeded for this challenge", wait)
+        }
+        Err(err) => {
+            error!("Failed to check submission limits: {}", err);
+            return Some(response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Failed to check submission limits"}).to_string(),
+            ));
+        }
+    };
+
+    // Whole seconds, rounded up so that a retry isn't too early
+    let seconds = (retry_after.num_milliseconds() + 999) / 1000;
+    let mut res = response(
+        StatusCode::TOO_MANY_REQUESTS,
+        json!({ "message": message }).to_string(),
+    );
+    res.headers_mut().insert(
+        http::header::RETRY_AFTER,
+        http::HeaderValue::from(seconds.max(1)),
+    );
+    Some(res)
+}
+
+/// Stamp a user as the owner of a submitted testrun
+///
+/// Anonymous callers keep the `userId` they submitted.
+fn with_owner(testrun: TestRun, caller: &Caller) -> TestRun {
+    match caller {
+        Caller::Anonymous => testrun,
+        Caller::User(user_id) => TestRun {
+            user_id: Some(user_id.clone()),
+            ..testrun
+        },
+    }
+}
+
+/// 403 Forbidden response for a testrun of another user
+fn forbidden(id: &str) -> Response<String> {
+    warn!("TestRun {} belongs to another user", id);
+    response(
+        StatusCode::FORBIDDEN,
+        json!({"message": "TestRun belongs to another user"}).to_string(),
+    )
+}
+
+/// Parse the testrun from the body of a put request
+///
+/// On failure, returns the message to send back to the client.
+fn parse_submission(
+    headers: &HeaderMap,
+    body: &[u8],
+    limits: &ArchiveLimits,
+) -> Result<TestRun, String> {
+    if body.is_empty() {
+        return Err("Missing testrun in request body".to_owned());
+    }
+
+    let boundary = headers
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(multipart::boundary);
+
+    match boundary {
+        Some(boundary) => parse_multipart(body, &boundary, limits),
+        None => parse_json(body, limits),
+    }
+}
+
+/// Parse a JSON testrun, expanding its archive if any
+fn parse_json(body: &[u8], limits: &ArchiveLimits) -> Result<TestRun, String> {
+    let mut submission: TestRunSubmission = serde_json::from_slice(body).map_err(|err| {
+        warn!("Failed to parse testrun: {}", err);
+        "Failed to parse testrun from request body".to_owned()
+    })?;
+
+    let files = submission
+        .archive
+        .take()
+        .map(|archive| archive::process_archive_string(&archive, limits))
+        .transpose()
+        .map_err(|err| format!("Invalid archive: {}", err))?;
+
+    with_files(submission.into_testrun(), files.into_iter().collect())
+}
+
+/// Parse a `multipart/form-data` submission
+fn parse_multipart(body: &[u8], boundary: &str, limits: &ArchiveLimits) -> Result<TestRun, String> {
+    let parts = multipart::parse(body, boundary).map_err(|err| match err {
+        Error::ClientError(msg) => msg.to_owned(),
+        err => err.to_string(),
+    })?;
+
+    let mut testrun = None;
+    let mut sources = Vec::new();
+    let mut files = Files::new(limits);
+    let mut has_files = false;
+    for part in parts {
+        match (part.name.as_str(), part.filename) {
+            ("testrun", _) => testrun = Some(parse_json(part.data, limits)?),
+            ("archive", _) => sources.push(
+                archive::expand(part.data, limits)
+                    .map_err(|err| format!("Invalid archive: {}", err))?,
+            ),
+            // Browsers send an empty file name when no file was picked
+            ("files", Some(filename)) if !filename.is_empty() => {
+                files
+                    .entry()
+                    .and_then(|_| files.add(filename, None, false, part.data))
+                    .map_err(|err| format!("Invalid files: {}", err))?;
+                has_files = true;
+            }
+            (name, _) => warn!("Ignoring multipart field '{}'", name),
+        }
+    }
+    if has_files {
+        sources.push(files.into_inner());
+    }
+
+    let testrun = testrun.ok_or_else(|| "Missing 'testrun' field in form data".to_owned())?;
+    with_files(testrun, sources)
+}
+
+/// Set the files of a testrun from one of several possible sources
+///
+/// Only one source can be used, including the `files` already set on the
+/// testrun.
+fn with_files(
+    mut testrun: TestRun,
+    sources: Vec<HashMap<String, FileEntry>>,
+) -> Result<TestRun, String> {
+    let mut sources = sources.into_iter();
+    if let Some(files) = sources.next() {
+        if !testrun.files.is_empty() || sources.next().is_some() {
+            return Err("Only one of 'files' or 'archive' can be set".to_owned());
+        }
+        testrun.files = files;
+    }
+
+    Ok(testrun)
+}
+
+/// Header carrying the client's idempotency key
+const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+/// Header telling the client that a response was replayed
+const IDEMPOTENT_REPLAYED: &str = "idempotent-replayed";
+
+/// Run `handler` at most once per `Idempotency-Key`
+///
+/// Without the header, `handler` always runs. Otherwise, the key is reserved
+/// before running `handler`, and its response recorded after. A retry of the
+/// same request gets the recorded response back, or a `409 Conflict` while
+/// the first one is still being handled, and a different request reusing the
+/// key gets a `422 Unprocessable Entity`. Server errors and rate limited
+/// requests release the key, so that they can be retried.
+///
+/// Keys of users are scoped to them, so that a user can't get the response
+/// recorded for another user. Responses are recorded for
+/// `config.idempotency_window`.
+async fn idempotent(
+    store: &dyn store::Store,
+    config: &ApiConfig,
+    method: &str,
+    path: &str,
+    submission: Submission<'_>,
+    handler: impl Future<Output = Response<String>>,
+) -> Response<String> {
+    let Submission {
+        caller,
+        headers,
+        body,
+        ..
+    } = submission;
+    let key = match headers.get(IDEMPOTENCY_KEY) {
+        Some(key) => match key.to_str() {
+            Ok(key) if !key.is_empty() && key.len() <= idempotency::MAX_KEY_LENGTH => key,
+            _ => {
+                warn!("Invalid Idempotency-Key header");
+                return response(
+                    StatusCode::BAD_REQUEST,
+                    json!({"message": "Invalid Idempotency-Key header"}).to_string(),
+                );
+            }
+        },
+        None => return handler.await,
+    };
+    let key = match caller {
+        Caller::Anonymous => key.to_owned(),
+        Caller::User(user_id) => format!("{}/{}", user_id, key),
+    };
+
+    let content_type = headers
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+    let request_hash = idempotency::request_hash(method, path, content_type, body);
+    let now = Utc::now();
+    match idempotency::reserve(store, &key, &request_hash, now).await {
+        Ok(Lookup::New) => (),
+        Ok(Lookup::Replay(record)) => {
+            info!("Replaying response for Idempotency-Key '{}'", key);
+            return replay(record);
+        }
+        Ok(Lookup::Mismatch) => {
+            warn!("Idempotency-Key '{}' reused for another request", key);
+            return response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                json!({"message": "Idempotency-Key was used for a different request"}).to_string(),
+            );
+        }
+        Ok(Lookup::InProgress) => {
+            warn!("Idempotency-Key '{}' is still being handled", key);
+            return response(
+                StatusCode::CONFLICT,
+                json!({"message": "A request with this Idempotency-Key is still being handled"})
+                    .to_string(),
+            );
+        }
+        Err(err) => {
+            error!("Failed to reserve Idempotency-Key '{}': {}", key, err);
+            return response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Failed to reserve Idempotency-Key"}).to_string(),
+            );
+        }
+    }
+
+    let res = handler.await;
+    if res.status().is_server_error() || res.status() == StatusCode::TOO_MANY_REQUESTS {
+        if let Err(err) = idempotency::release(store, &key, &request_hash).await {
+            error!("Failed to release Idempotency-Key '{}': {}", key, err);
+        }
+        return res;
+    }
+
+    let record = IdempotencyRecord {
+        key: key.clone(),
+        request_hash,
+        status: res.status().as_u16(),
+        headers: res
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_owned())))
+            .collect(),
+        body: res.body().clone(),
+        expires_at: now
+            .checked_add_signed(config.idempotency_window)
+            .unwrap_or(DateTime::<Utc>::MAX_UTC),
+    };
+    // The request was handled, so a failure to record it only affects retries
+    if let Err(err) = idempotency::complete(store, &record).await {
+        error!("Failed to record Idempotency-Key '{}': {}", key, err);
+    }
+
+    res
+}
+
+/// Rebuild a recorded response
+fn replay(record: IdempotencyRecord) -> Response<String> {
+    let mut builder = Response::builder().status(record.status);
+    for (name, value) in &record.headers {
+        builder = builder.header(name, value);
+    }
+
+    builder
+        .header(IDEMPOTENT_REPLAYED, "true")
+        .body(record.body)
+        .unwrap_or_else(|_| {
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"message": "Failed to replay response"}).to_string(),
+            )
+        })
+}
+
+/// Parse an `If-Match` header value
+///
+/// Only strong entity tags created by `with_etag` can match, anything else is
+/// ignored, as required by the strong comparison of `If-Match`.
+fn parse_if_match(value: &str) -> IfMatch {
+    if value.trim() == "*" {
+        return IfMatch::Any;
+    }
+
+    IfMatch::Versions(
+        value
+            .split(',')
+            .filter_map(|tag| {
+                tag.trim()
+                    .strip_prefix('"')?
+                    .strip_suffix('"')?
+                    .parse()
+                    .ok()
+            })
+            .collect(),
+    )
+}
+
+/// Add an `ETag` header holding the testrun version
+fn with_etag(mut response: Response<String>, version: u64) -> Response<String> {
+    response.headers_mut().insert(
+        http::header::ETAG,
+        http::HeaderValue::from_str(&format!("\"{}\"", version)).unwrap(),
+    );
+    response
+}
+
+/// HTTP Response with a JSON payload
+pub fn response(status_code: StatusCode, body: String) -> Response<String> {
+    Response::builder()
+        .status(status_code)
+        .header("Content-Type", "application/json")
+        .body(body)
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn if_match_any() {
+        assert_eq!(parse_if_match("*"), IfMatch::Any);
+    }
+
+    #[test]
+    fn if_match_versions() {
+        assert_eq!(
+            parse_if_match(r#""1", W/"2", "three", "4""#),
+            IfMatch::Versions(vec![1, 4])
+        );
+    }
+
+    #[test]
+    fn etag_header() {
+        let res = with_etag(response(StatusCode::OK, "{}".to_owned()), 3);
+
+        assert_eq!(res.headers()[http::header::ETAG], r#""3""#);
+    }
+}
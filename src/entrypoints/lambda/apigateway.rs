@@ -1,134 +1,992 @@
-use crate::{domain, model::TestRun, store};
-use lambda_http::{http::StatusCode, IntoResponse, Request, RequestExt, Response};
+use crate::{
+    entrypoints::{
+        api::{self, ApiConfig, Submission},
+        auth::Authenticator,
+    },
+    ratelimit::RateLimiter,
+    store,
+};
+use lambda_http::{
+    http::StatusCode, request::RequestContext, IntoResponse, Request, RequestExt, Response,
+};
 use serde_json::json;
-use tracing::{error, info, instrument, warn};
+use std::net::IpAddr;
+use tracing::{instrument, warn};
 
 type E = Box<dyn std::error::Error + Sync + Send + 'static>;
 
 /// Get a TestRun
-#[instrument(skip(store))]
+#[instrument(skip(store, auth))]
 pub async fn get_testrun(
     store: &dyn store::StoreGet,
+    auth: &dyn Authenticator,
     event: Request,
 ) -> Result<impl IntoResponse, E> {
-    let path_parameters = event.path_parameters();
-    let id = match path_parameters.first("id") {
+    let caller = match auth.authenticate(event.headers()) {
+        Ok(caller) => caller,
+        Err(err) => return Ok(api::unauthorized(err)),
+    };
+    let id = match path_id(&event) {
         Some(id) => id,
-        None => {
-            warn!("Missing 'id' parameter in path");
-            return Ok(response(
-                StatusCode::BAD_REQUEST,
-                json!({ "message": "Missing 'id' parameter in path" }).to_string(),
-            ));
-        }
+        None => return Ok(missing_id()),
     };
 
-    info!("Fetching Test Run #{}", id);
-    let testrun = domain::testrun::get_testrun(store, id).await;
-
-    Ok(match testrun {
-        // TestRun exists
-        Ok(Some(testrun)) => response(StatusCode::OK, json!(testrun).to_string()),
-        // TestRun doesn't exist
-        Ok(None) => {
-            warn!("TestRun not found: {}", id);
-            response(
-                StatusCode::NOT_FOUND,
-                json!({"message": "TestRun not found"}).to_string(),
-            )
-        }
-        // Error
-        Err(err) => {
-            error!("Error fetching testrun: {}", err);
-            response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                json!({"message": "Error fetching testrun"}).to_string(),
-            )
-        }
-    })
+    Ok(api::get_testrun(store, &caller, &id).await)
+}
+
+/// List TestRuns
+#[instrument(skip(store, auth))]
+pub async fn list_testruns(
+    store: &dyn store::StoreQuery,
+    auth: &dyn Authenticator,
+    event: Request,
+) -> Result<impl IntoResponse, E> {
+    let caller = match auth.authenticate(event.headers()) {
+        Ok(caller) => caller,
+        Err(err) => return Ok(api::unauthorized(err)),
+    };
+    let params = event
+        .query_string_parameters()
+        .iter()
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect();
+
+    Ok(api::list_testruns(store, &caller, &params).await)
 }
 
 /// Put a TestRun
-#[instrument(skip(store))]
+#[instrument(skip(store, limiter, config, auth, event))]
 pub async fn put_testrun(
-    store: &dyn store::StorePut,
+    store: &dyn store::Store,
+    limiter: &dyn RateLimiter,
+    config: &ApiConfig,
+    auth: &dyn Authenticator,
     event: Request,
 ) -> Result<impl IntoResponse, E> {
-    let path_parameters = event.path_parameters();
-    let id = match path_parameters.first("id") {
+    let caller = match auth.authenticate(event.headers()) {
+        Ok(caller) => caller,
+        Err(err) => return Ok(api::unauthorized(err)),
+    };
+    let id = match path_id(&event) {
         Some(id) => id,
-        None => {
-            warn!("Missing 'id' parameter in path");
-            return Ok(response(
-                StatusCode::BAD_REQUEST,
-                json!({ "message": "Missing 'id' parameter in path" }).to_string(),
-            ));
-        }
+        None => return Ok(missing_id()),
     };
 
-    let testrun: TestRun = match event.payload() {
-        Ok(Some(testrun)) => testrun,
-        Ok(None) => {
-            warn!("Missing testrun in request body");
-            return Ok(response(
-                StatusCode::BAD_REQUEST,
-                json!({"message": "Missing testrun in request body"}).to_string(),
-            ));
+    let submission = Submission {
+        caller: &caller,
+        client_ip: client_ip(&event),
+        headers: event.headers(),
+        body: event.body(),
+    };
+
+    Ok(api::put_testrun(store, limiter, config, submission, &id).await)
+}
+
+/// Create a TestRun under a server-generated id
+#[instrument(skip(store, limiter, config, auth, event))]
+pub async fn create_testrun(
+    store: &dyn store::Store,
+    limiter: &dyn RateLimiter,
+    config: &ApiConfig,
+    auth: &dyn Authenticator,
+    event: Request,
+) -> Result<impl IntoResponse, E> {
+    let caller = match auth.authenticate(event.headers()) {
+        Ok(caller) => caller,
+        Err(err) => return Ok(api::unauthorized(err)),
+    };
+
+    let submission = Submission {
+        caller: &caller,
+        client_ip: client_ip(&event),
+        headers: event.headers(),
+        body: event.body(),
+    };
+
+    Ok(api::create_testrun(store, limiter, config, submission, event.uri().path()).await)
+}
+
+/// Put the results of a TestRun
+///
+/// The route must only be reachable by runners, which the API enforces with
+/// IAM authorization.
+#[instrument(skip(store, event))]
+pub async fn put_results(store: &dyn store::Store, event: Request) -> Result<impl IntoResponse, E> {
+    let id = match path_id(&event) {
+        Some(id) => id,
+        None => return Ok(missing_id()),
+    };
+
+    Ok(api::put_results(store, &id, event.headers(), event.body()).await)
+}
+
+/// Delete a TestRun
+#[instrument(skip(store, auth))]
+pub async fn delete_testrun(
+    store: &dyn store::Store,
+    auth: &dyn Authenticator,
+    event: Request,
+) -> Result<impl IntoResponse, E> {
+    let caller = match auth.authenticate(event.headers()) {
+        Ok(caller) => caller,
+        Err(err) => return Ok(api::unauthorized(err)),
+    };
+    let id = match path_id(&event) {
+        Some(id) => id,
+        None => return Ok(missing_id()),
+    };
+
+    Ok(api::delete_testrun(store, &caller, &id).await)
+}
+
+/// Cancel a TestRun
+#[instrument(skip(store, auth))]
+pub async fn cancel_testrun(
+    store: &dyn store::Store,
+    auth: &dyn Authenticator,
+    event: Request,
+) -> Result<impl IntoResponse, E> {
+    let caller = match auth.authenticate(event.headers()) {
+        Ok(caller) => caller,
+        Err(err) => return Ok(api::unauthorized(err)),
+    };
+    let id = match path_id(&event) {
+        Some(id) => id,
+        None => return Ok(missing_id()),
+    };
+
+    Ok(api::cancel_testrun(store, &caller, &id).await)
+}
+
+/// Extract the testrun id from the path parameters
+fn path_id(event: &Request) -> Option<String> {
+    event.path_parameters().first("id").map(str::to_owned)
+}
+
+/// Source IP of the request, as seen by API Gateway
+fn client_ip(event: &Request) -> Option<IpAddr> {
+    let source_ip = match event.extensions().get::<RequestContext>()? {
+        RequestContext::ApiGatewayV2(context) => context.http.source_ip.as_deref(),
+        RequestContext::ApiGatewayV1(context) => context.identity.source_ip.as_deref(),
+        _ => None,
+    };
+
+    source_ip?.parse().ok()
+}
+
+/// 400 Bad Request response for a missing 'id' path parameter
+fn missing_id() -> Response<String> {
+    warn!("Missing 'id' parameter in path");
+    api::response(
+        StatusCode::BAD_REQUEST,
+        json!({ "message": "Missing 'id' parameter in path" }).to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        entrypoints::auth::{
+            tests::{get_headers, get_token, SECRET},
+            JwtAuthenticator, NoAuthenticator,
+        },
+        model::{TestRun, TestRunStatus, TestRunSummary},
+        ratelimit::MemoryRateLimiter,
+        store::{conformance, MemoryStore, StoreGet, StorePut},
+    };
+    use lambda_http::Body;
+    use std::collections::HashMap;
+
+    /// Stored testrun, without the creation time stamped on put
+    async fn get_stored(store: &MemoryStore, id: &str) -> Option<TestRun> {
+        store.get(id).await.unwrap().map(|testrun| TestRun {
+            created_at: None,
+            ..testrun
+        })
+    }
+
+    /// Testrun in `status` as a client submits it, without a creation time
+    fn get_mock_testrun(status: TestRunStatus) -> TestRun {
+        TestRun {
+            created_at: None,
+            ..conformance::get_testrun_with_status(status)
         }
-        Err(err) => {
-            warn!("Failed to parse testrun from request body: {}", err);
-            return Ok(response(
-                StatusCode::BAD_REQUEST,
-                json!({"message": "Failed to parse testrun from request body"}).to_string(),
-            ));
+    }
+
+    fn get_request(id: &str, body: Option<String>) -> Request {
+        let request = http::Request::builder().header("Content-Type", "application/json");
+        let request = match body {
+            Some(body) => request.body(Body::from(body)),
+            None => request.body(Body::Empty),
+        };
+
+        request
+            .unwrap()
+            .with_path_parameters(HashMap::from([("id".to_owned(), id.to_owned())]))
+    }
+
+    #[tokio::test]
+    async fn test_get_testrun() {
+        let store = MemoryStore::new();
+        store
+            .put(&get_mock_testrun(TestRunStatus::Queued))
+            .await
+            .unwrap();
+
+        let res = get_testrun(&store, &NoAuthenticator, get_request("1", None))
+            .await
+            .unwrap()
+            .into_response()
+            .await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[http::header::ETAG], r#""1""#);
+        let testrun: TestRun = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(testrun, get_mock_testrun(TestRunStatus::Queued));
+    }
+
+    #[tokio::test]
+    async fn test_get_testrun_missing() {
+        let store = MemoryStore::new();
+
+        let res = get_testrun(&store, &NoAuthenticator, get_request("1", None))
+            .await
+            .unwrap()
+            .into_response()
+            .await;
+
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_get_testrun_other_user() {
+        // GIVEN a testrun owned by 'user'
+        let store = MemoryStore::new();
+        store
+            .put(&get_mock_testrun(TestRunStatus::Queued))
+            .await
+            .unwrap();
+        let auth = JwtAuthenticator::new().with_hs256_secret(SECRET);
+        let request = |token: Option<&str>| {
+            let mut request = get_request("1", None);
+            if let Some(token) = token {
+                request.headers_mut().extend(get_headers(token));
+            }
+            request
+        };
+
+        // WHEN it is fetched without a token, by another user and by its owner
+        let mut responses = Vec::new();
+        for token in [None, Some(get_token("other")), Some(get_token("user"))] {
+            let res = get_testrun(&store, &auth, request(token.as_deref()))
+                .await
+                .unwrap()
+                .into_response()
+                .await;
+            responses.push(res.status());
         }
-    };
 
-    info!("Parsed testrun: {:?}", testrun);
+        // THEN only the owner gets it
+        assert_eq!(
+            responses,
+            [
+                StatusCode::UNAUTHORIZED,
+                StatusCode::FORBIDDEN,
+                StatusCode::OK
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_testrun_owner() {
+        // GIVEN a user submitting a testrun in the name of another
+        let store = MemoryStore::new();
+        let auth = JwtAuthenticator::new().with_hs256_secret(SECRET);
+        let body = json!(get_mock_testrun(TestRunStatus::Queued)).to_string();
+        let mut request = http::Request::builder()
+            .method("POST")
+            .uri("https://example.com/")
+            .header("Content-Type", "application/json")
+            .body(Body::from(body))
+            .unwrap();
+        request
+            .headers_mut()
+            .extend(get_headers(&get_token("caller")));
+
+        // WHEN creating the testrun
+        let res = create_testrun(
+            &store,
+            &MemoryRateLimiter::new(),
+            &ApiConfig::default(),
+            &auth,
+            request,
+        )
+        .await
+        .unwrap()
+        .into_response()
+        .await;
+
+        // THEN the caller owns it
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let location = res.headers()[http::header::LOCATION].to_str().unwrap();
+        let stored = store.get(&location[1..]).await.unwrap().unwrap();
+        assert_eq!(stored.user_id.as_deref(), Some("caller"));
+    }
+
+    #[tokio::test]
+    async fn test_put_testrun() {
+        let store = MemoryStore::new();
+        let body = json!(get_mock_testrun(TestRunStatus::Queued)).to_string();
 
-    if testrun.id != id {
-        warn!(
-            "TestRun ID in path ({}) does not match ID in body ({})",
-            id, testrun.id
+        let res = put_testrun(
+            &store,
+            &MemoryRateLimiter::new(),
+            &ApiConfig::default(),
+            &NoAuthenticator,
+            get_request("1", Some(body)),
+        )
+        .await
+        .unwrap()
+        .into_response()
+        .await;
+
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.headers()[http::header::ETAG], r#""1""#);
+        assert_eq!(
+            get_stored(&store, "1").await,
+            Some(get_mock_testrun(TestRunStatus::Queued))
         );
-        return Ok(response(
-            StatusCode::BAD_REQUEST,
-            json!({"message": "TestRun ID in path does not match ID in body"}).to_string(),
-        ));
-    }
-
-    // Put testrun
-    let res = domain::testrun::put_testrun(store, &testrun).await;
-
-    // Return response
-    //
-    // If the put was successful, we return a 201 Created. Otherwise, we return
-    // a 500 Internal Server Error.
-    Ok(match res {
-        // Testrun created
-        Ok(_) => {
-            info!("Queued testrun {:?}", testrun.id);
-            response(
-                StatusCode::CREATED,
-                json!({"message": "Testrun queued"}).to_string(),
-            )
-        }
-        // Error creating testrun
-        Err(err) => {
-            error!("Failed to create testrun {}: {}", testrun.id, err);
-            response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                json!({"message": "Failed to create testrun"}).to_string(),
+    }
+
+    #[tokio::test]
+    async fn test_create_testrun() {
+        // GIVEN a submission with an id, status and results
+        let store = MemoryStore::new();
+        let body = json!(get_mock_testrun(TestRunStatus::Passed)).to_string();
+        let request = http::Request::builder()
+            .method("POST")
+            .uri("https://example.com/")
+            .header("Content-Type", "application/json")
+            .body(Body::from(body))
+            .unwrap();
+
+        // WHEN creating the testrun
+        let res = create_testrun(
+            &store,
+            &MemoryRateLimiter::new(),
+            &ApiConfig::default(),
+            &NoAuthenticator,
+            request,
+        )
+        .await
+        .unwrap()
+        .into_response()
+        .await;
+
+        // THEN it is stored queued under a new id
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.headers()[http::header::ETAG], r#""1""#);
+        let location = res.headers()[http::header::LOCATION].to_str().unwrap();
+        let id = location.strip_prefix('/').unwrap();
+        assert_ne!(id, "1");
+        let stored = store.get(id).await.unwrap().unwrap();
+        assert_eq!(stored.status, TestRunStatus::Queued);
+        assert!(stored.tests.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_put_testrun_ignores_results() {
+        // GIVEN a client submission claiming to have passed
+        let store = MemoryStore::new();
+        let mut body = json!(get_mock_testrun(TestRunStatus::Passed));
+        body["tests"] = json!([{
+            "name": "test_hello",
+            "message": "",
+            "status": "passed",
+            "actualOutput": "hello",
+            "expectedOutput": "hello"
+        }]);
+
+        // WHEN putting the testrun
+        let res = put_testrun(
+            &store,
+            &MemoryRateLimiter::new(),
+            &ApiConfig::default(),
+            &NoAuthenticator,
+            get_request("1", Some(body.to_string())),
+        )
+        .await
+        .unwrap()
+        .into_response()
+        .await;
+
+        // THEN it is queued without results
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(
+            get_stored(&store, "1").await,
+            Some(get_mock_testrun(TestRunStatus::Queued))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_put_results() {
+        // GIVEN a running testrun
+        let store = MemoryStore::new();
+        store
+            .put(&get_mock_testrun(TestRunStatus::Running))
+            .await
+            .unwrap();
+        let body = json!({"status": "passed", "tests": [{
+            "name": "test_hello",
+            "message": "",
+            "status": "passed",
+            "actualOutput": "hello",
+            "expectedOutput": "hello"
+        }]});
+
+        // WHEN a runner writes its results
+        let res = put_results(&store, get_request("1", Some(body.to_string())))
+            .await
+            .unwrap()
+            .into_response()
+            .await;
+
+        // THEN they are stored with the submission
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[http::header::ETAG], r#""2""#);
+        let stored = store.get("1").await.unwrap().unwrap();
+        assert_eq!(stored.status, TestRunStatus::Passed);
+        assert_eq!(stored.tests.len(), 1);
+        assert_eq!(stored.files, get_mock_testrun(TestRunStatus::Running).files);
+    }
+
+    #[tokio::test]
+    async fn test_put_results_invalid() {
+        let store = MemoryStore::new();
+        store
+            .put(&get_mock_testrun(TestRunStatus::Queued))
+            .await
+            .unwrap();
+
+        let missing = put_results(
+            &store,
+            get_request("2", Some(r#"{"status": "running"}"#.to_owned())),
+        )
+        .await
+        .unwrap()
+        .into_response()
+        .await;
+        let invalid = put_results(
+            &store,
+            get_request("1", Some(r#"{"tests": []}"#.to_owned())),
+        )
+        .await
+        .unwrap()
+        .into_response()
+        .await;
+        let transition = put_results(
+            &store,
+            get_request("1", Some(r#"{"status": "passed"}"#.to_owned())),
+        )
+        .await
+        .unwrap()
+        .into_response()
+        .await;
+
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+        assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(transition.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_put_testrun_if_match() {
+        // GIVEN a stored testrun at version 1
+        let store = MemoryStore::new();
+        store
+            .put(&get_mock_testrun(TestRunStatus::Queued))
+            .await
+            .unwrap();
+        let body = json!(get_mock_testrun(TestRunStatus::Running)).to_string();
+
+        // WHEN updating it with a stale and then a current ETag
+        let mut request = get_request("1", Some(body.clone()));
+        request
+            .headers_mut()
+            .insert(http::header::IF_MATCH, r#""2""#.parse().unwrap());
+        let stale = put_testrun(
+            &store,
+            &MemoryRateLimiter::new(),
+            &ApiConfig::default(),
+            &NoAuthenticator,
+            request,
+        )
+        .await
+        .unwrap()
+        .into_response()
+        .await;
+        let mut request = get_request("1", Some(body));
+        request
+            .headers_mut()
+            .insert(http::header::IF_MATCH, r#""1""#.parse().unwrap());
+        let current = put_testrun(
+            &store,
+            &MemoryRateLimiter::new(),
+            &ApiConfig::default(),
+            &NoAuthenticator,
+            request,
+        )
+        .await
+        .unwrap()
+        .into_response()
+        .await;
+
+        // THEN only the current ETag is accepted
+        assert_eq!(stale.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(current.status(), StatusCode::CREATED);
+        assert_eq!(current.headers()[http::header::ETAG], r#""2""#);
+        assert_eq!(store.get("1").await.unwrap().unwrap().version, 2);
+    }
+
+    #[tokio::test]
+    async fn test_put_testrun_archive() {
+        // GIVEN a testrun whose files are sent as a ZIP archive
+        let store = MemoryStore::new();
+        let mut body = json!(get_mock_testrun(TestRunStatus::Queued));
+        body.as_object_mut().unwrap().remove("files");
+        body["archive"] = json!(crate::utils::zip::tests::get_zip_string(&[(
+            "main.py",
+            b"print('hello')"
+        )]));
+
+        // WHEN putting the testrun
+        let res = put_testrun(
+            &store,
+            &MemoryRateLimiter::new(),
+            &ApiConfig::default(),
+            &NoAuthenticator,
+            get_request("1", Some(body.to_string())),
+        )
+        .await
+        .unwrap()
+        .into_response()
+        .await;
+
+        // THEN the archive is expanded into its files
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(
+            get_stored(&store, "1").await,
+            Some(get_mock_testrun(TestRunStatus::Queued))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_put_testrun_files_and_archive() {
+        let store = MemoryStore::new();
+        let mut body = json!(get_mock_testrun(TestRunStatus::Queued));
+        body["archive"] = json!(crate::utils::zip::tests::get_zip_string(&[(
+            "main.py",
+            b"print('hello')"
+        )]));
+
+        let res = put_testrun(
+            &store,
+            &MemoryRateLimiter::new(),
+            &ApiConfig::default(),
+            &NoAuthenticator,
+            get_request("1", Some(body.to_string())),
+        )
+        .await
+        .unwrap()
+        .into_response()
+        .await;
+
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(store.get("1").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_put_testrun_invalid_archive() {
+        let store = MemoryStore::new();
+        let mut body = json!(get_mock_testrun(TestRunStatus::Queued));
+        body.as_object_mut().unwrap().remove("files");
+        body["archive"] = json!("not a zip");
+
+        let res = put_testrun(
+            &store,
+            &MemoryRateLimiter::new(),
+            &ApiConfig::default(),
+            &NoAuthenticator,
+            get_request("1", Some(body.to_string())),
+        )
+        .await
+        .unwrap()
+        .into_response()
+        .await;
+
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_put_testrun_unsafe_archive() {
+        let store = MemoryStore::new();
+        let mut body = json!(get_mock_testrun(TestRunStatus::Queued));
+        body.as_object_mut().unwrap().remove("files");
+        body["archive"] = json!(crate::utils::zip::tests::get_zip_string(&[(
+            "../main.py",
+            b""
+        )]));
+
+        let res = put_testrun(
+            &store,
+            &MemoryRateLimiter::new(),
+            &ApiConfig::default(),
+            &NoAuthenticator,
+            get_request("1", Some(body.to_string())),
+        )
+        .await
+        .unwrap()
+        .into_response()
+        .await;
+
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(
+            body["message"],
+            "Invalid archive: path '../main.py' is not allowed"
+        );
+        assert_eq!(store.get("1").await.unwrap(), None);
+    }
+
+    fn get_multipart_request(id: &str, parts: &[(&str, Option<&str>, &[u8])]) -> Request {
+        use crate::utils::multipart::tests::{get_body, BOUNDARY};
+
+        http::Request::builder()
+            .header(
+                "Content-Type",
+                format!("multipart/form-data; boundary={}", BOUNDARY),
             )
-        }
-    })
-}
+            .body(Body::from(get_body(parts)))
+            .unwrap()
+            .with_path_parameters(HashMap::from([("id".to_owned(), id.to_owned())]))
+    }
+
+    #[tokio::test]
+    async fn test_put_testrun_multipart_files() {
+        // GIVEN a form with the testrun and its files as separate fields
+        let store = MemoryStore::new();
+        let mut testrun = json!(get_mock_testrun(TestRunStatus::Queued));
+        testrun.as_object_mut().unwrap().remove("files");
+        let testrun = testrun.to_string();
+        let request = get_multipart_request(
+            "1",
+            &[
+                ("testrun", None, testrun.as_bytes()),
+                ("files", Some("main.py"), b"print('hello')"),
+            ],
+        );
+
+        // WHEN putting the testrun
+        let res = put_testrun(
+            &store,
+            &MemoryRateLimiter::new(),
+            &ApiConfig::default(),
+            &NoAuthenticator,
+            request,
+        )
+        .await
+        .unwrap()
+        .into_response()
+        .await;
+
+        // THEN the files are collected into the testrun
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(
+            get_stored(&store, "1").await,
+            Some(get_mock_testrun(TestRunStatus::Queued))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_put_testrun_multipart_archive() {
+        // GIVEN a form with the testrun and a tar.gz archive
+        let store = MemoryStore::new();
+        let mut testrun = json!(get_mock_testrun(TestRunStatus::Queued));
+        testrun.as_object_mut().unwrap().remove("files");
+        let testrun = testrun.to_string();
+        let archive = crate::utils::archive::tests::get_tar_gz(&[("main.py", b"print('hello')")]);
+        let request = get_multipart_request(
+            "1",
+            &[
+                ("testrun", None, testrun.as_bytes()),
+                ("archive", Some("src.tar.gz"), &archive),
+            ],
+        );
+
+        // WHEN putting the testrun
+        let res = put_testrun(
+            &store,
+            &MemoryRateLimiter::new(),
+            &ApiConfig::default(),
+            &NoAuthenticator,
+            request,
+        )
+        .await
+        .unwrap()
+        .into_response()
+        .await;
+
+        // THEN the archive is expanded into the testrun
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(
+            get_stored(&store, "1").await,
+            Some(get_mock_testrun(TestRunStatus::Queued))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_put_testrun_multipart_missing_testrun() {
+        let store = MemoryStore::new();
+        let request = get_multipart_request("1", &[("files", Some("main.py"), b"")]);
 
-/// HTTP Response with a JSON payload
-fn response(status_code: StatusCode, body: String) -> Response<String> {
-    Response::builder()
-        .status(status_code)
-        .header("Content-Type", "application/json")
-        .body(body)
+        let res = put_testrun(
+            &store,
+            &MemoryRateLimiter::new(),
+            &ApiConfig::default(),
+            &NoAuthenticator,
+            request,
+        )
+        .await
         .unwrap()
+        .into_response()
+        .await;
+
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(store.get("1").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_put_testrun_id_mismatch() {
+        let store = MemoryStore::new();
+        let body = json!(get_mock_testrun(TestRunStatus::Queued)).to_string();
+
+        let res = put_testrun(
+            &store,
+            &MemoryRateLimiter::new(),
+            &ApiConfig::default(),
+            &NoAuthenticator,
+            get_request("2", Some(body)),
+        )
+        .await
+        .unwrap()
+        .into_response()
+        .await;
+
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(store.get("1").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_put_testrun_missing_language() {
+        let store = MemoryStore::new();
+        let body = json!({
+            "id": "1",
+            "files": {}
+        })
+        .to_string();
+
+        let res = put_testrun(
+            &store,
+            &MemoryRateLimiter::new(),
+            &ApiConfig::default(),
+            &NoAuthenticator,
+            get_request("1", Some(body)),
+        )
+        .await
+        .unwrap()
+        .into_response()
+        .await;
+
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_put_testrun_invalid_transition() {
+        let store = MemoryStore::new();
+        store
+            .put(&get_mock_testrun(TestRunStatus::Passed))
+            .await
+            .unwrap();
+        let body = json!(get_mock_testrun(TestRunStatus::Queued)).to_string();
+
+        let res = put_testrun(
+            &store,
+            &MemoryRateLimiter::new(),
+            &ApiConfig::default(),
+            &NoAuthenticator,
+            get_request("1", Some(body)),
+        )
+        .await
+        .unwrap()
+        .into_response()
+        .await;
+
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            store.get("1").await.unwrap(),
+            Some(get_mock_testrun(TestRunStatus::Passed))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_testruns() {
+        // GIVEN a store with a testrun
+        let store = MemoryStore::new();
+        store
+            .put(&get_mock_testrun(TestRunStatus::Queued))
+            .await
+            .unwrap();
+        let request = http::Request::builder()
+            .body(Body::Empty)
+            .unwrap()
+            .with_query_string_parameters(HashMap::from([
+                ("user".to_owned(), "user".to_owned()),
+                ("status".to_owned(), "queued".to_owned()),
+            ]));
+
+        // WHEN listing testruns for the user
+        let res = list_testruns(&store, &NoAuthenticator, request)
+            .await
+            .unwrap()
+            .into_response()
+            .await;
+
+        // THEN it returns the summary of the testrun without a cursor
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "testruns": [TestRunSummary::from(&get_mock_testrun(TestRunStatus::Queued))],
+                "cursor": null
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_testruns_invalid_limit() {
+        let store = MemoryStore::new();
+        let request = http::Request::builder()
+            .body(Body::Empty)
+            .unwrap()
+            .with_query_string_parameters(HashMap::from([("limit".to_owned(), "-1".to_owned())]));
+
+        let res = list_testruns(&store, &NoAuthenticator, request)
+            .await
+            .unwrap()
+            .into_response()
+            .await;
+
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_get_testrun_missing_id() {
+        let store = MemoryStore::new();
+        let request = http::Request::builder().body(Body::Empty).unwrap();
+
+        let res = get_testrun(&store, &NoAuthenticator, request)
+            .await
+            .unwrap()
+            .into_response()
+            .await;
+
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_delete_testrun() {
+        let store = MemoryStore::new();
+        store
+            .put(&get_mock_testrun(TestRunStatus::Queued))
+            .await
+            .unwrap();
+
+        let res = delete_testrun(&store, &NoAuthenticator, get_request("1", None))
+            .await
+            .unwrap()
+            .into_response()
+            .await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(store.get("1").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_delete_testrun_missing() {
+        let store = MemoryStore::new();
+
+        let res = delete_testrun(&store, &NoAuthenticator, get_request("1", None))
+            .await
+            .unwrap()
+            .into_response()
+            .await;
+
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_testrun() {
+        let store = MemoryStore::new();
+        store
+            .put(&get_mock_testrun(TestRunStatus::Running))
+            .await
+            .unwrap();
+
+        let res = cancel_testrun(&store, &NoAuthenticator, get_request("1", None))
+            .await
+            .unwrap()
+            .into_response()
+            .await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[http::header::ETAG], r#""2""#);
+        let testrun: TestRun = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(testrun.status, TestRunStatus::Cancelled);
+        assert_eq!(store.get("1").await.unwrap(), Some(testrun));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_testrun_finished() {
+        let store = MemoryStore::new();
+        store
+            .put(&get_mock_testrun(TestRunStatus::Passed))
+            .await
+            .unwrap();
+
+        let res = cancel_testrun(&store, &NoAuthenticator, get_request("1", None))
+            .await
+            .unwrap()
+            .into_response()
+            .await;
+
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        assert_eq!(
+            store.get("1").await.unwrap(),
+            Some(get_mock_testrun(TestRunStatus::Passed))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancel_testrun_missing() {
+        let store = MemoryStore::new();
+
+        let res = cancel_testrun(&store, &NoAuthenticator, get_request("1", None))
+            .await
+            .unwrap()
+            .into_response()
+            .await;
+
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
 }
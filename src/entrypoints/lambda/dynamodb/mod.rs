@@ -1,29 +1,325 @@
-use crate::{domain, events::EventBus, model::Event};
+use crate::{
+    domain,
+    error::Error,
+    events::EventBus,
+    model::Event,
+    store::{blob::BlobStore, dynamodb::rehydrate},
+};
+use futures::future::join_all;
 use lambda_runtime::Context;
 use rayon::prelude::*;
-use tracing::{info, instrument};
+use tracing::{error, info, instrument};
+
+use self::model::{BatchItemFailure, DynamoDBEventResponse, DynamoDBRecord};
 
 pub mod model;
 
 type E = Box<dyn std::error::Error + Sync + Send + 'static>;
 
 /// Parse events from DynamoDB Streams
-#[instrument(skip(event_bus, event))]
+///
+/// Records that cannot be converted to an event never will be, so they are
+/// logged and skipped. Files and tests spilled to a blob store are read back
+/// from `blobs`. Records whose blobs could not be read, or whose event could
+/// not be sent, are reported as batch item failures, for Lambda to retry them
+/// and the records after them.
+///
+/// The records after one whose blobs could not be read are held back, as they
+/// are retried anyway. Events are sent together though, so when one of them
+/// fails, the events of later records may already be sent, and are sent again
+/// with the retry. Consumers deduplicate events by testrun id and version.
+#[instrument(skip(event_bus, blobs, event))]
 pub async fn parse_events(
     event_bus: &dyn EventBus<E = Event>,
+    blobs: Option<&dyn BlobStore>,
     event: model::DynamoDBEvent,
     _: Context,
-) -> Result<(), E> {
+) -> Result<DynamoDBEventResponse, E> {
     info!("Transform events");
-    let events = event
+    let (records, events): (Vec<_>, Vec<_>) = event
         .records
         .par_iter()
-        .map(|record| record.try_into())
-        .collect::<Result<Vec<_>, _>>()?;
+        .filter_map(|record| match Event::try_from(record) {
+            Ok(event) => Some((record, event)),
+            Err(err) => {
+                error!(
+                    "Skipping record '{}' that cannot be decoded: {}",
+                    record.event_id, err
+                );
+                None
+            }
+        })
+        .unzip();
+
+    info!("Reading back spilled payloads");
+    let rehydrated = join_all(
+        records
+            .iter()
+            .zip(events)
+            .map(|(record, mut event)| async move {
+                let res = rehydrate_event(&mut event, record, blobs).await;
+                (event, res)
+            }),
+    )
+    .await;
+
+    let mut failed = Vec::new();
+    let mut events = Vec::new();
+    for (position, (event, res)) in rehydrated.into_iter().enumerate() {
+        match res {
+            Ok(()) if failed.is_empty() => events.push(event),
+            Ok(()) => failed.push(position),
+            Err(err) => {
+                error!(
+                    "Failed to read the payload of '{}' back: {}",
+                    event.id(),
+                    err
+                );
+                failed.push(position);
+            }
+        }
+    }
 
     info!("Dispatching {} events", events.len());
-    domain::send_events(event_bus, &events).await?;
+    let result = domain::send_events(event_bus, &events).await?;
+    for failure in &result.failed {
+        error!(
+            "Failed to send event for '{}': {} {}",
+            events[failure.index].id(),
+            failure.code,
+            failure.message.as_deref().unwrap_or_default()
+        );
+        failed.push(failure.index);
+    }
     info!("Done dispatching events");
 
-    Ok(())
+    failed.sort_unstable();
+    Ok(DynamoDBEventResponse {
+        batch_item_failures: failed
+            .into_iter()
+            .map(|position| BatchItemFailure {
+                item_identifier: records[position].dynamodb.sequence_number.clone(),
+            })
+            .collect(),
+    })
+}
+
+/// Read back the payloads that the images of `record` spilled to `blobs`
+async fn rehydrate_event(
+    event: &mut Event,
+    record: &DynamoDBRecord,
+    blobs: Option<&dyn BlobStore>,
+) -> Result<(), Error> {
+    let images = &record.dynamodb;
+    match event {
+        Event::Created { testrun, .. } | Event::Cancelled { testrun, .. } => {
+            rehydrate(testrun, &images.new_image, blobs).await
+        }
+        Event::Updated { old, new, .. } => {
+            rehydrate(old, &images.old_image, blobs).await?;
+            rehydrate(new, &images.new_image, blobs).await
+        }
+        Event::Deleted { testrun, .. } | Event::Expired { testrun, .. } => {
+            rehydrate(testrun, &images.old_image, blobs).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        events::memory::MemoryEventBus,
+        store::{
+            blob::{blob_key, LocalBlobStore},
+            conformance,
+        },
+    };
+    use std::collections::BTreeMap;
+
+    /// Insert record of a testrun, with an invalid image for `None`
+    fn get_record(id: Option<&str>, sequence_number: &str) -> serde_json::Value {
+        let image = match id {
+            Some(id) => serde_json::json!({
+                "id": {"S": id},
+                "files": {"M": {}},
+                "language": {"S": "python"},
+                "status": {"S": "queued"},
+                "tests": {"L": []}
+            }),
+            None => serde_json::json!({"id": {"N": "1"}}),
+        };
+
+        insert_record(image, sequence_number)
+    }
+
+    /// Insert record of a testrun whose files and tests were spilled
+    fn get_spilled_record(
+        id: &str,
+        sequence_number: &str,
+        files_ref: &str,
+        tests_ref: &str,
+    ) -> serde_json::Value {
+        let image = serde_json::json!({
+            "id": {"S": id},
+            "filesRef": {"S": files_ref},
+            "language": {"S": "python"},
+            "status": {"S": "queued"},
+            "testsRef": {"S": tests_ref}
+        });
+
+        insert_record(image, sequence_number)
+    }
+
+    fn insert_record(image: serde_json::Value, sequence_number: &str) -> serde_json::Value {
+        serde_json::json!({
+            "awsRegion": "eu-west-1",
+            "dynamodb": {
+                "NewImage": image,
+                "SequenceNumber": sequence_number,
+                "SizeBytes": 120,
+                "StreamViewType": "NEW_AND_OLD_IMAGES"
+            },
+            "eventID": sequence_number,
+            "eventName": "INSERT",
+            "eventSource": "aws:dynamodb",
+            "eventSourceARN": "arn:aws:dynamodb:eu-west-1:123456789012:table/test/stream/2023-01-01T00:00:00.000",
+            "eventVersion": "1.1"
+        })
+    }
+
+    #[tokio::test]
+    async fn parse_events_reports_failures() {
+        // GIVEN a batch with an undecodable record, and a bus rejecting an event
+        let event = serde_json::from_value(serde_json::json!({
+            "Records": [
+                get_record(Some("1"), "101"),
+                get_record(None, "102"),
+                get_record(Some("3"), "103"),
+                get_record(Some("4"), "104"),
+            ]
+        }))
+        .unwrap();
+
+        let bus = MemoryEventBus::new();
+        bus.reject("3");
+
+        // WHEN parsing the batch
+        let res = parse_events(&bus, None, event, Context::default())
+            .await
+            .unwrap();
+
+        // THEN only the record of the rejected event is reported, and Lambda
+        // retries from it, sending the event of the later record again
+        assert_eq!(
+            res.batch_item_failures,
+            vec![BatchItemFailure {
+                item_identifier: "103".to_owned()
+            }]
+        );
+        let sent = bus.events();
+        assert_eq!(
+            sent.iter().map(|event| event.id()).collect::<Vec<_>>(),
+            vec!["1", "4"]
+        );
+    }
+
+    #[tokio::test]
+    async fn parse_events_success() {
+        let event = serde_json::from_value(serde_json::json!({
+            "Records": [get_record(Some("1"), "101")]
+        }))
+        .unwrap();
+
+        let bus = MemoryEventBus::new();
+
+        let res = parse_events(&bus, None, event, Context::default())
+            .await
+            .unwrap();
+
+        assert_eq!(res, DynamoDBEventResponse::default());
+        assert!(matches!(&bus.events()[..], [Event::Created { .. }]));
+    }
+
+    #[tokio::test]
+    async fn parse_events_spilled() {
+        // GIVEN a record whose files and tests were spilled to a blob store
+        let testrun = conformance::get_testrun();
+        let dir = tempfile::tempdir().unwrap();
+        let blobs = LocalBlobStore::new(dir.path());
+        let files = serde_json::to_vec(&testrun.files.iter().collect::<BTreeMap<_, _>>()).unwrap();
+        let tests = serde_json::to_vec(&testrun.tests).unwrap();
+        blobs.put(&blob_key(&files), &files).await.unwrap();
+        blobs.put(&blob_key(&tests), &tests).await.unwrap();
+
+        let event = serde_json::from_value(serde_json::json!({
+            "Records": [get_spilled_record("1", "101", &blob_key(&files), &blob_key(&tests))]
+        }))
+        .unwrap();
+
+        let bus = MemoryEventBus::new();
+
+        // WHEN parsing the batch
+        let res = parse_events(&bus, Some(&blobs), event, Context::default())
+            .await
+            .unwrap();
+
+        // THEN the event holds the files and tests read back from the blobs,
+        // and their keys
+        assert_eq!(res, DynamoDBEventResponse::default());
+        match &bus.events()[..] {
+            [Event::Created {
+                testrun: created,
+                refs,
+            }] => {
+                assert_eq!(created.files, testrun.files);
+                assert_eq!(created.tests, testrun.tests);
+                assert_eq!(refs.files_ref, Some(blob_key(&files)));
+                assert_eq!(refs.tests_ref, Some(blob_key(&tests)));
+            }
+            events => panic!("unexpected events: {:?}", events),
+        }
+    }
+
+    #[tokio::test]
+    async fn parse_events_missing_blob() {
+        // GIVEN a batch where the blobs of a testrun are missing
+        let dir = tempfile::tempdir().unwrap();
+        let blobs = LocalBlobStore::new(dir.path());
+
+        let event = serde_json::from_value(serde_json::json!({
+            "Records": [
+                get_record(Some("1"), "101"),
+                get_spilled_record("2", "102", "sha256/missing", "sha256/missing"),
+                get_record(Some("3"), "103"),
+            ]
+        }))
+        .unwrap();
+
+        let bus = MemoryEventBus::new();
+
+        // WHEN parsing the batch
+        let res = parse_events(&bus, Some(&blobs), event, Context::default())
+            .await
+            .unwrap();
+
+        // THEN the records from that testrun on are reported, and only the
+        // earlier ones sent
+        assert_eq!(
+            res.batch_item_failures,
+            vec![
+                BatchItemFailure {
+                    item_identifier: "102".to_owned()
+                },
+                BatchItemFailure {
+                    item_identifier: "103".to_owned()
+                },
+            ]
+        );
+        let sent = bus.events();
+        assert_eq!(
+            sent.iter().map(|event| event.id()).collect::<Vec<_>>(),
+            vec!["1"]
+        );
+    }
 }
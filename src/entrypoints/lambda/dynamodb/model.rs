@@ -7,10 +7,12 @@
 
 use crate::{
     error::Error,
-    model::{Event, Test, TestRun},
+    model::{Event, PayloadRefs, TestRun, TestRunStatus},
+    store::dynamodb::codec::{self, Attribute},
 };
+use base64::{engine::general_purpose::STANDARD, Engine as _};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{borrow::Cow, collections::HashMap};
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct DynamoDBEvent {
@@ -18,6 +20,22 @@ pub struct DynamoDBEvent {
     pub records: Vec<DynamoDBRecord>,
 }
 
+/// Response to a batch of records, listing the records to retry
+///
+/// Lambda retries the batch from the failure with the lowest sequence number.
+#[derive(Deserialize, Serialize, Debug, Default, PartialEq)]
+pub struct DynamoDBEventResponse {
+    #[serde(rename = "batchItemFailures")]
+    pub batch_item_failures: Vec<BatchItemFailure>,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct BatchItemFailure {
+    /// Sequence number of the failed record
+    #[serde(rename = "itemIdentifier")]
+    pub item_identifier: String,
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct DynamoDBRecord {
     #[serde(rename = "awsRegion")]
@@ -40,6 +58,35 @@ pub struct DynamoDBRecord {
 
     #[serde(rename = "eventVersion")]
     pub event_version: String,
+
+    /// Set when the record was caused by DynamoDB itself, such as a TTL
+    /// deletion
+    #[serde(
+        rename = "userIdentity",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub user_identity: Option<UserIdentity>,
+}
+
+impl DynamoDBRecord {
+    /// Whether the record is a deletion of an expired item by the table TTL
+    pub fn is_ttl_removal(&self) -> bool {
+        self.event_name == "REMOVE"
+            && self.user_identity.as_ref().is_some_and(|identity| {
+                identity.identity_type == "Service"
+                    && identity.principal_id == "dynamodb.amazonaws.com"
+            })
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct UserIdentity {
+    #[serde(rename = "type")]
+    pub identity_type: String,
+
+    #[serde(rename = "principalId")]
+    pub principal_id: String,
 }
 
 impl TryFrom<&DynamoDBRecord> for Event {
@@ -50,22 +97,41 @@ impl TryFrom<&DynamoDBRecord> for Event {
         match value.event_name.as_str() {
             "INSERT" => {
                 let testrun = (&value.dynamodb.new_image).try_into()?;
-                Ok(Event::Created { testrun })
+                let refs = payload_refs(&value.dynamodb.new_image)?;
+                Ok(Event::Created { testrun, refs })
             }
             "MODIFY" => {
-                let old = (&value.dynamodb.old_image).try_into()?;
-                let new = (&value.dynamodb.new_image).try_into()?;
-                Ok(Event::Updated { old, new })
+                let old: TestRun = (&value.dynamodb.old_image).try_into()?;
+                let new: TestRun = (&value.dynamodb.new_image).try_into()?;
+                let refs = payload_refs(&value.dynamodb.new_image)?;
+                match (old.status, new.status) {
+                    (from, TestRunStatus::Cancelled) if from != TestRunStatus::Cancelled => {
+                        Ok(Event::Cancelled { testrun: new, refs })
+                    }
+                    _ => Ok(Event::Updated { old, new, refs }),
+                }
             }
             "REMOVE" => {
                 let testrun = (&value.dynamodb.old_image).try_into()?;
-                Ok(Event::Deleted { testrun })
+                let refs = payload_refs(&value.dynamodb.old_image)?;
+                match value.is_ttl_removal() {
+                    true => Ok(Event::Expired { testrun, refs }),
+                    false => Ok(Event::Deleted { testrun, refs }),
+                }
             }
             _ => Err(Error::InternalError("Unknown event type")),
         }
     }
 }
 
+/// Blob keys of the payloads an image spilled, see `codec::FILES_REF`
+fn payload_refs(image: &HashMap<String, AttributeValue>) -> Result<PayloadRefs, Error> {
+    Ok(PayloadRefs {
+        files_ref: codec::decode_ref(image, codec::FILES_REF)?,
+        tests_ref: codec::decode_ref(image, codec::TESTS_REF)?,
+    })
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct DynamoDBStreamRecord {
     #[serde(rename = "ApproximateCreationDateTime", default)]
@@ -93,23 +159,34 @@ pub struct DynamoDBStreamRecord {
 /// Attribute Value
 ///
 /// This is a copy of the `AttributeValue` struct from the AWS SDK for Rust,
-/// but without blob and `is_`-prefixed methods.
+/// but without binary sets and `is_`-prefixed methods. Binary values are kept
+/// base64-encoded, as they appear in the stream records.
 /// See https://docs.rs/aws-sdk-dynamodb/0.0.22-alpha/aws_sdk_dynamodb/model/enum.AttributeValue.html
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub enum AttributeValue {
-    // B(Blob),
+    B(String),
+    #[serde(rename = "BOOL")]
     Bool(bool),
     // Bs(Vec<Blob>),
     L(Vec<AttributeValue>),
     M(HashMap<String, AttributeValue>),
     N(String),
+    #[serde(rename = "NS")]
     Ns(Vec<String>),
+    #[serde(rename = "NULL")]
     Null(bool),
     S(String),
+    #[serde(rename = "SS")]
     Ss(Vec<String>),
 }
 
 impl AttributeValue {
+    pub fn as_b(&self) -> Option<Vec<u8>> {
+        match self {
+            AttributeValue::B(b) => STANDARD.decode(b).ok(),
+            _ => None,
+        }
+    }
     pub fn as_bool(&self) -> Option<bool> {
         match self {
             AttributeValue::Bool(b) => Some(*b),
@@ -160,56 +237,312 @@ impl AttributeValue {
     }
 }
 
+impl Attribute for AttributeValue {
+    fn from_s(value: String) -> Self {
+        AttributeValue::S(value)
+    }
+
+    fn from_n(value: String) -> Self {
+        AttributeValue::N(value)
+    }
+
+    fn from_b(value: Vec<u8>) -> Self {
+        AttributeValue::B(STANDARD.encode(value))
+    }
+
+    fn from_bool(value: bool) -> Self {
+        AttributeValue::Bool(value)
+    }
+
+    fn from_l(value: Vec<Self>) -> Self {
+        AttributeValue::L(value)
+    }
+
+    fn from_m(value: HashMap<String, Self>) -> Self {
+        AttributeValue::M(value)
+    }
+
+    fn try_s(&self) -> Option<&str> {
+        self.as_s()
+    }
+
+    fn try_n(&self) -> Option<&str> {
+        match self {
+            AttributeValue::N(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    fn try_b(&self) -> Option<Cow<'_, [u8]>> {
+        self.as_b().map(Cow::Owned)
+    }
+
+    fn try_bool(&self) -> Option<bool> {
+        self.as_bool()
+    }
+
+    fn try_l(&self) -> Option<&[Self]> {
+        self.as_l().map(Vec::as_slice)
+    }
+
+    fn try_m(&self) -> Option<&HashMap<String, Self>> {
+        self.as_m()
+    }
+}
+
 impl TryFrom<&HashMap<String, AttributeValue>> for TestRun {
     type Error = Error;
 
-    /// Try to convert a DynamoDB item into a Product
+    /// Try to convert a DynamoDB item into a TestRun
     ///
     /// This could fail as the DynamoDB item might be missing some fields.
     fn try_from(value: &HashMap<String, AttributeValue>) -> Result<Self, Self::Error> {
-        // Convert the files attribute to a HashMap
-        let files_json: String = value
-            .get("files")
-            .ok_or(Error::InternalError("Missing files"))?
-            .as_s()
-            .ok_or(Error::InternalError("files is not a string"))?
-            .to_string();
-
-        let files: HashMap<String, String> = serde_json::from_str(&files_json)
-            .map_err(|e| Error::InternalError("Couldn't parse HashMap from payload"))?;
-
-        // Convert the tests attribute to a Vec<Test>
-        let tests_json: String = value
-            .get("tests")
-            .ok_or(Error::InternalError("Missing tests"))?
-            .as_s()
-            .ok_or(Error::InternalError("tests is not a string"))?
-            .to_string();
-
-        let tests: Vec<Test> = serde_json::from_str(&tests_json)
-            .map_err(|e| Error::InternalError("Couldn't parse HashMap from payload"))?;
-
-        Ok(TestRun {
-            id: value
-                .get("id")
-                .ok_or(Error::InternalError("Missing id"))?
-                .as_s()
-                .ok_or(Error::InternalError("id is not a string"))?
-                .to_string(),
-            language: value
-                .get("language")
-                .ok_or(Error::InternalError("Missing language"))?
-                .as_s()
-                .ok_or(Error::InternalError("language is not a string"))?
-                .to_string(),
-            files,
-            status: value
-                .get("status")
-                .ok_or(Error::InternalError("Missing status"))?
-                .as_s()
-                .ok_or(Error::InternalError("status is not a string"))?
-                .to_string(),
-            tests,
-        })
+        Ok(codec::decode(value)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        model::{FileEntry, Test, TestStatus},
+        store::dynamodb::codec::Compression,
+    };
+
+    #[test]
+    fn record_insert_to_event() {
+        let record: DynamoDBRecord = serde_json::from_str(
+            r#"{
+                "awsRegion": "eu-west-1",
+                "dynamodb": {
+                    "ApproximateCreationDateTime": 1676000000,
+                    "Keys": {"id": {"S": "1"}},
+                    "NewImage": {
+                        "id": {"S": "1"},
+                        "files": {"M": {
+                            "main.py": {"S": "print('hello')"},
+                            "main.wasm": {"M": {
+                                "content": {"B": "AGFzbQ=="},
+                                "executable": {"BOOL": true}
+                            }}
+                        }},
+                        "language": {"S": "python"},
+                        "status": {"S": "queued"},
+                        "tests": {"L": []}
+                    },
+                    "SequenceNumber": "100000000000000000001",
+                    "SizeBytes": 120,
+                    "StreamViewType": "NEW_AND_OLD_IMAGES"
+                },
+                "eventID": "1",
+                "eventName": "INSERT",
+                "eventSource": "aws:dynamodb",
+                "eventSourceARN": "arn:aws:dynamodb:eu-west-1:123456789012:table/test/stream/2023-01-01T00:00:00.000",
+                "eventVersion": "1.1"
+            }"#,
+        )
+        .unwrap();
+
+        let event: Event = (&record).try_into().unwrap();
+
+        match event {
+            Event::Created { testrun, .. } => {
+                assert_eq!(testrun.id, "1");
+                assert_eq!(
+                    testrun.files.get("main.py"),
+                    Some(&FileEntry::text("print('hello')"))
+                );
+                assert_eq!(
+                    testrun.files.get("main.wasm"),
+                    Some(&FileEntry::binary(b"\0asm".to_vec()).with_executable(true))
+                );
+                assert!(testrun.tests.is_empty());
+            }
+            _ => panic!("Expected a Created event"),
+        }
+    }
+
+    #[test]
+    fn record_compressed_modify_to_event() {
+        // GIVEN an uncompressed old image and a zstd compressed new image
+        let old = TestRun {
+            id: "1".to_owned(),
+            user_id: None,
+            challenge_id: None,
+            files: HashMap::from([("main.py".to_owned(), "print('hello')".into())]),
+            language: "python".to_owned(),
+            status: TestRunStatus::Queued,
+            tests: Vec::new(),
+            version: 1,
+            created_at: None,
+        };
+        let new = TestRun {
+            status: TestRunStatus::Passed,
+            tests: vec![Test {
+                message: "".to_owned(),
+                name: "test_hello".to_owned(),
+                status: TestStatus::Passed,
+                actual_output: "hello".repeat(100),
+                expected_output: "hello".repeat(100),
+            }],
+            version: 2,
+            ..old.clone()
+        };
+        let record = DynamoDBRecord {
+            aws_region: "eu-west-1".to_owned(),
+            dynamodb: DynamoDBStreamRecord {
+                approximate_creation_date_time: None,
+                keys: HashMap::from([("id".to_owned(), AttributeValue::S("1".to_owned()))]),
+                new_image: codec::encode_with(&new, Compression::Zstd),
+                old_image: codec::encode(&old),
+                sequence_number: "100000000000000000001".to_owned(),
+                size_bytes: 200.0,
+                stream_view_type: "NEW_AND_OLD_IMAGES".to_owned(),
+            },
+            event_id: "1".to_owned(),
+            event_name: "MODIFY".to_owned(),
+            event_source: "aws:dynamodb".to_owned(),
+            event_source_arn:
+                "arn:aws:dynamodb:eu-west-1:123456789012:table/test/stream/2023-01-01T00:00:00.000"
+                    .to_owned(),
+            event_version: "1.1".to_owned(),
+            user_identity: None,
+        };
+
+        // WHEN converting the record
+        let event: Event = (&record).try_into().unwrap();
+
+        // THEN both images are decoded
+        match event {
+            Event::Updated {
+                old: old_testrun,
+                new: new_testrun,
+                ..
+            } => {
+                assert_eq!(old_testrun, old);
+                assert_eq!(new_testrun, new);
+            }
+            _ => panic!("Expected an Updated event"),
+        }
+    }
+
+    #[test]
+    fn record_cancel_to_event() {
+        let testrun = |status: &str| {
+            format!(
+                r#"{{
+                    "id": {{"S": "1"}},
+                    "files": {{"M": {{}}}},
+                    "language": {{"S": "python"}},
+                    "status": {{"S": "{}"}},
+                    "tests": {{"L": []}}
+                }}"#,
+                status
+            )
+        };
+        let record: DynamoDBRecord = serde_json::from_str(&format!(
+            r#"{{
+                "awsRegion": "eu-west-1",
+                "dynamodb": {{
+                    "Keys": {{"id": {{"S": "1"}}}},
+                    "NewImage": {},
+                    "OldImage": {},
+                    "SequenceNumber": "100000000000000000001",
+                    "SizeBytes": 120,
+                    "StreamViewType": "NEW_AND_OLD_IMAGES"
+                }},
+                "eventID": "1",
+                "eventName": "MODIFY",
+                "eventSource": "aws:dynamodb",
+                "eventSourceARN": "arn:aws:dynamodb:eu-west-1:123456789012:table/test/stream/2023-01-01T00:00:00.000",
+                "eventVersion": "1.1"
+            }}"#,
+            testrun("cancelled"),
+            testrun("running")
+        ))
+        .unwrap();
+
+        let event: Event = (&record).try_into().unwrap();
+
+        assert!(matches!(
+            event,
+            Event::Cancelled { testrun, .. } if testrun.status == TestRunStatus::Cancelled
+        ));
+    }
+
+    /// REMOVE record of the given testrun, with an optional user identity
+    fn get_remove_record(user_identity: &str) -> DynamoDBRecord {
+        serde_json::from_str(&format!(
+            r#"{{
+                "awsRegion": "eu-west-1",
+                "dynamodb": {{
+                    "Keys": {{"id": {{"S": "1"}}}},
+                    "OldImage": {{
+                        "id": {{"S": "1"}},
+                        "files": {{"M": {{}}}},
+                        "language": {{"S": "python"}},
+                        "status": {{"S": "passed"}},
+                        "tests": {{"L": []}},
+                        "ttl": {{"N": "1680000000"}}
+                    }},
+                    "SequenceNumber": "100000000000000000001",
+                    "SizeBytes": 80,
+                    "StreamViewType": "NEW_AND_OLD_IMAGES"
+                }},
+                "eventID": "1",
+                "eventName": "REMOVE",
+                "eventSource": "aws:dynamodb",
+                "eventSourceARN": "arn:aws:dynamodb:eu-west-1:123456789012:table/test/stream/2023-01-01T00:00:00.000",
+                "eventVersion": "1.1"{}
+            }}"#,
+            user_identity
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn record_ttl_remove_to_event() {
+        let record = get_remove_record(
+            r#", "userIdentity": {"type": "Service", "principalId": "dynamodb.amazonaws.com"}"#,
+        );
+
+        let event: Event = (&record).try_into().unwrap();
+
+        assert!(matches!(event, Event::Expired { testrun, .. } if testrun.id == "1"));
+    }
+
+    #[test]
+    fn record_user_remove_to_event() {
+        let record = get_remove_record("");
+
+        let event: Event = (&record).try_into().unwrap();
+
+        assert!(matches!(event, Event::Deleted { testrun, .. } if testrun.id == "1"));
+    }
+
+    #[test]
+    fn record_invalid_image() {
+        let record: DynamoDBRecord = serde_json::from_str(
+            r#"{
+                "awsRegion": "eu-west-1",
+                "dynamodb": {
+                    "Keys": {"id": {"S": "1"}},
+                    "NewImage": {"id": {"S": "1"}, "deleted": {"BOOL": true}},
+                    "SequenceNumber": "100000000000000000001",
+                    "SizeBytes": 20,
+                    "StreamViewType": "NEW_AND_OLD_IMAGES"
+                },
+                "eventID": "1",
+                "eventName": "INSERT",
+                "eventSource": "aws:dynamodb",
+                "eventSourceARN": "arn:aws:dynamodb:eu-west-1:123456789012:table/test/stream/2023-01-01T00:00:00.000",
+                "eventVersion": "1.1"
+            }"#,
+        )
+        .unwrap();
+
+        let event: Result<Event, Error> = (&record).try_into();
+
+        assert!(matches!(event, Err(Error::CodecError(_))));
     }
 }
@@ -1 +1,6 @@
+pub mod api;
+pub mod auth;
+#[cfg(feature = "lambda")]
 pub mod lambda;
+#[cfg(feature = "server")]
+pub mod server;
@@ -0,0 +1,1133 @@
+//! # Standalone HTTP server
+//!
+//! Serves the same API as the Lambda functions over plain HTTP, so the whole
+//! service can run locally without SAM.
+
+use crate::{
+    domain,
+    entrypoints::{
+        api::{self, ApiConfig, Submission},
+        auth::Authenticator,
+    },
+    ratelimit::RateLimiter,
+    store::Store,
+};
+use hyper::{
+    body::HttpBody,
+    server::conn::AddrStream,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde_json::json;
+use std::{
+    convert::Infallible,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tracing::{error, info, instrument, warn};
+
+/// Default size above which request bodies are rejected, in bytes
+///
+/// Leaves room for a base64-encoded archive at the default
+/// `ARCHIVE_MAX_TOTAL_SIZE`.
+pub const DEFAULT_MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/// Backends shared by all requests
+#[derive(Clone)]
+pub struct Services {
+    pub store: Arc<dyn Store>,
+    pub auth: Arc<dyn Authenticator>,
+    pub limiter: Arc<dyn RateLimiter>,
+    /// Settings of the submission handlers
+    pub config: ApiConfig,
+    /// Bearer token of runners writing results, if any
+    pub runner_token: Option<String>,
+    /// Size above which request bodies are rejected, in bytes
+    pub max_body_size: usize,
+}
+
+/// Serve the API on `addr` until `shutdown` completes
+///
+/// In-flight requests are allowed to finish before returning.
+pub async fn serve(
+    services: Services,
+    addr: SocketAddr,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), hyper::Error> {
+    let make_service = make_service_fn(move |conn: &AddrStream| {
+        let services = services.clone();
+        let client_ip = conn.remote_addr().ip();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                route(services.clone(), Some(client_ip), req)
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    info!("Listening on http://{}", server.local_addr());
+
+    server.with_graceful_shutdown(shutdown).await
+}
+
+/// Dispatch a request to the matching API handler
+///
+/// Runners writing results authenticate with `services.runner_token`, every
+/// other request must be authenticated by `services.auth`. Submissions are
+/// rate limited per `client_ip`, if known.
+#[instrument(skip(services, req), fields(method = %req.method(), path = %req.uri().path()))]
+pub async fn route(
+    services: Services,
+    client_ip: Option<IpAddr>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let Services {
+        store,
+        auth,
+        limiter,
+        config,
+        runner_token,
+        max_body_size,
+    } = services;
+    let id = req.uri().path().trim_start_matches('/').to_owned();
+
+    // Results of a testrun
+    if let Some((id, "results")) = id.split_once('/') {
+        let res = match *req.method() {
+            Method::PUT if !has_token(req.headers(), runner_token.as_deref()) => {
+                warn!("Rejected results from a caller that isn't a runner");
+                api::response(
+                    StatusCode::FORBIDDEN,
+                    json!({"message": "Only runners can write results"}).to_string(),
+                )
+            }
+            Method::PUT => {
+                let (parts, body) = req.into_parts();
+                match read_body(&parts.headers, body, max_body_size).await {
+                    Ok(body) => api::put_results(store.as_ref(), id, &parts.headers, &body).await,
+                    Err(res) => res,
+                }
+            }
+            _ => method_not_allowed("PUT"),
+        };
+        return Ok(res.map(Body::from));
+    }
+
+    let caller = match auth.authenticate(req.headers()) {
+        Ok(caller) => caller,
+        Err(err) => return Ok(api::unauthorized(err).map(Body::from)),
+    };
+
+    // Collection routes
+    if id.is_empty() {
+        let res = match *req.method() {
+            Method::GET => {
+                let params = form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+                    .into_owned()
+                    .collect();
+                api::list_testruns(store.as_ref(), &caller, &params).await
+            }
+            Method::POST => {
+                let path = req.uri().path().to_owned();
+                let (parts, body) = req.into_parts();
+                match read_body(&parts.headers, body, max_body_size).await {
+                    Ok(body) => {
+                        let submission = Submission {
+                            caller: &caller,
+                            client_ip,
+                            headers: &parts.headers,
+                            body: &body,
+                        };
+                        api::create_testrun(
+                            store.as_ref(),
+                            limiter.as_ref(),
+                            &config,
+                            submission,
+                            &path,
+                        )
+                        .await
+                    }
+                    Err(res) => res,
+                }
+            }
+            _ => method_not_allowed("GET, POST"),
+        };
+        return Ok(res.map(Body::from));
+    }
+
+    // Actions on a testrun
+    if let Some((id, action)) = id.split_once('/') {
+        let res = match (action, req.method()) {
+            ("cancel", &Method::POST) => api::cancel_testrun(store.as_ref(), &caller, id).await,
+            ("cancel", _) => method_not_allowed("POST"),
+            _ => not_found(),
+        };
+        return Ok(res.map(Body::from));
+    }
+
+    let res = match *req.method() {
+        Method::GET => api::get_testrun(store.as_ref(), &caller, &id).await,
+        Method::PUT => {
+            let (parts, body) = req.into_parts();
+            match read_body(&parts.headers, body, max_body_size).await {
+                Ok(body) => {
+                    let submission = Submission {
+                        caller: &caller,
+                        client_ip,
+                        headers: &parts.headers,
+                        body: &body,
+                    };
+                    api::put_testrun(store.as_ref(), limiter.as_ref(), &config, submission, &id)
+                        .await
+                }
+                Err(res) => res,
+            }
+        }
+        Method::DELETE => api::delete_testrun(store.as_ref(), &caller, &id).await,
+        _ => method_not_allowed("GET, PUT, DELETE"),
+    };
+
+    Ok(res.map(Body::from))
+}
+
+/// Whether the request holds `token` as a bearer token
+///
+/// Without `token`, no request holds it.
+fn has_token(headers: &http::HeaderMap, token: Option<&str>) -> bool {
+    let token = match token {
+        Some(token) if !token.is_empty() => token,
+        _ => return false,
+    };
+
+    headers
+        .get(http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()))
+}
+
+/// Compare secrets in a time that doesn't depend on where they differ
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// 404 Not Found response for an unknown path
+fn not_found() -> http::Response<String> {
+    warn!("No route for path");
+    api::response(
+        StatusCode::NOT_FOUND,
+        json!({"message": "Not found"}).to_string(),
+    )
+}
+
+/// Read a request body of at most `max_size` bytes
+///
+/// Bodies declaring a larger `Content-Length` are rejected before reading
+/// them, others as soon as they grow past `max_size`.
+async fn read_body(
+    headers: &http::HeaderMap,
+    mut body: Body,
+    max_size: usize,
+) -> Result<Vec<u8>, http::Response<String>> {
+    let declared = headers
+        .get(http::header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if declared.is_some_and(|size| size > max_size as u64) {
+        return Err(payload_too_large(max_size));
+    }
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(read_error)?;
+        if bytes.len() + chunk.len() > max_size {
+            return Err(payload_too_large(max_size));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(bytes)
+}
+
+/// 400 Bad Request response for a body that couldn't be read
+fn read_error(err: hyper::Error) -> http::Response<String> {
+    warn!("Failed to read request body: {}", err);
+    api::response(
+        StatusCode::BAD_REQUEST,
+        json!({"message": "Failed to read request body"}).to_string(),
+    )
+}
+
+/// 413 Payload Too Large response for a body over `max_size` bytes
+fn payload_too_large(max_size: usize) -> http::Response<String> {
+    warn!("Rejected a request body over {} bytes", max_size);
+    api::response(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        json!({"message": format!("Request body is larger than {} bytes", max_size)}).to_string(),
+    )
+}
+
+/// 405 Method Not Allowed response listing the allowed methods
+fn method_not_allowed(allow: &'static str) -> http::Response<String> {
+    let mut res = api::response(
+        StatusCode::METHOD_NOT_ALLOWED,
+        json!({"message": "Method not allowed"}).to_string(),
+    );
+    res.headers_mut()
+        .insert(http::header::ALLOW, http::HeaderValue::from_static(allow));
+    res
+}
+
+/// Purge expired testruns every `period`, forever
+///
+/// Errors are logged and the purge is tried again at the next period.
+pub async fn purge_periodically(store: Arc<dyn Store>, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+        match domain::testrun::purge_testruns(store.as_ref()).await {
+            Ok(purged) => info!("Purged {} expired testruns", purged),
+            Err(err) => error!("Failed to purge expired testruns: {}", err),
+        }
+    }
+}
+
+/// Resolve once the process is asked to stop, with Ctrl+C or SIGTERM
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    info!("Shutting down");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        domain::{idempotency, ratelimit::SubmissionPolicy},
+        entrypoints::auth::{
+            tests::{get_headers, get_token, SECRET},
+            JwtAuthenticator, NoAuthenticator,
+        },
+        error::Error,
+        model::{TestRun, TestRunStatus, TestRunSummary},
+        ratelimit::{MemoryRateLimiter, TokenBucket},
+        store::{
+            conformance::get_testrun_with_status, IdempotencyRecord, MemoryStore, StoreDelete,
+            StoreGet, StoreIdempotency, StorePurge, StorePut, StoreQuery, TestRunPage,
+            TestRunQuery,
+        },
+    };
+    use async_trait::async_trait;
+    use chrono::{DateTime, Utc};
+
+    /// Services around `store`, without authentication or rate limiting
+    fn get_services(store: Arc<dyn Store>) -> Services {
+        Services {
+            store,
+            auth: Arc::new(NoAuthenticator),
+            limiter: Arc::new(MemoryRateLimiter::new()),
+            config: ApiConfig::default(),
+            runner_token: None,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+
+    /// Services around `store`, limiting submissions with `policy`
+    fn get_limited_services(store: Arc<dyn Store>, policy: SubmissionPolicy) -> Services {
+        Services {
+            config: ApiConfig {
+                policy,
+                ..Default::default()
+            },
+            ..get_services(store)
+        }
+    }
+
+    /// Services around `store`, authenticating JWTs signed with `SECRET`
+    fn get_jwt_services(store: Arc<dyn Store>) -> Services {
+        Services {
+            auth: Arc::new(JwtAuthenticator::new().with_hs256_secret(SECRET)),
+            ..get_services(store)
+        }
+    }
+
+    async fn send(store: Arc<dyn Store>, method: Method, path: &str, body: Body) -> Response<Body> {
+        let req = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(body)
+            .unwrap();
+
+        route(get_services(store), None, req).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_get() {
+        let store = Arc::new(MemoryStore::new());
+        store
+            .put(&get_testrun_with_status(TestRunStatus::Queued))
+            .await
+            .unwrap();
+
+        let res = send(store, Method::GET, "/1", Body::empty()).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let testrun: TestRun = serde_json::from_slice(&body).unwrap();
+        assert_eq!(testrun, get_testrun_with_status(TestRunStatus::Queued));
+    }
+
+    #[tokio::test]
+    async fn test_put_then_get() {
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let body = json!(get_testrun_with_status(TestRunStatus::Queued)).to_string();
+
+        let res = send(store.clone(), Method::PUT, "/1", Body::from(body)).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+
+        let res = send(store, Method::GET, "/1", Body::empty()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_list() {
+        let store = Arc::new(MemoryStore::new());
+        store
+            .put(&get_testrun_with_status(TestRunStatus::Queued))
+            .await
+            .unwrap();
+
+        let res = send(
+            store,
+            Method::GET,
+            "/?user=user&language=python",
+            Body::empty(),
+        )
+        .await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body["testruns"],
+            json!([TestRunSummary::from(&get_testrun_with_status(
+                TestRunStatus::Queued
+            ))])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_then_get() {
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let body = json!({"language": "python", "files": {"main.py": "print('hello')"}});
+
+        let res = send(
+            store.clone(),
+            Method::POST,
+            "/",
+            Body::from(body.to_string()),
+        )
+        .await;
+
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let location = res.headers()[http::header::LOCATION]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let created: TestRun = serde_json::from_slice(&body).unwrap();
+        assert_eq!(location, format!("/{}", created.id));
+
+        let res = send(store, Method::GET, &location, Body::empty()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    async fn send_with_key(
+        store: Arc<dyn Store>,
+        method: Method,
+        path: &str,
+        key: &str,
+        body: String,
+    ) -> Response<Body> {
+        let req = Request::builder()
+            .method(method)
+            .uri(path)
+            .header("Idempotency-Key", key)
+            .body(Body::from(body))
+            .unwrap();
+
+        route(get_services(store), None, req).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_put_idempotency_key() {
+        // GIVEN a put that was already handled
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let body = json!(get_testrun_with_status(TestRunStatus::Queued)).to_string();
+        let first = send_with_key(store.clone(), Method::PUT, "/1", "key", body.clone()).await;
+
+        // WHEN it is retried with the same key
+        let retry = send_with_key(store.clone(), Method::PUT, "/1", "key", body).await;
+
+        // THEN the first response is replayed without writing again
+        assert_eq!(first.status(), StatusCode::CREATED);
+        assert!(first.headers().get("idempotent-replayed").is_none());
+        assert_eq!(retry.status(), StatusCode::CREATED);
+        assert_eq!(retry.headers()["idempotent-replayed"], "true");
+        assert_eq!(retry.headers()[http::header::ETAG], r#""1""#);
+        assert_eq!(store.get("1").await.unwrap().unwrap().version, 1);
+    }
+
+    #[tokio::test]
+    async fn test_put_idempotency_key_mismatch() {
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let body = json!(get_testrun_with_status(TestRunStatus::Queued)).to_string();
+        send_with_key(store.clone(), Method::PUT, "/1", "key", body).await;
+
+        let other = json!(TestRun {
+            status: TestRunStatus::Running,
+            ..get_testrun_with_status(TestRunStatus::Queued)
+        })
+        .to_string();
+        let res = send_with_key(store.clone(), Method::PUT, "/1", "key", other).await;
+
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(store.get("1").await.unwrap().unwrap().version, 1);
+    }
+
+    #[tokio::test]
+    async fn test_put_idempotency_key_in_progress() {
+        // GIVEN a put whose key is reserved by the same request, still running
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let body = json!(get_testrun_with_status(TestRunStatus::Queued)).to_string();
+        let request_hash = idempotency::request_hash("PUT", "/1", None, body.as_bytes());
+        let lookup = idempotency::reserve(store.as_ref(), "key", &request_hash, Utc::now()).await;
+        assert_eq!(lookup.unwrap(), idempotency::Lookup::New);
+
+        // WHEN a duplicate is sent concurrently
+        let res = send_with_key(store.clone(), Method::PUT, "/1", "key", body.clone()).await;
+
+        // THEN it is refused without writing
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        assert_eq!(store.get("1").await.unwrap(), None);
+
+        // AND once the first request gave up the key, a retry is handled
+        idempotency::release(store.as_ref(), "key", &request_hash)
+            .await
+            .unwrap();
+        let res = send_with_key(store.clone(), Method::PUT, "/1", "key", body).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert!(res.headers().get("idempotent-replayed").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_idempotency_key() {
+        // GIVEN concurrent creates with the same key
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let body = json!({"language": "python"}).to_string();
+
+        // WHEN they are all sent at once
+        let responses = futures::future::join_all(
+            (0..10).map(|_| send_with_key(store.clone(), Method::POST, "/", "key", body.clone())),
+        )
+        .await;
+
+        // THEN a single testrun is created, and the others are refused or
+        // replayed
+        let created = responses
+            .iter()
+            .filter(|res| {
+                res.status() == StatusCode::CREATED
+                    && res.headers().get("idempotent-replayed").is_none()
+            })
+            .count();
+        assert_eq!(created, 1);
+        assert!(responses
+            .iter()
+            .all(|res| matches!(res.status(), StatusCode::CREATED | StatusCode::CONFLICT)));
+        let query = TestRunQuery {
+            limit: 10,
+            ..Default::default()
+        };
+        assert_eq!(store.query(&query).await.unwrap().testruns.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_create_idempotency_key() {
+        // GIVEN a create that was already handled
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let body = json!({"language": "python"}).to_string();
+        let first = send_with_key(store.clone(), Method::POST, "/", "key", body.clone()).await;
+
+        // WHEN it is retried with the same key
+        let retry = send_with_key(store.clone(), Method::POST, "/", "key", body).await;
+
+        // THEN no other testrun is created
+        assert_eq!(retry.status(), StatusCode::CREATED);
+        assert_eq!(
+            retry.headers()[http::header::LOCATION],
+            first.headers()[http::header::LOCATION]
+        );
+        let query = TestRunQuery {
+            limit: 10,
+            ..Default::default()
+        };
+        let page = store.query(&query).await.unwrap();
+        assert_eq!(page.testruns.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_idempotency_key() {
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let body = json!(get_testrun_with_status(TestRunStatus::Queued)).to_string();
+
+        let res = send_with_key(store.clone(), Method::PUT, "/1", "", body).await;
+
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(store.get("1").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_list_method_not_allowed() {
+        let store = Arc::new(MemoryStore::new());
+
+        let res = send(store, Method::PUT, "/", Body::empty()).await;
+
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers()[http::header::ALLOW], "GET, POST");
+    }
+
+    #[tokio::test]
+    async fn test_unknown_path() {
+        let store = Arc::new(MemoryStore::new());
+
+        let res = send(store, Method::GET, "/1/tests", Body::empty()).await;
+
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_method_not_allowed() {
+        let store = Arc::new(MemoryStore::new());
+
+        let res = send(store, Method::PATCH, "/1", Body::empty()).await;
+
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers()[http::header::ALLOW], "GET, PUT, DELETE");
+    }
+
+    #[tokio::test]
+    async fn test_delete() {
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        store
+            .put(&get_testrun_with_status(TestRunStatus::Queued))
+            .await
+            .unwrap();
+
+        let res = send(store.clone(), Method::DELETE, "/1", Body::empty()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = send(store, Method::GET, "/1", Body::empty()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        store
+            .put(&get_testrun_with_status(TestRunStatus::Queued))
+            .await
+            .unwrap();
+
+        let res = send(store.clone(), Method::POST, "/1/cancel", Body::empty()).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let testrun: TestRun = serde_json::from_slice(&body).unwrap();
+        assert_eq!(testrun.status, TestRunStatus::Cancelled);
+        assert_eq!(testrun.version, 2);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_method_not_allowed() {
+        let store = Arc::new(MemoryStore::new());
+
+        let res = send(store, Method::GET, "/1/cancel", Body::empty()).await;
+
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers()[http::header::ALLOW], "POST");
+    }
+
+    #[test]
+    fn test_has_token() {
+        let mut headers = http::HeaderMap::new();
+        assert!(!has_token(&headers, Some("secret")));
+
+        headers.insert(
+            http::header::AUTHORIZATION,
+            "Bearer secret".parse().unwrap(),
+        );
+        assert!(has_token(&headers, Some("secret")));
+        assert!(!has_token(&headers, Some("secret2")));
+        assert!(!has_token(&headers, Some("")));
+        assert!(!has_token(&headers, None));
+    }
+
+    #[tokio::test]
+    async fn test_put_results() {
+        // GIVEN a queued testrun and a runner token
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        store
+            .put(&get_testrun_with_status(TestRunStatus::Queued))
+            .await
+            .unwrap();
+        let results = json!({"status": "running", "tests": []}).to_string();
+        let request = |token: &str| {
+            Request::builder()
+                .method(Method::PUT)
+                .uri("/1/results")
+                .header("Authorization", format!("Bearer {}", token))
+                .body(Body::from(results.clone()))
+                .unwrap()
+        };
+
+        // WHEN writing results with a wrong and then the right token
+        let services = Services {
+            runner_token: Some("runner-secret".to_owned()),
+            ..get_jwt_services(store.clone())
+        };
+        let rejected = route(services.clone(), None, request("client"))
+            .await
+            .unwrap();
+        let accepted = route(services, None, request("runner-secret"))
+            .await
+            .unwrap();
+
+        // THEN only the runner can write them
+        assert_eq!(rejected.status(), StatusCode::FORBIDDEN);
+        assert_eq!(accepted.status(), StatusCode::OK);
+        let stored = store.get("1").await.unwrap().unwrap();
+        assert_eq!(stored.status, TestRunStatus::Running);
+        assert_eq!(stored.version, 2);
+    }
+
+    #[tokio::test]
+    async fn test_jwt_owner() {
+        // GIVEN JWT authentication and a testrun created by 'user'
+        let services = get_jwt_services(Arc::new(MemoryStore::new()));
+        let request = |method: Method, path: &str, user: &str| {
+            let mut req = Request::builder()
+                .method(method)
+                .uri(path)
+                .body(Body::from(json!({"language": "python"}).to_string()))
+                .unwrap();
+            req.headers_mut().extend(get_headers(&get_token(user)));
+            req
+        };
+        let res = route(services.clone(), None, request(Method::POST, "/", "user"))
+            .await
+            .unwrap();
+        let location = res.headers()[http::header::LOCATION]
+            .to_str()
+            .unwrap()
+            .to_owned();
+
+        // WHEN another user accesses it
+        let get = route(
+            services.clone(),
+            None,
+            request(Method::GET, &location, "other"),
+        )
+        .await
+        .unwrap();
+        let put = route(
+            services.clone(),
+            None,
+            request(Method::PUT, &location, "other"),
+        )
+        .await
+        .unwrap();
+        let delete = route(
+            services.clone(),
+            None,
+            request(Method::DELETE, &location, "other"),
+        )
+        .await
+        .unwrap();
+        let list = route(
+            services.clone(),
+            None,
+            request(Method::GET, "/?limit=10", "other"),
+        )
+        .await
+        .unwrap();
+
+        // THEN it is forbidden, and not listed
+        assert_eq!(get.status(), StatusCode::FORBIDDEN);
+        assert_eq!(put.status(), StatusCode::FORBIDDEN);
+        assert_eq!(delete.status(), StatusCode::FORBIDDEN);
+        assert_eq!(list.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(list.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["testruns"], json!([]));
+
+        // AND the owner can still get it
+        let res = route(services, None, request(Method::GET, &location, "user"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    /// Policy allowing `per_minute` submissions a minute per IP
+    fn per_ip(per_minute: u32) -> SubmissionPolicy {
+        SubmissionPolicy {
+            per_ip: Some(TokenBucket::per_minute(per_minute)),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_per_ip() {
+        // GIVEN a limit of one submission a minute per IP
+        let services = get_limited_services(Arc::new(MemoryStore::new()), per_ip(1));
+        let request = || {
+            Request::builder()
+                .method(Method::POST)
+                .uri("/")
+                .body(Body::from(json!({"language": "python"}).to_string()))
+                .unwrap()
+        };
+        let ip = Some("192.0.2.1".parse().unwrap());
+
+        // WHEN a client submits twice in a row
+        let first = route(services.clone(), ip, request()).await.unwrap();
+        let second = route(services.clone(), ip, request()).await.unwrap();
+        let other = route(services, Some("192.0.2.2".parse().unwrap()), request())
+            .await
+            .unwrap();
+
+        // THEN the second submission has to wait
+        assert_eq!(first.status(), StatusCode::CREATED);
+        assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(second.headers()[http::header::RETRY_AFTER], "60");
+        assert_eq!(other.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_before_parsing() {
+        // GIVEN a client that used its bucket
+        let services = get_limited_services(Arc::new(MemoryStore::new()), per_ip(1));
+        let request = |body: &str| {
+            Request::builder()
+                .method(Method::POST)
+                .uri("/")
+                .body(Body::from(body.to_owned()))
+                .unwrap()
+        };
+        let ip = Some("192.0.2.3".parse().unwrap());
+        let first = route(services.clone(), ip, request(r#"{"language": "python"}"#))
+            .await
+            .unwrap();
+        assert_eq!(first.status(), StatusCode::CREATED);
+
+        // WHEN it sends a body that can't be parsed
+        let res = route(services, ip, request("not json")).await.unwrap();
+
+        // THEN it is throttled before the body is parsed
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_quota_counts_valid_submissions() {
+        // GIVEN a daily quota of two submissions per challenge
+        let policy = SubmissionPolicy {
+            daily_quota: Some(2),
+            ..Default::default()
+        };
+        let services = get_limited_services(Arc::new(MemoryStore::new()), policy);
+        let request = |method: Method, path: &str, testrun: serde_json::Value| {
+            Request::builder()
+                .method(method)
+                .uri(path)
+                .body(Body::from(testrun.to_string()))
+                .unwrap()
+        };
+        let testrun = json!({
+            "userId": "quota-user",
+            "challengeId": "quota-challenge",
+            "language": "python"
+        });
+
+        // WHEN a user sends a submission rejected by its If-Match, then valid
+        // ones
+        let mut conditional = request(Method::PUT, "/1", testrun.clone());
+        conditional
+            .headers_mut()
+            .insert(http::header::IF_MATCH, "\"1\"".parse().unwrap());
+        let rejected = route(services.clone(), None, conditional).await.unwrap();
+        let first = route(
+            services.clone(),
+            None,
+            request(Method::POST, "/", testrun.clone()),
+        )
+        .await
+        .unwrap();
+        let second = route(
+            services.clone(),
+            None,
+            request(Method::POST, "/", testrun.clone()),
+        )
+        .await
+        .unwrap();
+        let third = route(services, None, request(Method::POST, "/", testrun))
+            .await
+            .unwrap();
+
+        // THEN only the valid ones count against the quota
+        assert_eq!(rejected.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(first.status(), StatusCode::CREATED);
+        assert_eq!(second.status(), StatusCode::CREATED);
+        assert_eq!(third.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    /// Store where another writer updates an existing testrun right before
+    /// each put, so that puts of it always conflict
+    struct RacingStore(MemoryStore);
+
+    impl Store for RacingStore {}
+
+    #[async_trait]
+    impl StoreGet for RacingStore {
+        async fn get(&self, id: &str) -> Result<Option<TestRun>, Error> {
+            self.0.get(id).await
+        }
+    }
+
+    #[async_trait]
+    impl StorePut for RacingStore {
+        async fn put(&self, testrun: &TestRun) -> Result<(), Error> {
+            if let Some(current) = self.0.get(&testrun.id).await? {
+                let version = current.version + 1;
+                self.0.put(&TestRun { version, ..current }).await?;
+            }
+            self.0.put(testrun).await
+        }
+    }
+
+    #[async_trait]
+    impl StoreDelete for RacingStore {
+        async fn delete(&self, id: &str) -> Result<(), Error> {
+            self.0.delete(id).await
+        }
+    }
+
+    #[async_trait]
+    impl StoreQuery for RacingStore {
+        async fn query(&self, query: &TestRunQuery) -> Result<TestRunPage, Error> {
+            self.0.query(query).await
+        }
+    }
+
+    #[async_trait]
+    impl StorePurge for RacingStore {
+        async fn purge(&self, now: DateTime<Utc>) -> Result<usize, Error> {
+            self.0.purge(now).await
+        }
+    }
+
+    #[async_trait]
+    impl StoreIdempotency for RacingStore {
+        async fn get_idempotency(
+            &self,
+            key: &str,
+            now: DateTime<Utc>,
+        ) -> Result<Option<IdempotencyRecord>, Error> {
+            self.0.get_idempotency(key, now).await
+        }
+
+        async fn put_idempotency(&self, record: &IdempotencyRecord) -> Result<(), Error> {
+            self.0.put_idempotency(record).await
+        }
+
+        async fn update_idempotency(&self, record: &IdempotencyRecord) -> Result<(), Error> {
+            self.0.update_idempotency(record).await
+        }
+
+        async fn delete_idempotency(&self, key: &str, request_hash: &str) -> Result<(), Error> {
+            self.0.delete_idempotency(key, request_hash).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_quota_refunded_on_conflict() {
+        // GIVEN a daily quota of one submission per challenge, and a testrun
+        // that another writer keeps updating
+        let store = RacingStore(MemoryStore::new());
+        store
+            .0
+            .put(&get_testrun_with_status(TestRunStatus::Queued))
+            .await
+            .unwrap();
+        let policy = SubmissionPolicy {
+            daily_quota: Some(1),
+            ..Default::default()
+        };
+        let services = get_limited_services(Arc::new(store), policy);
+        let request = |method: Method, path: &str| {
+            Request::builder()
+                .method(method)
+                .uri(path)
+                .body(Body::from(
+                    json!(get_testrun_with_status(TestRunStatus::Queued)).to_string(),
+                ))
+                .unwrap()
+        };
+
+        // WHEN the user's put of it loses the race
+        let conflict = route(services.clone(), None, request(Method::PUT, "/1"))
+            .await
+            .unwrap();
+        assert_eq!(conflict.status(), StatusCode::CONFLICT);
+
+        // THEN the quota is unchanged, and still applies to later submissions
+        let created = route(services.clone(), None, request(Method::POST, "/"))
+            .await
+            .unwrap();
+        let limited = route(services, None, request(Method::POST, "/"))
+            .await
+            .unwrap();
+        assert_eq!(created.status(), StatusCode::CREATED);
+        assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_body_too_large() {
+        // GIVEN a server accepting bodies of up to 16 bytes
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let services = Services {
+            max_body_size: 16,
+            ..get_services(store.clone())
+        };
+        let body = json!(get_testrun_with_status(TestRunStatus::Queued)).to_string();
+
+        // WHEN sending a larger body, with and without a Content-Length
+        let declared = Request::builder()
+            .method(Method::PUT)
+            .uri("/1")
+            .header(http::header::CONTENT_LENGTH, body.len())
+            .body(Body::from(body.clone()))
+            .unwrap();
+        let (mut sender, streamed) = Body::channel();
+        tokio::spawn(async move {
+            for chunk in body.as_bytes().chunks(8) {
+                if sender.send_data(chunk.to_vec().into()).await.is_err() {
+                    break;
+                }
+            }
+        });
+        let streamed = Request::builder()
+            .method(Method::PUT)
+            .uri("/1")
+            .body(streamed)
+            .unwrap();
+        let declared = route(services.clone(), None, declared).await.unwrap();
+        let streamed = route(services, None, streamed).await.unwrap();
+
+        // THEN both are rejected without writing anything
+        assert_eq!(declared.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(streamed.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(store.get("1").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_jwt_missing_token() {
+        let services = get_jwt_services(Arc::new(MemoryStore::new()));
+        let req = Request::builder().uri("/1").body(Body::empty()).unwrap();
+
+        let res = route(services, None, req).await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(res.headers()[http::header::WWW_AUTHENTICATE], "Bearer");
+    }
+
+    #[tokio::test]
+    async fn test_serve_graceful_shutdown() {
+        use std::io::{Read, Write};
+
+        // GIVEN a running server
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve(get_services(store.clone()), addr, async {
+            stopped.await.ok();
+        }));
+
+        // WHEN it is asked to stop while it reads the body of a request
+        let (reading, read) = tokio::sync::oneshot::channel();
+        let (send_body, body_sent) = std::sync::mpsc::channel();
+        let body = json!(get_testrun_with_status(TestRunStatus::Queued)).to_string();
+        let client = tokio::task::spawn_blocking(move || {
+            let mut stream = loop {
+                match std::net::TcpStream::connect(addr) {
+                    Ok(stream) => break stream,
+                    Err(_) => std::thread::sleep(Duration::from_millis(10)),
+                }
+            };
+            write!(
+                stream,
+                "PUT /1 HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: {}\r\n\r\n",
+                body.len()
+            )
+            .unwrap();
+
+            // The server asks for the body once the handler reads it
+            let mut continued = [0; 25];
+            stream.read_exact(&mut continued).unwrap();
+            assert_eq!(&continued, b"HTTP/1.1 100 Continue\r\n\r\n");
+            reading.send(()).unwrap();
+
+            body_sent.recv().unwrap();
+            stream.write_all(body.as_bytes()).unwrap();
+            let mut res = String::new();
+            stream.read_to_string(&mut res).unwrap();
+            res
+        });
+        read.await.unwrap();
+        stop.send(()).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // THEN it waits for the request to finish before stopping
+        assert!(!server.is_finished());
+        send_body.send(()).unwrap();
+        let res = client.await.unwrap();
+        assert!(res.starts_with("HTTP/1.1 201"), "{}", res);
+        server.await.unwrap().unwrap();
+        assert!(store.get("1").await.unwrap().is_some());
+    }
+}
@@ -7,8 +7,20 @@ use std::fmt;
 pub enum Error {
     InitError(&'static str),
     ClientError(&'static str),
+    /// A conditional write failed because the stored testrun changed
+    ConflictError(&'static str),
+    /// A client precondition, such as `If-Match`, does not hold
+    PreconditionError(&'static str),
+    /// The caller could not be authenticated
+    AuthError(&'static str),
     InternalError(&'static str),
     SdkError(String),
+    CodecError(CodecError),
+    DatabaseError(String),
+    /// A blob could not be read from or written to the blob store
+    BlobError(String),
+    /// Events were rejected by the event bus
+    EventBusError(String),
 }
 
 impl fmt::Display for Error {
@@ -16,14 +28,113 @@ impl fmt::Display for Error {
         match self {
             Error::InitError(msg) => write!(f, "InitError: {}", msg),
             Error::ClientError(msg) => write!(f, "ClientError: {}", msg),
+            Error::ConflictError(msg) => write!(f, "ConflictError: {}", msg),
+            Error::PreconditionError(msg) => write!(f, "PreconditionError: {}", msg),
+            Error::AuthError(msg) => write!(f, "AuthError: {}", msg),
             Error::InternalError(msg) => write!(f, "InternalError: {}", msg),
             Error::SdkError(err) => write!(f, "SdkError: {}", err),
+            Error::CodecError(err) => write!(f, "CodecError: {}", err),
+            Error::DatabaseError(err) => write!(f, "DatabaseError: {}", err),
+            Error::BlobError(err) => write!(f, "BlobError: {}", err),
+            Error::EventBusError(err) => write!(f, "EventBusError: {}", err),
         }
     }
 }
 
 impl error::Error for Error {}
 
+/// Errors raised while converting between a `TestRun` and a stored item
+#[derive(Debug, PartialEq)]
+pub enum CodecError {
+    /// A required attribute is not present in the item
+    MissingAttribute(String),
+    /// An attribute is present but holds a value of the wrong type
+    InvalidType {
+        attribute: String,
+        expected: &'static str,
+    },
+    /// An attribute holds a value that is not recognised
+    InvalidValue { attribute: String, value: String },
+    /// A compressed attribute cannot be decompressed or parsed
+    InvalidPayload { attribute: String, reason: String },
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            CodecError::MissingAttribute(attribute) => {
+                write!(f, "missing attribute '{}'", attribute)
+            }
+            CodecError::InvalidType {
+                attribute,
+                expected,
+            } => write!(f, "attribute '{}' is not of type {}", attribute, expected),
+            CodecError::InvalidValue { attribute, value } => {
+                write!(f, "attribute '{}' has invalid value '{}'", attribute, value)
+            }
+            CodecError::InvalidPayload { attribute, reason } => {
+                write!(
+                    f,
+                    "attribute '{}' has an invalid payload: {}",
+                    attribute, reason
+                )
+            }
+        }
+    }
+}
+
+impl error::Error for CodecError {}
+
+/// Reasons an uploaded archive is refused
+#[derive(Debug, PartialEq)]
+pub enum ArchiveError {
+    /// The archive is not valid base64
+    InvalidEncoding,
+    /// The archive format is not supported
+    UnknownFormat,
+    /// The archive could not be read
+    InvalidArchive(String),
+    /// The archive holds more entries than allowed
+    TooManyEntries { limit: usize },
+    /// A single file is larger than allowed once uncompressed
+    FileTooLarge { path: String, limit: u64 },
+    /// All files together are larger than allowed once uncompressed
+    ArchiveTooLarge { limit: u64 },
+    /// A path is absolute or escapes the archive root
+    UnsafePath(String),
+    /// An entry is a symbolic link
+    Symlink(String),
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            ArchiveError::InvalidEncoding => write!(f, "archive is not valid URL-safe base64"),
+            ArchiveError::UnknownFormat => write!(f, "archive format is not supported"),
+            ArchiveError::InvalidArchive(err) => write!(f, "archive could not be read: {}", err),
+            ArchiveError::TooManyEntries { limit } => {
+                write!(f, "archive has more than {} entries", limit)
+            }
+            ArchiveError::FileTooLarge { path, limit } => {
+                write!(f, "file '{}' is larger than {} bytes", path, limit)
+            }
+            ArchiveError::ArchiveTooLarge { limit } => {
+                write!(f, "archive is larger than {} bytes uncompressed", limit)
+            }
+            ArchiveError::UnsafePath(path) => write!(f, "path '{}' is not allowed", path),
+            ArchiveError::Symlink(path) => write!(f, "'{}' is a symbolic link", path),
+        }
+    }
+}
+
+impl error::Error for ArchiveError {}
+
+impl From<CodecError> for Error {
+    fn from(value: CodecError) -> Error {
+        Error::CodecError(value)
+    }
+}
+
 impl From<std::num::ParseFloatError> for Error {
     fn from(_: std::num::ParseFloatError) -> Error {
         Error::InternalError("Unable to parse float")
@@ -44,3 +155,15 @@ where
         Error::SdkError(format!("{}", value))
     }
 }
+
+impl From<diesel::result::Error> for Error {
+    fn from(value: diesel::result::Error) -> Error {
+        Error::DatabaseError(format!("{}", value))
+    }
+}
+
+impl From<diesel::r2d2::PoolError> for Error {
+    fn from(value: diesel::r2d2::PoolError) -> Error {
+        Error::DatabaseError(format!("{}", value))
+    }
+}
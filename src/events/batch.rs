@@ -0,0 +1,290 @@
+//! # Batched message buses
+//!
+//! Sending logic shared by EventBridge, SNS and SQS, which all accept batches
+//! of up to ten entries and report failures for each entry of a batch.
+
+use async_trait::async_trait;
+use futures::future::join_all;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+use tracing::warn;
+
+use super::{
+    backoff, detail_type, send_in_order, EventDetail, FailedEvent, SlimEvent, REQUEST_FAILED,
+};
+use crate::{error::Error, model::Event};
+
+/// Entries a batch holds
+const MAX_ENTRIES: usize = 10;
+
+/// Size of the entries of a batch, the same for all three services
+const MAX_BATCH_SIZE: usize = 256 * 1024;
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 4;
+const DEFAULT_BASE_DELAY: Duration = Duration::from_millis(100);
+const MAX_DELAY: Duration = Duration::from_secs(5);
+
+/// SNS or SQS message carrying an event
+#[derive(Clone, Debug)]
+pub(crate) struct Message {
+    /// Type of the event, sent as the `type` message attribute
+    pub kind: &'static str,
+    pub body: String,
+    /// Id of the testrun, which orders the messages of FIFO topics and queues
+    pub group_id: String,
+}
+
+impl Message {
+    pub fn new(event: &Event, detail: EventDetail) -> Message {
+        let slim = || serde_json::to_string(&SlimEvent::from(event)).unwrap();
+        let mut message = Message {
+            kind: detail_type(event),
+            body: match detail {
+                EventDetail::Full => serde_json::to_string(event).unwrap(),
+                EventDetail::Slim => slim(),
+            },
+            group_id: event.id().to_owned(),
+        };
+        if message.size() > MAX_BATCH_SIZE {
+            message.body = slim();
+        }
+
+        message
+    }
+
+    /// Deduplication id of FIFO topics and queues, so that resending the
+    /// same event has no effect
+    pub fn deduplication_id(&self) -> String {
+        format!("{:x}", Sha256::digest(self.body.as_bytes()))
+    }
+
+    /// Size of the message, as counted against `MAX_BATCH_SIZE`
+    pub fn size(&self) -> usize {
+        self.body.len() + "type".len() + "String".len() + self.kind.len()
+    }
+}
+
+/// Entry of a batch that could not be sent
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct EntryFailure {
+    /// Position of the entry in the batch
+    pub position: usize,
+    pub code: String,
+    pub message: Option<String>,
+    /// Whether the entry itself is at fault, rather than the service
+    pub sender_fault: bool,
+}
+
+/// Failures of a batch of `len` entries, given the positions reported as
+/// `successful` and the `failures` reported with a valid position
+///
+/// Entries reported in neither, such as those of failures whose id could not
+/// be parsed, may not have been delivered, so they are failed to be retried.
+pub(crate) fn with_missing(
+    len: usize,
+    successful: &[usize],
+    mut failures: Vec<EntryFailure>,
+) -> Vec<EntryFailure> {
+    let reported = successful
+        .iter()
+        .copied()
+        .chain(failures.iter().map(|failure| failure.position))
+        .collect::<HashSet<_>>();
+    let missing = (0..len)
+        .filter(|position| !reported.contains(position))
+        .map(|position| EntryFailure {
+            position,
+            code: REQUEST_FAILED.to_owned(),
+            message: Some("No result for the entry".to_owned()),
+            sender_fault: false,
+        })
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        warn!("{} entries of the batch have no result", missing.len());
+    }
+    failures.extend(missing);
+
+    failures
+}
+
+/// Service accepting batches of entries
+#[async_trait]
+pub(crate) trait Publisher: Send + Sync {
+    /// Entry carrying a single event
+    type Entry: Clone + Send + Sync;
+
+    /// Entry carrying `event`, slim if the full entry would not fit in a batch
+    fn entry(&self, event: &Event, detail: EventDetail) -> Self::Entry;
+
+    /// Size of an entry, as counted against `MAX_BATCH_SIZE`
+    fn size(entry: &Self::Entry) -> usize;
+
+    /// Publish a batch, identifying each entry by its position
+    ///
+    /// Returns the entries that could not be published, including those
+    /// without a result, see `with_missing`.
+    async fn publish(&self, entries: &[Self::Entry]) -> Result<Vec<EntryFailure>, Error>;
+}
+
+/// Settings of a `Publisher`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct BatchSettings {
+    pub detail: EventDetail,
+    /// Attempts at sending an entry that failed without being at fault
+    pub max_attempts: u32,
+    /// Base delay of the jittered backoff between attempts
+    pub base_delay: Duration,
+}
+
+impl Default for BatchSettings {
+    fn default() -> Self {
+        BatchSettings {
+            detail: EventDetail::Full,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            base_delay: DEFAULT_BASE_DELAY,
+        }
+    }
+}
+
+/// Send events in batches, keeping the order of the events of each testrun
+///
+/// Entries that failed without being at fault are retried with a jittered
+/// backoff. Returns the events that could not be sent.
+pub(crate) async fn send_batches<P: Publisher>(
+    publisher: &P,
+    events: &[Event],
+    settings: &BatchSettings,
+) -> Vec<FailedEvent> {
+    let failed = send_in_order(events, |round| async move {
+        let mut batches: Vec<Vec<(usize, P::Entry)>> = Vec::new();
+        let mut batch_size = 0;
+        for index in round {
+            let entry = publisher.entry(&events[index], settings.detail);
+            let size = P::size(&entry);
+            match batches.last_mut() {
+                Some(batch) if batch.len() < MAX_ENTRIES && batch_size + size <= MAX_BATCH_SIZE => {
+                    batch.push((index, entry));
+                    batch_size += size;
+                }
+                _ => {
+                    batches.push(vec![(index, entry)]);
+                    batch_size = size;
+                }
+            }
+        }
+
+        join_all(
+            batches
+                .into_iter()
+                .map(|batch| send_batch(publisher, events, batch, settings)),
+        )
+        .await
+        .into_iter()
+        .flatten()
+        .collect()
+    })
+    .await;
+    if !failed.is_empty() {
+        warn!(
+            "{} of {} events could not be sent",
+            failed.len(),
+            events.len()
+        );
+    }
+
+    failed
+}
+
+/// Send a single batch, retrying the entries that may succeed later
+async fn send_batch<P: Publisher>(
+    publisher: &P,
+    events: &[Event],
+    mut pending: Vec<(usize, P::Entry)>,
+    settings: &BatchSettings,
+) -> Vec<FailedEvent> {
+    let mut failed = Vec::new();
+    let mut attempt = 0;
+
+    loop {
+        let entries = pending
+            .iter()
+            .map(|(_, entry)| entry.clone())
+            .collect::<Vec<_>>();
+        let failures = match publisher.publish(&entries).await {
+            Ok(failures) => failures,
+            Err(err) => {
+                warn!("Failed to send {} events: {}", pending.len(), err);
+                let message = err.to_string();
+                failed.extend(pending.into_iter().map(|(index, _)| FailedEvent {
+                    index,
+                    code: REQUEST_FAILED.to_owned(),
+                    message: Some(message.clone()),
+                }));
+                break;
+            }
+        };
+        let mut failures = failures
+            .into_iter()
+            .map(|failure| (failure.position, failure))
+            .collect::<HashMap<_, _>>();
+
+        let mut retry = Vec::new();
+        for (position, (index, entry)) in pending.into_iter().enumerate() {
+            let Some(failure) = failures.remove(&position) else {
+                continue;
+            };
+            let event = FailedEvent {
+                index,
+                code: failure.code,
+                message: failure.message,
+            };
+            if failure.sender_fault {
+                warn!(
+                    "Event for '{}' was rejected with {}",
+                    events[index].id(),
+                    event.code
+                );
+                failed.push(event);
+            } else {
+                retry.push((event, entry));
+            }
+        }
+
+        attempt += 1;
+        if retry.is_empty() || attempt >= settings.max_attempts {
+            failed.extend(retry.into_iter().map(|(event, _)| event));
+            break;
+        }
+        warn!("Retrying {} events", retry.len());
+        tokio::time::sleep(backoff(attempt - 1, settings.base_delay, MAX_DELAY)).await;
+        pending = retry
+            .into_iter()
+            .map(|(event, entry)| (event.index, entry))
+            .collect();
+    }
+
+    failed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::tests::created;
+
+    #[test]
+    fn message_slims_large_events() {
+        let mut event = created("1");
+        if let Event::Created { testrun, .. } = &mut event {
+            testrun.files = [("main.py".to_owned(), "x".repeat(MAX_BATCH_SIZE).into())].into();
+        }
+
+        let message = Message::new(&event, EventDetail::Full);
+
+        assert!(message.size() < 1024);
+        assert_eq!(message.kind, "TestRunCreated");
+        assert_eq!(message.group_id, "1");
+    }
+}
@@ -0,0 +1,115 @@
+//! # Broadcast event bus
+//!
+//! Event bus publishing events on a tokio broadcast channel, for in-process
+//! subscribers such as a local runner or a server-sent events endpoint.
+//!
+//! Each subscriber receives the events sent after it subscribed. A subscriber
+//! falling more than the channel capacity behind skips the oldest events, and
+//! is told how many with `RecvError::Lagged`.
+
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tracing::{debug, instrument};
+
+use super::{EventBus, SendResult};
+use crate::{error::Error, model::Event};
+
+/// Events a subscriber can fall behind by default
+const DEFAULT_CAPACITY: usize = 1024;
+
+/// Broadcast event bus
+#[derive(Clone)]
+pub struct BroadcastEventBus {
+    sender: broadcast::Sender<Arc<Event>>,
+}
+
+impl BroadcastEventBus {
+    /// Create a bus on which subscribers can fall `capacity` events behind
+    pub fn new(capacity: usize) -> BroadcastEventBus {
+        let (sender, _) = broadcast::channel(capacity.max(1));
+
+        BroadcastEventBus { sender }
+    }
+
+    /// Receive the events sent from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Event>> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for BroadcastEventBus {
+    fn default() -> Self {
+        BroadcastEventBus::new(DEFAULT_CAPACITY)
+    }
+}
+
+#[async_trait]
+impl EventBus for BroadcastEventBus {
+    type E = Event;
+
+    #[instrument(skip(self, event), fields(id = event.id()))]
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        // Sending only fails without subscribers, which is not an error
+        if self.sender.send(Arc::new(event.clone())).is_err() {
+            debug!("No subscriber for the event of '{}'", event.id());
+        }
+
+        Ok(())
+    }
+
+    #[instrument(skip(self, events))]
+    async fn send_events(&self, events: &[Self::E]) -> Result<SendResult, Error> {
+        for event in events {
+            self.send_event(event).await?;
+        }
+
+        Ok(SendResult::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::tests::created;
+    use tokio::sync::broadcast::error::RecvError;
+
+    #[tokio::test]
+    async fn subscribers_receive_events() {
+        // GIVEN two subscribers
+        let bus = BroadcastEventBus::default();
+        let mut first = bus.subscribe();
+        let mut second = bus.subscribe();
+
+        // WHEN sending events
+        bus.send_events(&[created("1"), created("2")])
+            .await
+            .unwrap();
+
+        // THEN both receive them in order
+        for receiver in [&mut first, &mut second] {
+            assert_eq!(receiver.recv().await.unwrap().id(), "1");
+            assert_eq!(receiver.recv().await.unwrap().id(), "2");
+        }
+    }
+
+    #[tokio::test]
+    async fn send_without_subscribers() {
+        let bus = BroadcastEventBus::default();
+
+        assert!(bus.send_event(&created("1")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn lagging_subscriber() {
+        let bus = BroadcastEventBus::new(1);
+        let mut receiver = bus.subscribe();
+
+        bus.send_events(&[created("1"), created("2")])
+            .await
+            .unwrap();
+
+        assert!(matches!(receiver.recv().await, Err(RecvError::Lagged(1))));
+        assert_eq!(receiver.recv().await.unwrap().id(), "2");
+    }
+}
@@ -1,25 +1,54 @@
 use aws_sdk_eventbridge::model::PutEventsRequestEntry;
 
-use crate::model::Event;
+use crate::{
+    events::{detail_type, EventDetail, SlimEvent},
+    model::Event,
+};
 
 static SOURCE: &str = "preprocess-test-runs";
 
+/// Size EventBridge allows for the entries of a single PutEvents request
+pub const MAX_REQUEST_SIZE: usize = 256 * 1024;
+
 pub trait EventExt {
-    fn to_eventbridge(&self, bus_name: &str) -> PutEventsRequestEntry;
+    /// Convert to an entry, slim if the full entry would not fit in a request
+    fn to_eventbridge(&self, bus_name: &str, detail: EventDetail) -> PutEventsRequestEntry;
 }
 
 impl EventExt for Event {
-    fn to_eventbridge(&self, bus_name: &str) -> PutEventsRequestEntry {
-        PutEventsRequestEntry::builder()
+    fn to_eventbridge(&self, bus_name: &str, detail: EventDetail) -> PutEventsRequestEntry {
+        let builder = PutEventsRequestEntry::builder()
             .event_bus_name(bus_name)
             .source(SOURCE)
-            .detail_type(match self {
-                Event::Created { .. } => "TestRunCreated",
-                Event::Updated { .. } => "TestRunUpdated",
-                Event::Deleted { .. } => "TestRunDeleted",
-            })
-            .resources(self.id())
-            .detail(serde_json::to_string(self).unwrap())
+            .detail_type(detail_type(self))
+            .resources(self.id());
+
+        if detail == EventDetail::Full {
+            let entry = builder
+                .clone()
+                .detail(serde_json::to_string(self).unwrap())
+                .build();
+            if entry_size(&entry) <= MAX_REQUEST_SIZE {
+                return entry;
+            }
+        }
+
+        builder
+            .detail(serde_json::to_string(&SlimEvent::from(self)).unwrap())
             .build()
     }
 }
+
+/// Size of an entry, as EventBridge counts it against `MAX_REQUEST_SIZE`
+pub fn entry_size(entry: &PutEventsRequestEntry) -> usize {
+    entry.time().map_or(0, |_| 14)
+        + entry.source().map_or(0, str::len)
+        + entry.detail_type().map_or(0, str::len)
+        + entry.detail().map_or(0, str::len)
+        + entry
+            .resources()
+            .unwrap_or_default()
+            .iter()
+            .map(String::len)
+            .sum::<usize>()
+}
@@ -1,59 +1,391 @@
 use async_trait::async_trait;
-use aws_sdk_eventbridge::Client;
-use futures::future::join_all;
+use aws_sdk_eventbridge::{model::PutEventsRequestEntry, Client};
+use std::time::Duration;
 use tracing::instrument;
 
 use crate::{error::Error, model::Event};
 
-use self::ext::EventExt;
+use self::ext::{entry_size, EventExt};
 
-use super::EventBus;
+use super::{
+    batch::{send_batches, with_missing, BatchSettings, EntryFailure, Publisher},
+    send_one, EventBus, EventDetail, SendResult,
+};
 
 mod ext;
 
+/// Entry error codes that may succeed when retried
+const RETRYABLE_CODES: [&str; 2] = ["ThrottlingException", "InternalFailure"];
+
+/// EventBridge bus
+///
+/// Events of the same testrun are delivered in the order they are given, as
+/// requests for later events only go out once earlier ones are sent.
 pub struct EventBridgeBus {
     client: Client,
     bus_name: String,
+    settings: BatchSettings,
 }
 
 impl EventBridgeBus {
     pub fn new(client: Client, bus_name: String) -> Self {
-        Self { client, bus_name }
+        Self {
+            client,
+            bus_name,
+            settings: BatchSettings::default(),
+        }
+    }
+
+    /// Set how much of a testrun events carry
+    ///
+    /// Full events too large for EventBridge are sent slim regardless.
+    pub fn with_detail(mut self, detail: EventDetail) -> Self {
+        self.settings.detail = detail;
+        self
+    }
+
+    /// Attempts at sending an event with a retryable error, and the base
+    /// delay of the jittered backoff between attempts
+    pub fn with_retries(mut self, max_attempts: u32, base_delay: Duration) -> Self {
+        self.settings.max_attempts = max_attempts.max(1);
+        self.settings.base_delay = base_delay;
+        self
     }
 }
 
 #[async_trait]
-impl EventBus for EventBridgeBus {
-    type E = Event;
+impl Publisher for EventBridgeBus {
+    type Entry = PutEventsRequestEntry;
 
-    #[instrument(skip(self))]
-    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
-        self.client
+    fn entry(&self, event: &Event, detail: EventDetail) -> PutEventsRequestEntry {
+        event.to_eventbridge(&self.bus_name, detail)
+    }
+
+    fn size(entry: &PutEventsRequestEntry) -> usize {
+        entry_size(entry)
+    }
+
+    async fn publish(&self, entries: &[PutEventsRequestEntry]) -> Result<Vec<EntryFailure>, Error> {
+        // The SDK already retries requests that failed as a whole
+        let output = self
+            .client
             .put_events()
-            .entries(event.to_eventbridge(&self.bus_name))
+            .set_entries(Some(entries.to_vec()))
             .send()
-            .await?;
+            .await
+            .map_err(|err| Error::EventBusError(err.to_string()))?;
+
+        // Result entries are in the same order as the request entries
+        let mut successful = Vec::new();
+        let mut failures = Vec::new();
+        let results = output.entries().unwrap_or_default();
+        for (position, result) in results.iter().take(entries.len()).enumerate() {
+            match result.error_code() {
+                None => successful.push(position),
+                Some(code) => failures.push(EntryFailure {
+                    position,
+                    code: code.to_owned(),
+                    message: result.error_message().map(str::to_owned),
+                    sender_fault: !RETRYABLE_CODES.contains(&code),
+                }),
+            }
+        }
+
+        Ok(with_missing(entries.len(), &successful, failures))
+    }
+}
+
+#[async_trait]
+impl EventBus for EventBridgeBus {
+    type E = Event;
 
-        Ok(())
+    #[instrument(skip(self, event), fields(id = event.id()))]
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        send_one(self, event).await
     }
 
     #[instrument(skip(self, events))]
-    async fn send_events(&self, events: &[Self::E]) -> Result<(), Error> {
-        let response = join_all(events.iter().collect::<Vec<_>>().chunks(10).map(|chunk| {
-            self.client
-                .put_events()
-                .set_entries(Some(
-                    chunk
-                        .iter()
-                        .map(|e| e.to_eventbridge(&self.bus_name))
-                        .collect::<Vec<_>>(),
-                ))
-                .send()
-        }))
+    async fn send_events(&self, events: &[Self::E]) -> Result<SendResult, Error> {
+        Ok(SendResult {
+            failed: send_batches(self, events, &self.settings).await,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{tests::created, FailedEvent, PRECEDING_EVENT_FAILED};
+    use aws_sdk_eventbridge::{config::Builder, Credentials, Region};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+
+    type ConnectEvent = (http::Request<SdkBody>, http::Response<SdkBody>);
+
+    /// Bus replaying the given responses, without delay between retries
+    async fn get_bus(responses: Vec<&str>) -> (EventBridgeBus, TestConnection<SdkBody>) {
+        let events = responses
+            .into_iter()
+            .map(|body| -> ConnectEvent {
+                (
+                    http::Request::builder()
+                        .uri("https://events.eu-west-1.amazonaws.com/")
+                        .body(SdkBody::from("{}"))
+                        .unwrap(),
+                    http::Response::builder()
+                        .status(200)
+                        .body(SdkBody::from(body.to_owned()))
+                        .unwrap(),
+                )
+            })
+            .collect();
+        let conn = TestConnection::new(events);
+        let cfg = aws_config::from_env()
+            .region(Region::new("eu-west-1"))
+            .credentials_provider(Credentials::new(
+                "accesskey",
+                "privatekey",
+                None,
+                None,
+                "dummy",
+            ))
+            .load()
+            .await;
+        let client = Client::from_conf(
+            Builder::from(&cfg)
+                .http_connector(DynConnector::new(conn.clone()))
+                .build(),
+        );
+
+        (
+            EventBridgeBus::new(client, "bus".to_owned()).with_retries(3, Duration::ZERO),
+            conn,
+        )
+    }
+
+    /// Ids of the events sent by a request
+    fn sent_ids(conn: &TestConnection<SdkBody>, index: usize) -> Vec<String> {
+        let body: serde_json::Value =
+            serde_json::from_slice(conn.requests()[index].actual.body().bytes().unwrap()).unwrap();
+        body["Entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["Resources"][0].as_str().unwrap().to_owned())
+            .collect()
+    }
+
+    const THROTTLED: &str =
+        r#"{"ErrorCode": "ThrottlingException", "ErrorMessage": "Rate exceeded"}"#;
+
+    #[tokio::test]
+    async fn send_events_retries_throttled() {
+        // GIVEN a bus throttling the second event once
+        let (bus, conn) = get_bus(vec![
+            &format!(
+                r#"{{"FailedEntryCount": 1, "Entries": [{{"EventId": "1"}}, {}, {{"EventId": "3"}}]}}"#,
+                THROTTLED
+            ),
+            r#"{"FailedEntryCount": 0, "Entries": [{"EventId": "2"}]}"#,
+        ])
+        .await;
+
+        // WHEN sending events
+        let res = bus
+            .send_events(&[created("a"), created("b"), created("c")])
+            .await
+            .unwrap();
+
+        // THEN only the throttled event is sent again
+        assert!(res.is_success());
+        assert_eq!(conn.requests().len(), 2);
+        assert_eq!(sent_ids(&conn, 1), vec!["b"]);
+    }
+
+    #[tokio::test]
+    async fn send_events_rejected() {
+        // GIVEN a bus rejecting an event as malformed
+        let (bus, conn) = get_bus(vec![
+            r#"{"FailedEntryCount": 1, "Entries": [{"EventId": "1"}, {"ErrorCode": "MalformedDetail", "ErrorMessage": "Detail is malformed"}]}"#,
+        ])
+        .await;
+
+        // WHEN sending events
+        let res = bus
+            .send_events(&[created("a"), created("b")])
+            .await
+            .unwrap();
+
+        // THEN the event is reported without being retried
+        assert_eq!(conn.requests().len(), 1);
+        assert_eq!(
+            res.failed,
+            vec![FailedEvent {
+                index: 1,
+                code: "MalformedDetail".to_owned(),
+                message: Some("Detail is malformed".to_owned()),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn send_events_retries_exhausted() {
+        // GIVEN a bus that keeps throttling an event
+        let response = format!(r#"{{"FailedEntryCount": 1, "Entries": [{}]}}"#, THROTTLED);
+        let (bus, conn) = get_bus(vec![&response, &response, &response]).await;
+
+        // WHEN sending the event
+        let res = bus.send_event(&created("a")).await;
+
+        // THEN it fails once all attempts are used
+        assert_eq!(conn.requests().len(), 3);
+        assert!(matches!(res, Err(Error::EventBusError(_))));
+    }
+
+    #[tokio::test]
+    async fn send_events_missing_results() {
+        // GIVEN a bus returning fewer results than events were sent
+        let (bus, conn) = get_bus(vec![
+            r#"{"FailedEntryCount": 0, "Entries": [{"EventId": "1"}]}"#,
+            &sent(2),
+        ])
+        .await;
+
+        // WHEN sending events
+        let res = bus
+            .send_events(&[created("a"), created("b"), created("c")])
+            .await
+            .unwrap();
+
+        // THEN the events without a result are sent again
+        assert!(res.is_success());
+        assert_eq!(conn.requests().len(), 2);
+        assert_eq!(sent_ids(&conn, 1), vec!["b", "c"]);
+    }
+
+    /// Created event of a testrun with a file of the given size
+    fn get_large_event(id: &str, size: usize) -> Event {
+        let mut event = created(id);
+        if let Event::Created { testrun, .. } = &mut event {
+            testrun.files = [("main.py".to_owned(), "x".repeat(size).into())].into();
+        }
+        event
+    }
+
+    /// Response of a request whose entries were all sent
+    fn sent(count: usize) -> String {
+        let entries = (0..count)
+            .map(|index| format!(r#"{{"EventId": "{}"}}"#, index))
+            .collect::<Vec<_>>();
+        format!(
+            r#"{{"FailedEntryCount": 0, "Entries": [{}]}}"#,
+            entries.join(", ")
+        )
+    }
+
+    #[tokio::test]
+    async fn send_events_chunks_by_size() {
+        // GIVEN events of which only two fit in a request
+        let (bus, conn) = get_bus(vec![&sent(2), &sent(1)]).await;
+        let events = (0..3)
+            .map(|index| get_large_event(&index.to_string(), 100 * 1024))
+            .collect::<Vec<_>>();
+
+        // WHEN sending them
+        let res = bus.send_events(&events).await.unwrap();
+
+        // THEN they are split by size
+        assert!(res.is_success());
+        assert_eq!(sent_ids(&conn, 0), vec!["0", "1"]);
+        assert_eq!(sent_ids(&conn, 1), vec!["2"]);
+    }
+
+    #[tokio::test]
+    async fn send_events_slims_large_events() {
+        // GIVEN an event larger than EventBridge allows
+        let (bus, conn) = get_bus(vec![&sent(1)]).await;
+
+        // WHEN sending it
+        bus.send_event(&get_large_event("1", 300 * 1024))
+            .await
+            .unwrap();
+
+        // THEN it is sent without its files
+        let body: serde_json::Value =
+            serde_json::from_slice(conn.requests()[0].actual.body().bytes().unwrap()).unwrap();
+        let detail: serde_json::Value =
+            serde_json::from_str(body["Entries"][0]["Detail"].as_str().unwrap()).unwrap();
+        assert_eq!(detail["type"], "Created");
+        assert_eq!(detail["id"], "1");
+        assert!(detail.get("testrun").is_none());
+    }
+
+    #[tokio::test]
+    async fn send_events_slim_detail() {
+        let (bus, conn) = get_bus(vec![&sent(1)]).await;
+        let bus = bus.with_detail(EventDetail::Slim);
+
+        bus.send_events(&[created("a")]).await.unwrap();
+
+        let body: serde_json::Value =
+            serde_json::from_slice(conn.requests()[0].actual.body().bytes().unwrap()).unwrap();
+        let detail: serde_json::Value =
+            serde_json::from_str(body["Entries"][0]["Detail"].as_str().unwrap()).unwrap();
+        assert_eq!(detail["id"], "a");
+        assert!(detail.get("testrun").is_none());
+    }
+
+    #[tokio::test]
+    async fn send_events_keeps_order() {
+        // GIVEN a bus rejecting the first event of a testrun
+        let (bus, conn) = get_bus(vec![
+            r#"{"FailedEntryCount": 1, "Entries": [{"ErrorCode": "InvalidArgument"}, {"EventId": "2"}]}"#,
+            &sent(1),
+        ])
+        .await;
+        let events = vec![
+            get_large_event("a", 1),
+            get_large_event("b", 1),
+            get_large_event("a", 1),
+            get_large_event("b", 1),
+        ];
+
+        // WHEN sending events of the same testruns
+        let res = bus.send_events(&events).await.unwrap();
+
+        // THEN later events are sent after earlier ones, but not after a failure
+        assert_eq!(sent_ids(&conn, 0), vec!["a", "b"]);
+        assert_eq!(sent_ids(&conn, 1), vec!["b"]);
+        assert_eq!(
+            res.failed
+                .iter()
+                .map(|event| (event.index, event.code.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(0, "InvalidArgument"), (2, PRECEDING_EVENT_FAILED)]
+        );
+    }
+
+    #[tokio::test]
+    async fn send_events_indexes_chunks() {
+        // GIVEN a bus rejecting the first event of the second chunk
+        let (bus, _) = get_bus(vec![
+            &sent(10),
+            r#"{"FailedEntryCount": 1, "Entries": [{"ErrorCode": "InvalidArgument"}, {"EventId": "2"}]}"#,
+        ])
         .await;
 
-        response.into_iter().collect::<Result<Vec<_>, _>>()?;
+        // WHEN sending more events than fit in a request
+        let res = bus
+            .send_events(
+                &(0..12)
+                    .map(|index| created(&index.to_string()))
+                    .collect::<Vec<_>>(),
+            )
+            .await
+            .unwrap();
 
-        Ok(())
+        // THEN the failure is indexed in the sent events
+        assert_eq!(res.failed.len(), 1);
+        assert_eq!(res.failed[0].index, 10);
     }
 }
@@ -0,0 +1,150 @@
+//! # In-memory event bus
+//!
+//! Event bus recording the events it is sent, for tests to assert on.
+
+use async_trait::async_trait;
+use std::{
+    collections::HashSet,
+    sync::{Mutex, MutexGuard},
+};
+use tracing::instrument;
+
+use super::{send_one, EventBus, FailedEvent, SendResult, PRECEDING_EVENT_FAILED};
+use crate::{error::Error, model::Event};
+
+/// Code of the events of rejected testruns
+pub const REJECTED: &str = "Rejected";
+
+/// In-memory event bus
+#[derive(Default)]
+pub struct MemoryEventBus {
+    /// Events sent, in order
+    events: Mutex<Vec<Event>>,
+    /// Testruns whose events are rejected
+    rejected: Mutex<HashSet<String>>,
+}
+
+impl MemoryEventBus {
+    pub fn new() -> MemoryEventBus {
+        MemoryEventBus::default()
+    }
+
+    /// Reject the events of a testrun, as a failing bus would
+    pub fn reject(&self, id: &str) {
+        lock(&self.rejected)
+            .expect("event bus lock poisoned")
+            .insert(id.to_owned());
+    }
+
+    /// Events sent so far
+    pub fn events(&self) -> Vec<Event> {
+        lock(&self.events).expect("event bus lock poisoned").clone()
+    }
+
+    /// Remove the events sent so far
+    pub fn take(&self) -> Vec<Event> {
+        std::mem::take(&mut *lock(&self.events).expect("event bus lock poisoned"))
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>, Error> {
+    mutex
+        .lock()
+        .map_err(|_| Error::InternalError("Event bus lock poisoned"))
+}
+
+#[async_trait]
+impl EventBus for MemoryEventBus {
+    type E = Event;
+
+    #[instrument(skip(self, event), fields(id = event.id()))]
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        send_one(self, event).await
+    }
+
+    /// Record events, keeping the order of each testrun like other buses do
+    #[instrument(skip(self, events))]
+    async fn send_events(&self, events: &[Self::E]) -> Result<SendResult, Error> {
+        let rejected = lock(&self.rejected)?;
+        let mut sent = lock(&self.events)?;
+        let mut failed = Vec::new();
+        let mut failed_ids = HashSet::new();
+
+        for (index, event) in events.iter().enumerate() {
+            let (code, message) = if failed_ids.contains(event.id()) {
+                (PRECEDING_EVENT_FAILED, None)
+            } else if rejected.contains(event.id()) {
+                (
+                    REJECTED,
+                    Some(format!("events of '{}' are rejected", event.id())),
+                )
+            } else {
+                sent.push(event.clone());
+                continue;
+            };
+            failed_ids.insert(event.id());
+            failed.push(FailedEvent {
+                index,
+                code: code.to_owned(),
+                message,
+            });
+        }
+
+        Ok(SendResult { failed })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::tests::created;
+
+    #[tokio::test]
+    async fn send_events_records() {
+        let bus = MemoryEventBus::new();
+
+        bus.send_event(&created("1")).await.unwrap();
+        let res = bus
+            .send_events(&[created("2"), created("3")])
+            .await
+            .unwrap();
+
+        assert!(res.is_success());
+        let ids = bus
+            .take()
+            .iter()
+            .map(|e| e.id().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec!["1", "2", "3"]);
+        assert!(bus.events().is_empty());
+    }
+
+    #[tokio::test]
+    async fn send_events_rejected() {
+        // GIVEN a bus rejecting the events of a testrun
+        let bus = MemoryEventBus::new();
+        bus.reject("1");
+
+        // WHEN sending events of several testruns
+        let res = bus
+            .send_events(&[created("1"), created("2"), created("1")])
+            .await
+            .unwrap();
+
+        // THEN only the events of other testruns are recorded
+        assert_eq!(bus.events().len(), 1);
+        assert_eq!(
+            res.failed
+                .iter()
+                .map(|event| (event.index, event.code.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(0, REJECTED), (2, PRECEDING_EVENT_FAILED)]
+        );
+        match bus.send_event(&created("1")).await {
+            Err(Error::EventBusError(msg)) => {
+                assert_eq!(msg, "Rejected: events of '1' are rejected")
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+}
@@ -1,13 +1,383 @@
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    time::Duration,
+};
 
-use crate::error::Error;
+use crate::{
+    error::Error,
+    model::{Event, TestRun, TestRunStatus},
+};
 
+mod batch;
+pub mod broadcast;
 pub mod eventbridge;
+pub mod memory;
+pub mod sns;
+pub mod sqs;
+
+/// Event that could not be sent
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FailedEvent {
+    /// Position of the event in the sent slice
+    pub index: usize,
+    /// Error code returned by the bus
+    pub code: String,
+    pub message: Option<String>,
+}
+
+/// Outcome of sending a batch of events
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SendResult {
+    /// Events that were still rejected once retries were exhausted, in the
+    /// order they were sent
+    pub failed: Vec<FailedEvent>,
+}
+
+impl SendResult {
+    /// Whether all events were sent
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
 
 #[async_trait]
-pub trait EventBus {
+pub trait EventBus: Send + Sync {
     type E;
 
     async fn send_event(&self, event: &Self::E) -> Result<(), Error>;
-    async fn send_events(&self, events: &[Self::E]) -> Result<(), Error>;
+
+    /// Send a batch of events
+    ///
+    /// Events rejected by the bus are listed in the result rather than
+    /// failing the whole batch.
+    async fn send_events(&self, events: &[Self::E]) -> Result<SendResult, Error>;
+}
+
+/// Send a single event as a batch of one, failing with its error
+///
+/// Shared by the buses that only send batches.
+pub(crate) async fn send_one<B>(bus: &B, event: &B::E) -> Result<(), Error>
+where
+    B: EventBus + ?Sized,
+    B::E: Sync,
+{
+    let result = bus.send_events(std::slice::from_ref(event)).await?;
+
+    match result.failed.into_iter().next() {
+        None => Ok(()),
+        Some(failed) => Err(Error::EventBusError(format!(
+            "{}: {}",
+            failed.code,
+            failed.message.unwrap_or_default()
+        ))),
+    }
+}
+
+/// How much of a testrun an event carries
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EventDetail {
+    /// The whole testrun, and the previous one for updates
+    #[default]
+    Full,
+    /// Only a `SlimEvent`
+    Slim,
+}
+
+impl FromStr for EventDetail {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(EventDetail::Full),
+            "slim" => Ok(EventDetail::Slim),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Event without the files and tests of the testrun
+///
+/// Consumers needing them fetch the testrun by id, or read the blobs in
+/// `files_ref` and `tests_ref` when the store spilled them.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SlimEvent {
+    /// Variant of the full event, such as `Created`
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub challenge_id: Option<String>,
+    pub status: TestRunStatus,
+    pub version: u64,
+    /// Status before an update
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_status: Option<TestRunStatus>,
+    /// Fields changed by an update
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changed: Vec<String>,
+    /// Blob key of the spilled files, see `PayloadRefs`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub files_ref: Option<String>,
+    /// Blob key of the spilled tests, see `PayloadRefs`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tests_ref: Option<String>,
+}
+
+impl From<&Event> for SlimEvent {
+    fn from(event: &Event) -> SlimEvent {
+        let (kind, testrun, old) = match event {
+            Event::Created { testrun, .. } => ("Created", testrun, None),
+            Event::Updated { old, new, .. } => ("Updated", new, Some(old)),
+            Event::Cancelled { testrun, .. } => ("Cancelled", testrun, None),
+            Event::Deleted { testrun, .. } => ("Deleted", testrun, None),
+            Event::Expired { testrun, .. } => ("Expired", testrun, None),
+        };
+        let refs = event.refs();
+
+        SlimEvent {
+            kind: kind.to_owned(),
+            id: testrun.id.clone(),
+            user_id: testrun.user_id.clone(),
+            challenge_id: testrun.challenge_id.clone(),
+            status: testrun.status,
+            version: testrun.version,
+            old_status: old.map(|old| old.status),
+            changed: old.map(|old| changed(old, testrun)).unwrap_or_default(),
+            files_ref: refs.files_ref.clone(),
+            tests_ref: refs.tests_ref.clone(),
+        }
+    }
+}
+
+/// Names of the fields that differ between two versions of a testrun
+fn changed(old: &TestRun, new: &TestRun) -> Vec<String> {
+    [
+        ("userId", old.user_id != new.user_id),
+        ("challengeId", old.challenge_id != new.challenge_id),
+        ("files", old.files != new.files),
+        ("language", old.language != new.language),
+        ("status", old.status != new.status),
+        ("tests", old.tests != new.tests),
+    ]
+    .into_iter()
+    .filter(|(_, changed)| *changed)
+    .map(|(name, _)| name.to_owned())
+    .collect()
+}
+
+/// Split events into rounds holding at most one event of each testrun
+///
+/// Sending rounds one after the other keeps the events of each testrun in
+/// order, while the events of a round can be sent concurrently. Returns the
+/// indexes of the events of each round.
+pub(crate) fn rounds(events: &[Event]) -> Vec<Vec<usize>> {
+    let mut rounds: Vec<Vec<usize>> = Vec::new();
+    let mut seen = HashMap::<&str, usize>::new();
+
+    for (index, event) in events.iter().enumerate() {
+        let round = seen.entry(event.id()).or_insert(0);
+        if *round == rounds.len() {
+            rounds.push(Vec::new());
+        }
+        rounds[*round].push(index);
+        *round += 1;
+    }
+
+    rounds
+}
+
+/// Type of an event, as exposed to consumers
+pub(crate) fn detail_type(event: &Event) -> &'static str {
+    match event {
+        Event::Created { .. } => "TestRunCreated",
+        Event::Updated { .. } => "TestRunUpdated",
+        Event::Cancelled { .. } => "TestRunCancelled",
+        Event::Deleted { .. } => "TestRunDeleted",
+        Event::Expired { .. } => "TestRunExpired",
+    }
+}
+
+/// Code of events whose request failed as a whole
+pub(crate) const REQUEST_FAILED: &str = "RequestFailed";
+
+/// Code of events not sent because an earlier event of their testrun failed
+pub(crate) const PRECEDING_EVENT_FAILED: &str = "PrecedingEventFailed";
+
+/// Send events round by round with `send_round`, which returns the events of
+/// a round that could not be sent
+///
+/// Once an event fails, the following events of its testrun are not sent.
+pub(crate) async fn send_in_order<F, Fut>(events: &[Event], send_round: F) -> Vec<FailedEvent>
+where
+    F: Fn(Vec<usize>) -> Fut,
+    Fut: std::future::Future<Output = Vec<FailedEvent>>,
+{
+    let mut failed = Vec::new();
+    let mut failed_ids = HashSet::new();
+
+    for round in rounds(events) {
+        let (blocked, ready): (Vec<_>, Vec<_>) = round
+            .into_iter()
+            .partition(|index| failed_ids.contains(events[*index].id()));
+        failed.extend(blocked.into_iter().map(|index| FailedEvent {
+            index,
+            code: PRECEDING_EVENT_FAILED.to_owned(),
+            message: None,
+        }));
+        if ready.is_empty() {
+            continue;
+        }
+
+        for event in send_round(ready).await {
+            failed_ids.insert(events[event.index].id());
+            failed.push(event);
+        }
+    }
+
+    failed.sort_by_key(|event| event.index);
+    failed
+}
+
+/// Delay before retry `attempt`, starting at 0, with full jitter
+///
+/// The delay is picked at random between zero and `base` doubled on every
+/// attempt, up to `max`.
+pub(crate) fn backoff(attempt: u32, base: Duration, max: Duration) -> Duration {
+    let ceiling = base.saturating_mul(2u32.saturating_pow(attempt)).min(max);
+    let millis = u64::try_from(ceiling.as_millis()).unwrap_or(u64::MAX);
+
+    Duration::from_millis(fastrand::u64(0..=millis))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{
+        model::{PayloadRefs, TestStatus},
+        store::conformance::get_testrun,
+    };
+    use aws_smithy_client::test_connection::TestConnection;
+    use aws_smithy_http::body::SdkBody;
+
+    /// Created event of the conformance testrun, under `id`
+    pub fn created(id: &str) -> Event {
+        Event::Created {
+            testrun: TestRun {
+                id: id.to_owned(),
+                ..get_testrun()
+            },
+            refs: PayloadRefs::default(),
+        }
+    }
+
+    /// Body of a request, as a string
+    pub fn request_body(conn: &TestConnection<SdkBody>, index: usize) -> String {
+        String::from_utf8(
+            conn.requests()[index]
+                .actual
+                .body()
+                .bytes()
+                .unwrap()
+                .to_vec(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn rounds_keep_order() {
+        let events = vec![created("a"), created("a"), created("b"), created("a")];
+
+        assert_eq!(rounds(&events), vec![vec![0, 2], vec![1], vec![3]]);
+    }
+
+    #[tokio::test]
+    async fn send_in_order_blocks_failed_ids() {
+        // GIVEN a testrun whose first event fails
+        let events = vec![created("a"), created("b"), created("a"), created("b")];
+
+        // WHEN sending the events in order
+        let sent = std::sync::Mutex::new(Vec::new());
+        let failed = send_in_order(&events, |round| {
+            sent.lock().unwrap().push(round.clone());
+            async move {
+                round
+                    .into_iter()
+                    .filter(|index| *index == 0)
+                    .map(|index| FailedEvent {
+                        index,
+                        code: "InvalidArgument".to_owned(),
+                        message: None,
+                    })
+                    .collect()
+            }
+        })
+        .await;
+
+        // THEN its following events are not sent
+        assert_eq!(*sent.lock().unwrap(), vec![vec![0, 1], vec![3]]);
+        assert_eq!(
+            failed
+                .iter()
+                .map(|event| (event.index, event.code.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(0, "InvalidArgument"), (2, PRECEDING_EVENT_FAILED)]
+        );
+    }
+
+    #[test]
+    fn slim_event_update() {
+        let old = get_testrun();
+        let mut new = get_testrun();
+        new.status = TestRunStatus::Failed;
+        new.tests[0].status = TestStatus::Failed;
+        new.version += 1;
+
+        let slim = SlimEvent::from(&Event::Updated {
+            old,
+            new,
+            refs: PayloadRefs::default(),
+        });
+
+        assert_eq!(slim.kind, "Updated");
+        assert_eq!(slim.status, TestRunStatus::Failed);
+        assert_eq!(slim.changed, vec!["status", "tests"]);
+        let json = serde_json::to_value(&slim).unwrap();
+        assert!(json.get("files").is_none());
+    }
+
+    #[test]
+    fn slim_event_refs() {
+        let refs = PayloadRefs {
+            files_ref: Some("sha256/files".to_owned()),
+            tests_ref: None,
+        };
+
+        let slim = SlimEvent::from(&Event::Expired {
+            testrun: get_testrun(),
+            refs,
+        });
+
+        let json = serde_json::to_value(&slim).unwrap();
+        assert_eq!(json["filesRef"], "sha256/files");
+        assert!(json.get("testsRef").is_none());
+    }
+
+    #[test]
+    fn backoff_bounds() {
+        let base = Duration::from_millis(100);
+        let max = Duration::from_secs(1);
+
+        for _ in 0..100 {
+            assert!(backoff(0, base, max) <= base);
+            assert!(backoff(2, base, max) <= base * 4);
+            assert!(backoff(20, base, max) <= max);
+        }
+        assert_eq!(backoff(3, Duration::ZERO, max), Duration::ZERO);
+    }
 }
@@ -0,0 +1,303 @@
+//! # SNS event bus
+//!
+//! Event bus publishing events as messages to an SNS topic. On FIFO topics, the
+//! messages of a testrun share its id as `MessageGroupId`, so subscribed
+//! queues receive them in order.
+
+use async_trait::async_trait;
+use aws_sdk_sns::{
+    model::{MessageAttributeValue, PublishBatchRequestEntry},
+    Client,
+};
+use std::time::Duration;
+use tracing::instrument;
+
+use super::{
+    batch::{send_batches, with_missing, BatchSettings, EntryFailure, Message, Publisher},
+    send_one, EventBus, EventDetail, SendResult,
+};
+use crate::{error::Error, model::Event};
+
+/// SNS event bus
+pub struct SnsBus {
+    client: Client,
+    topic_arn: String,
+    settings: BatchSettings,
+}
+
+impl SnsBus {
+    /// Bus publishing to the topic `topic_arn`
+    pub fn new(client: Client, topic_arn: String) -> Self {
+        Self {
+            client,
+            topic_arn,
+            settings: BatchSettings::default(),
+        }
+    }
+
+    /// Set how much of a testrun events carry
+    pub fn with_detail(mut self, detail: EventDetail) -> Self {
+        self.settings.detail = detail;
+        self
+    }
+
+    /// Set the attempts and backoff of retries, as for
+    /// `EventBridgeBus::with_retries`
+    pub fn with_retries(mut self, max_attempts: u32, base_delay: Duration) -> Self {
+        self.settings.max_attempts = max_attempts.max(1);
+        self.settings.base_delay = base_delay;
+        self
+    }
+
+    fn is_fifo(&self) -> bool {
+        self.topic_arn.ends_with(".fifo")
+    }
+}
+
+#[async_trait]
+impl Publisher for SnsBus {
+    type Entry = Message;
+
+    fn entry(&self, event: &Event, detail: EventDetail) -> Message {
+        Message::new(event, detail)
+    }
+
+    fn size(entry: &Message) -> usize {
+        entry.size()
+    }
+
+    async fn publish(&self, messages: &[Message]) -> Result<Vec<EntryFailure>, Error> {
+        let entries = messages
+            .iter()
+            .enumerate()
+            .map(|(position, message)| {
+                let mut entry = PublishBatchRequestEntry::builder()
+                    .id(position.to_string())
+                    .message(&message.body)
+                    .message_attributes(
+                        "type",
+                        MessageAttributeValue::builder()
+                            .data_type("String")
+                            .string_value(message.kind)
+                            .build(),
+                    );
+                if self.is_fifo() {
+                    entry = entry
+                        .message_group_id(&message.group_id)
+                        .message_deduplication_id(message.deduplication_id());
+                }
+                entry.build()
+            })
+            .collect();
+
+        let output = self
+            .client
+            .publish_batch()
+            .topic_arn(&self.topic_arn)
+            .set_publish_batch_request_entries(Some(entries))
+            .send()
+            .await
+            .map_err(|err| Error::EventBusError(err.to_string()))?;
+
+        let successful = output
+            .successful()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|entry| entry.id()?.parse().ok())
+            .collect::<Vec<_>>();
+        let failures = output
+            .failed()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|failure| {
+                Some(EntryFailure {
+                    position: failure.id()?.parse().ok()?,
+                    code: failure.code().unwrap_or_default().to_owned(),
+                    message: failure.message().map(str::to_owned),
+                    sender_fault: failure.sender_fault(),
+                })
+            })
+            .collect();
+
+        Ok(with_missing(messages.len(), &successful, failures))
+    }
+}
+
+#[async_trait]
+impl EventBus for SnsBus {
+    type E = Event;
+
+    #[instrument(skip(self, event), fields(id = event.id()))]
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        send_one(self, event).await
+    }
+
+    #[instrument(skip(self, events))]
+    async fn send_events(&self, events: &[Self::E]) -> Result<SendResult, Error> {
+        Ok(SendResult {
+            failed: send_batches(self, events, &self.settings).await,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{
+        tests::{created, request_body},
+        REQUEST_FAILED,
+    };
+    use aws_sdk_sns::{config::Builder, Credentials, Region};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+
+    const TOPIC: &str = "arn:aws:sns:eu-west-1:123456789012:events";
+
+    const PUBLISHED: &str = r#"<PublishBatchResponse>
+        <PublishBatchResult>
+            <Successful><member><Id>0</Id><MessageId>1</MessageId></member></Successful>
+        </PublishBatchResult>
+    </PublishBatchResponse>"#;
+
+    /// Bus publishing to `topic_arn` and replaying the given responses
+    async fn get_bus(topic_arn: &str, responses: Vec<&str>) -> (SnsBus, TestConnection<SdkBody>) {
+        let conn = TestConnection::new(
+            responses
+                .into_iter()
+                .map(|body| {
+                    (
+                        http::Request::builder()
+                            .uri("https://sns.eu-west-1.amazonaws.com/")
+                            .body(SdkBody::from(""))
+                            .unwrap(),
+                        http::Response::builder()
+                            .status(200)
+                            .body(SdkBody::from(body.to_owned()))
+                            .unwrap(),
+                    )
+                })
+                .collect(),
+        );
+        let cfg = aws_config::from_env()
+            .region(Region::new("eu-west-1"))
+            .credentials_provider(Credentials::new(
+                "accesskey",
+                "privatekey",
+                None,
+                None,
+                "dummy",
+            ))
+            .load()
+            .await;
+        let client = Client::from_conf(
+            Builder::from(&cfg)
+                .http_connector(DynConnector::new(conn.clone()))
+                .build(),
+        );
+
+        (
+            SnsBus::new(client, topic_arn.to_owned()).with_retries(3, Duration::ZERO),
+            conn,
+        )
+    }
+
+    #[tokio::test]
+    async fn send_events_publish_batch() {
+        // GIVEN a topic rejecting the second message
+        let (bus, conn) = get_bus(
+            TOPIC,
+            vec![
+                r#"<PublishBatchResponse>
+                    <PublishBatchResult>
+                        <Successful><member><Id>0</Id><MessageId>1</MessageId></member></Successful>
+                        <Failed><member>
+                            <Id>1</Id>
+                            <Code>InvalidParameter</Code>
+                            <Message>Invalid message</Message>
+                            <SenderFault>true</SenderFault>
+                        </member></Failed>
+                    </PublishBatchResult>
+                </PublishBatchResponse>"#,
+            ],
+        )
+        .await;
+        let bus = bus.with_detail(EventDetail::Slim);
+
+        // WHEN publishing events
+        let res = bus
+            .send_events(&[created("a"), created("b")])
+            .await
+            .unwrap();
+
+        // THEN they are published in a single batch
+        assert_eq!(conn.requests().len(), 1);
+        let body = request_body(&conn, 0);
+        assert!(body.starts_with("Action=PublishBatch&Version=2010-03-31&TopicArn="));
+        assert!(body.contains("PublishBatchRequestEntries.member.2.Id=1"));
+        assert!(body.contains(
+            "PublishBatchRequestEntries.member.1.MessageAttributes.entry.1.Value.StringValue=TestRunCreated"
+        ));
+        assert!(!body.contains("MessageGroupId"));
+
+        // AND the rejected message is reported
+        assert_eq!(res.failed.len(), 1);
+        assert_eq!(res.failed[0].index, 1);
+        assert_eq!(res.failed[0].code, "InvalidParameter");
+        assert_eq!(res.failed[0].message.as_deref(), Some("Invalid message"));
+    }
+
+    #[tokio::test]
+    async fn send_event_fifo_topic() {
+        let (bus, conn) = get_bus(&format!("{}.fifo", TOPIC), vec![PUBLISHED]).await;
+
+        bus.send_event(&created("a")).await.unwrap();
+
+        let body = request_body(&conn, 0);
+        assert!(body.contains("PublishBatchRequestEntries.member.1.MessageGroupId=a"));
+        assert!(body.contains("PublishBatchRequestEntries.member.1.MessageDeduplicationId="));
+    }
+
+    #[tokio::test]
+    async fn send_events_unknown_failure_id() {
+        // GIVEN a topic failing an entry under an id that isn't a position
+        let (bus, conn) = get_bus(
+            TOPIC,
+            vec![
+                r#"<PublishBatchResponse>
+                    <PublishBatchResult>
+                        <Failed><member>
+                            <Id>first</Id>
+                            <Code>InternalError</Code>
+                            <SenderFault>false</SenderFault>
+                        </member></Failed>
+                    </PublishBatchResult>
+                </PublishBatchResponse>"#,
+                PUBLISHED,
+            ],
+        )
+        .await;
+
+        // WHEN publishing an event
+        let res = bus.send_events(&[created("a")]).await.unwrap();
+
+        // THEN it is retried rather than counted as published
+        assert_eq!(conn.requests().len(), 2);
+        assert!(res.is_success());
+    }
+
+    #[tokio::test]
+    async fn send_events_missing_result() {
+        // GIVEN a topic returning no result for the entries
+        let empty = "<PublishBatchResponse><PublishBatchResult/></PublishBatchResponse>";
+        let (bus, conn) = get_bus(TOPIC, vec![empty, empty, empty]).await;
+
+        // WHEN publishing an event
+        let res = bus.send_events(&[created("a")]).await.unwrap();
+
+        // THEN it is retried, and reported as failed
+        assert_eq!(conn.requests().len(), 3);
+        assert_eq!(res.failed.len(), 1);
+        assert_eq!(res.failed[0].index, 0);
+        assert_eq!(res.failed[0].code, REQUEST_FAILED);
+    }
+}
@@ -0,0 +1,327 @@
+//! # SQS event bus
+//!
+//! Event bus sending events as messages to an SQS queue. On FIFO queues, the
+//! messages of a testrun share its id as `MessageGroupId`, so consumers
+//! receive them in order.
+
+use async_trait::async_trait;
+use aws_sdk_sqs::{
+    model::{MessageAttributeValue, SendMessageBatchRequestEntry},
+    Client,
+};
+use std::time::Duration;
+use tracing::instrument;
+
+use super::{
+    batch::{send_batches, with_missing, BatchSettings, EntryFailure, Message, Publisher},
+    send_one, EventBus, EventDetail, SendResult,
+};
+use crate::{error::Error, model::Event};
+
+/// SQS event bus
+pub struct SqsBus {
+    client: Client,
+    queue_url: String,
+    settings: BatchSettings,
+}
+
+impl SqsBus {
+    /// Bus sending to the queue at `queue_url`
+    pub fn new(client: Client, queue_url: String) -> Self {
+        Self {
+            client,
+            queue_url,
+            settings: BatchSettings::default(),
+        }
+    }
+
+    /// Set how much of a testrun events carry
+    pub fn with_detail(mut self, detail: EventDetail) -> Self {
+        self.settings.detail = detail;
+        self
+    }
+
+    /// Set the attempts and backoff of retries, as for
+    /// `EventBridgeBus::with_retries`
+    pub fn with_retries(mut self, max_attempts: u32, base_delay: Duration) -> Self {
+        self.settings.max_attempts = max_attempts.max(1);
+        self.settings.base_delay = base_delay;
+        self
+    }
+
+    fn is_fifo(&self) -> bool {
+        self.queue_url.ends_with(".fifo")
+    }
+}
+
+#[async_trait]
+impl Publisher for SqsBus {
+    type Entry = Message;
+
+    fn entry(&self, event: &Event, detail: EventDetail) -> Message {
+        Message::new(event, detail)
+    }
+
+    fn size(entry: &Message) -> usize {
+        entry.size()
+    }
+
+    async fn publish(&self, messages: &[Message]) -> Result<Vec<EntryFailure>, Error> {
+        let entries = messages
+            .iter()
+            .enumerate()
+            .map(|(position, message)| {
+                let mut entry = SendMessageBatchRequestEntry::builder()
+                    .id(position.to_string())
+                    .message_body(&message.body)
+                    .message_attributes(
+                        "type",
+                        MessageAttributeValue::builder()
+                            .data_type("String")
+                            .string_value(message.kind)
+                            .build(),
+                    );
+                if self.is_fifo() {
+                    entry = entry
+                        .message_group_id(&message.group_id)
+                        .message_deduplication_id(message.deduplication_id());
+                }
+                entry.build()
+            })
+            .collect();
+
+        let output = self
+            .client
+            .send_message_batch()
+            .queue_url(&self.queue_url)
+            .set_entries(Some(entries))
+            .send()
+            .await
+            .map_err(|err| Error::EventBusError(err.to_string()))?;
+
+        let successful = output
+            .successful()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|entry| entry.id()?.parse().ok())
+            .collect::<Vec<_>>();
+        let failures = output
+            .failed()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|failure| {
+                Some(EntryFailure {
+                    position: failure.id()?.parse().ok()?,
+                    code: failure.code().unwrap_or_default().to_owned(),
+                    message: failure.message().map(str::to_owned),
+                    sender_fault: failure.sender_fault(),
+                })
+            })
+            .collect();
+
+        Ok(with_missing(messages.len(), &successful, failures))
+    }
+}
+
+#[async_trait]
+impl EventBus for SqsBus {
+    type E = Event;
+
+    #[instrument(skip(self, event), fields(id = event.id()))]
+    async fn send_event(&self, event: &Self::E) -> Result<(), Error> {
+        send_one(self, event).await
+    }
+
+    #[instrument(skip(self, events))]
+    async fn send_events(&self, events: &[Self::E]) -> Result<SendResult, Error> {
+        Ok(SendResult {
+            failed: send_batches(self, events, &self.settings).await,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{
+        tests::{created, request_body},
+        REQUEST_FAILED,
+    };
+    use aws_sdk_sqs::{config::Builder, Credentials, Region};
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+
+    const SENT: &str = r#"<SendMessageBatchResponse>
+        <SendMessageBatchResult>
+            <SendMessageBatchResultEntry><Id>0</Id><MessageId>1</MessageId></SendMessageBatchResultEntry>
+        </SendMessageBatchResult>
+    </SendMessageBatchResponse>"#;
+
+    /// Bus sending to `queue_url` and replaying the given responses
+    async fn get_bus(queue_url: &str, responses: Vec<&str>) -> (SqsBus, TestConnection<SdkBody>) {
+        let conn = TestConnection::new(
+            responses
+                .into_iter()
+                .map(|body| {
+                    (
+                        http::Request::builder()
+                            .uri(queue_url)
+                            .body(SdkBody::from(""))
+                            .unwrap(),
+                        http::Response::builder()
+                            .status(200)
+                            .body(SdkBody::from(body.to_owned()))
+                            .unwrap(),
+                    )
+                })
+                .collect(),
+        );
+        let cfg = aws_config::from_env()
+            .region(Region::new("eu-west-1"))
+            .credentials_provider(Credentials::new(
+                "accesskey",
+                "privatekey",
+                None,
+                None,
+                "dummy",
+            ))
+            .load()
+            .await;
+        let client = Client::from_conf(
+            Builder::from(&cfg)
+                .http_connector(DynConnector::new(conn.clone()))
+                .build(),
+        );
+
+        (
+            SqsBus::new(client, queue_url.to_owned()).with_retries(3, Duration::ZERO),
+            conn,
+        )
+    }
+
+    #[tokio::test]
+    async fn send_events_fifo() {
+        // GIVEN a FIFO queue
+        let (bus, conn) = get_bus(
+            "https://sqs.eu-west-1.amazonaws.com/123456789012/events.fifo",
+            vec![SENT, SENT],
+        )
+        .await;
+
+        // WHEN sending two events of a testrun
+        let res = bus
+            .send_events(&[created("a"), created("a")])
+            .await
+            .unwrap();
+
+        // THEN they are sent one after the other, grouped by testrun
+        assert!(res.is_success());
+        assert_eq!(conn.requests().len(), 2);
+        let body = request_body(&conn, 0);
+        assert!(body.starts_with("Action=SendMessageBatch&Version=2012-11-05&QueueUrl="));
+        assert!(body.contains("SendMessageBatchRequestEntry.1.MessageGroupId=a"));
+        assert!(body.contains("SendMessageBatchRequestEntry.1.MessageDeduplicationId="));
+        assert!(body.contains(
+            "SendMessageBatchRequestEntry.1.MessageAttribute.1.Value.StringValue=TestRunCreated"
+        ));
+        assert!(!body.contains("SendMessageBatchRequestEntry.2"));
+    }
+
+    #[tokio::test]
+    async fn send_events_standard_queue() {
+        let (bus, conn) = get_bus(
+            "https://sqs.eu-west-1.amazonaws.com/123456789012/events",
+            vec![
+                r#"<SendMessageBatchResponse><SendMessageBatchResult>
+                    <SendMessageBatchResultEntry><Id>0</Id></SendMessageBatchResultEntry>
+                    <SendMessageBatchResultEntry><Id>1</Id></SendMessageBatchResultEntry>
+                </SendMessageBatchResult></SendMessageBatchResponse>"#,
+            ],
+        )
+        .await;
+
+        let res = bus
+            .send_events(&[created("a"), created("b")])
+            .await
+            .unwrap();
+
+        assert!(res.is_success());
+        let body = request_body(&conn, 0);
+        assert!(body.contains("SendMessageBatchRequestEntry.2.Id=1"));
+        assert!(!body.contains("MessageGroupId"));
+    }
+
+    #[tokio::test]
+    async fn send_events_partial_failure() {
+        // GIVEN a queue failing an entry on its side, then rejecting another
+        let (bus, conn) = get_bus(
+            "https://sqs.eu-west-1.amazonaws.com/123456789012/events",
+            vec![
+                r#"<SendMessageBatchResponse><SendMessageBatchResult>
+                    <SendMessageBatchResultEntry><Id>0</Id></SendMessageBatchResultEntry>
+                    <BatchResultErrorEntry><Id>1</Id><SenderFault>false</SenderFault><Code>InternalError</Code></BatchResultErrorEntry>
+                    <BatchResultErrorEntry><Id>2</Id><SenderFault>true</SenderFault><Code>InvalidParameterValue</Code><Message>Too long</Message></BatchResultErrorEntry>
+                </SendMessageBatchResult></SendMessageBatchResponse>"#,
+                SENT,
+            ],
+        )
+        .await;
+
+        // WHEN sending events
+        let res = bus
+            .send_events(&[created("a"), created("b"), created("c")])
+            .await
+            .unwrap();
+
+        // THEN the failed entry is retried, and the rejected one is reported
+        assert_eq!(conn.requests().len(), 2);
+        assert!(request_body(&conn, 1).contains("SendMessageBatchRequestEntry.1.Id=0"));
+        assert_eq!(res.failed.len(), 1);
+        assert_eq!(res.failed[0].index, 2);
+        assert_eq!(res.failed[0].code, "InvalidParameterValue");
+        assert_eq!(res.failed[0].message.as_deref(), Some("Too long"));
+    }
+
+    #[tokio::test]
+    async fn send_events_unknown_failure_id() {
+        // GIVEN a queue failing an entry under an id that isn't a position
+        let (bus, conn) = get_bus(
+            "https://sqs.eu-west-1.amazonaws.com/123456789012/events",
+            vec![
+                r#"<SendMessageBatchResponse><SendMessageBatchResult>
+                    <BatchResultErrorEntry><Id>first</Id><SenderFault>false</SenderFault><Code>InternalError</Code></BatchResultErrorEntry>
+                </SendMessageBatchResult></SendMessageBatchResponse>"#,
+                SENT,
+            ],
+        )
+        .await;
+
+        // WHEN sending an event
+        let res = bus.send_events(&[created("a")]).await.unwrap();
+
+        // THEN it is retried rather than counted as sent
+        assert_eq!(conn.requests().len(), 2);
+        assert!(res.is_success());
+    }
+
+    #[tokio::test]
+    async fn send_events_missing_result() {
+        // GIVEN a queue returning no result for the entries
+        let empty =
+            "<SendMessageBatchResponse><SendMessageBatchResult/></SendMessageBatchResponse>";
+        let (bus, conn) = get_bus(
+            "https://sqs.eu-west-1.amazonaws.com/123456789012/events",
+            vec![empty, empty, empty],
+        )
+        .await;
+
+        // WHEN sending an event
+        let res = bus.send_events(&[created("a")]).await.unwrap();
+
+        // THEN it is retried, and reported as failed
+        assert_eq!(conn.requests().len(), 3);
+        assert_eq!(res.failed.len(), 1);
+        assert_eq!(res.failed[0].index, 0);
+        assert_eq!(res.failed[0].code, REQUEST_FAILED);
+    }
+}
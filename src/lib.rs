@@ -3,5 +3,6 @@ pub mod entrypoints;
 pub mod error;
 pub mod events;
 pub mod model;
+pub mod ratelimit;
 pub mod store;
 pub mod utils;
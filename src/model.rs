@@ -1,43 +1,459 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt, str::FromStr};
 
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 
+/// Lifecycle state of a testrun
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TestRunStatus {
+    Queued,
+    Running,
+    Passed,
+    Failed,
+    Errored,
+    TimedOut,
+    Cancelled,
+}
+
+impl TestRunStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TestRunStatus::Queued => "queued",
+            TestRunStatus::Running => "running",
+            TestRunStatus::Passed => "passed",
+            TestRunStatus::Failed => "failed",
+            TestRunStatus::Errored => "errored",
+            TestRunStatus::TimedOut => "timed_out",
+            TestRunStatus::Cancelled => "cancelled",
+        }
+    }
+
+    /// Whether the testrun has reached a final state
+    pub fn is_final(&self) -> bool {
+        !matches!(self, TestRunStatus::Queued | TestRunStatus::Running)
+    }
+}
+
+impl fmt::Display for TestRunStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for TestRunStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "queued" => Ok(TestRunStatus::Queued),
+            "running" => Ok(TestRunStatus::Running),
+            "passed" => Ok(TestRunStatus::Passed),
+            "failed" => Ok(TestRunStatus::Failed),
+            "errored" => Ok(TestRunStatus::Errored),
+            "timed_out" => Ok(TestRunStatus::TimedOut),
+            "cancelled" => Ok(TestRunStatus::Cancelled),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Outcome of a single test
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TestStatus {
+    Passed,
+    Failed,
+    #[default]
+    Skipped,
+    Errored,
+}
+
+impl TestStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TestStatus::Passed => "passed",
+            TestStatus::Failed => "failed",
+            TestStatus::Skipped => "skipped",
+            TestStatus::Errored => "errored",
+        }
+    }
+}
+
+impl fmt::Display for TestStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for TestStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "passed" => Ok(TestStatus::Passed),
+            "failed" => Ok(TestStatus::Failed),
+            "skipped" => Ok(TestStatus::Skipped),
+            "errored" => Ok(TestStatus::Errored),
+            _ => Err(()),
+        }
+    }
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Test {
     pub message: String,
     pub name: String,
-    pub status: String,
+    pub status: TestStatus,
 
     pub actual_output: String,
     pub expected_output: String,
 }
 
+/// Contents of a submitted file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FileContents {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// File submitted with a testrun
+///
+/// In JSON, a text file that isn't executable is a plain string, as it was
+/// before binary files were supported. Any other file is an object such as
+/// `{"content": "AGFzbQ==", "encoding": "base64", "executable": true}`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "FileEntryRepr", try_from = "FileEntryRepr")]
+pub struct FileEntry {
+    pub contents: FileContents,
+    pub executable: bool,
+}
+
+impl FileEntry {
+    pub fn text(contents: impl Into<String>) -> FileEntry {
+        FileEntry {
+            contents: FileContents::Text(contents.into()),
+            executable: false,
+        }
+    }
+
+    pub fn binary(contents: impl Into<Vec<u8>>) -> FileEntry {
+        FileEntry {
+            contents: FileContents::Binary(contents.into()),
+            executable: false,
+        }
+    }
+
+    /// Store bytes as text if they are valid UTF-8, and as binary otherwise
+    pub fn from_bytes(contents: Vec<u8>) -> FileEntry {
+        match String::from_utf8(contents) {
+            Ok(text) => FileEntry::text(text),
+            Err(err) => FileEntry::binary(err.into_bytes()),
+        }
+    }
+
+    pub fn with_executable(self, executable: bool) -> FileEntry {
+        FileEntry { executable, ..self }
+    }
+
+    /// Text contents, if the file isn't binary
+    pub fn as_text(&self) -> Option<&str> {
+        match &self.contents {
+            FileContents::Text(text) => Some(text),
+            FileContents::Binary(_) => None,
+        }
+    }
+
+    /// Raw bytes of the file, whether text or binary
+    pub fn as_bytes(&self) -> &[u8] {
+        match &self.contents {
+            FileContents::Text(text) => text.as_bytes(),
+            FileContents::Binary(bytes) => bytes,
+        }
+    }
+}
+
+impl From<&str> for FileEntry {
+    fn from(value: &str) -> FileEntry {
+        FileEntry::text(value)
+    }
+}
+
+impl From<String> for FileEntry {
+    fn from(value: String) -> FileEntry {
+        FileEntry::text(value)
+    }
+}
+
+/// Encoding of the `content` of a file in JSON
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileEncoding {
+    #[default]
+    Utf8,
+    Base64,
+}
+
+/// JSON representation of a `FileEntry`
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum FileEntryRepr {
+    Text(String),
+    Entry {
+        content: String,
+        #[serde(default)]
+        encoding: FileEncoding,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        executable: bool,
+    },
+}
+
+impl From<FileEntry> for FileEntryRepr {
+    fn from(value: FileEntry) -> FileEntryRepr {
+        match (value.contents, value.executable) {
+            (FileContents::Text(text), false) => FileEntryRepr::Text(text),
+            (FileContents::Text(text), executable) => FileEntryRepr::Entry {
+                content: text,
+                encoding: FileEncoding::Utf8,
+                executable,
+            },
+            (FileContents::Binary(bytes), executable) => FileEntryRepr::Entry {
+                content: STANDARD.encode(bytes),
+                encoding: FileEncoding::Base64,
+                executable,
+            },
+        }
+    }
+}
+
+impl TryFrom<FileEntryRepr> for FileEntry {
+    type Error = String;
+
+    fn try_from(value: FileEntryRepr) -> Result<FileEntry, String> {
+        Ok(match value {
+            FileEntryRepr::Text(text) => FileEntry::text(text),
+            FileEntryRepr::Entry {
+                content,
+                encoding,
+                executable,
+            } => {
+                let entry = match encoding {
+                    FileEncoding::Utf8 => FileEntry::text(content),
+                    FileEncoding::Base64 => FileEntry::binary(
+                        STANDARD
+                            .decode(content)
+                            .map_err(|err| format!("invalid base64 file content: {}", err))?,
+                    ),
+                };
+                entry.with_executable(executable)
+            }
+        })
+    }
+}
+
 #[serde_as]
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct TestRun {
     pub id: String,
-    pub files: HashMap<String, String>,
+    /// User who submitted the testrun
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    /// Challenge the testrun was submitted against
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub challenge_id: Option<String>,
+    #[serde(default)]
+    pub files: HashMap<String, FileEntry>,
     pub language: String,
-    pub status: String,
+    pub status: TestRunStatus,
     pub tests: Vec<Test>,
+    /// Incremented on every write, starting at 1
+    ///
+    /// Stores only accept a write if the stored version is exactly one less,
+    /// so concurrent writers cannot silently overwrite each other.
+    #[serde(default)]
+    pub version: u64,
+    /// When the testrun was first stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// Testrun without its files and test results, as returned by listings
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TestRunSummary {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub challenge_id: Option<String>,
+    pub language: String,
+    pub status: TestRunStatus,
+    #[serde(default)]
+    pub version: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl From<&TestRun> for TestRunSummary {
+    fn from(testrun: &TestRun) -> TestRunSummary {
+        TestRunSummary {
+            id: testrun.id.clone(),
+            user_id: testrun.user_id.clone(),
+            challenge_id: testrun.challenge_id.clone(),
+            language: testrun.language.clone(),
+            status: testrun.status,
+            version: testrun.version,
+            created_at: testrun.created_at,
+        }
+    }
+}
+
+/// Blob keys of the `files` and `tests` of a testrun, when the store spilled
+/// them to a blob store
+///
+/// Blobs are kept in the bucket named by `BLOB_BUCKET_NAME`, see
+/// `store::blob`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PayloadRefs {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub files_ref: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tests_ref: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+impl PayloadRefs {
+    pub fn is_empty(&self) -> bool {
+        self.files_ref.is_none() && self.tests_ref.is_none()
+    }
+}
+
+/// Change to a testrun
+///
+/// `refs` point to the spilled payloads of the testrun the event is about,
+/// the new one for updates.
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(tag = "type")]
 pub enum Event {
-    Created { testrun: TestRun },
-    Updated { old: TestRun, new: TestRun },
-    Deleted { testrun: TestRun },
+    Created {
+        testrun: TestRun,
+        #[serde(default, skip_serializing_if = "PayloadRefs::is_empty")]
+        refs: PayloadRefs,
+    },
+    Updated {
+        old: TestRun,
+        new: TestRun,
+        #[serde(default, skip_serializing_if = "PayloadRefs::is_empty")]
+        refs: PayloadRefs,
+    },
+    /// Moved to the cancelled status, runners should stop working on it
+    Cancelled {
+        testrun: TestRun,
+        #[serde(default, skip_serializing_if = "PayloadRefs::is_empty")]
+        refs: PayloadRefs,
+    },
+    Deleted {
+        testrun: TestRun,
+        #[serde(default, skip_serializing_if = "PayloadRefs::is_empty")]
+        refs: PayloadRefs,
+    },
+    /// Deleted by the store once past its retention
+    Expired {
+        testrun: TestRun,
+        #[serde(default, skip_serializing_if = "PayloadRefs::is_empty")]
+        refs: PayloadRefs,
+    },
 }
 
 impl Event {
     pub fn id(&self) -> &str {
         match self {
-            Event::Created { testrun } => testrun.id.as_str(),
+            Event::Created { testrun, .. } => testrun.id.as_str(),
             Event::Updated { new, .. } => new.id.as_str(),
-            Event::Deleted { testrun } => testrun.id.as_str(),
+            Event::Cancelled { testrun, .. } => testrun.id.as_str(),
+            Event::Deleted { testrun, .. } => testrun.id.as_str(),
+            Event::Expired { testrun, .. } => testrun.id.as_str(),
+        }
+    }
+
+    /// Blob keys of the spilled payloads, see `PayloadRefs`
+    pub fn refs(&self) -> &PayloadRefs {
+        match self {
+            Event::Created { refs, .. }
+            | Event::Updated { refs, .. }
+            | Event::Cancelled { refs, .. }
+            | Event::Deleted { refs, .. }
+            | Event::Expired { refs, .. } => refs,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn file_entry_text_json() {
+        let entry: FileEntry = serde_json::from_value(json!("print('hello')")).unwrap();
+
+        assert_eq!(entry, FileEntry::text("print('hello')"));
+        assert_eq!(
+            serde_json::to_value(&entry).unwrap(),
+            json!("print('hello')")
+        );
+    }
+
+    #[test]
+    fn file_entry_binary_json() {
+        let value = json!({"content": "AGFzbQ==", "encoding": "base64", "executable": true});
+
+        let entry: FileEntry = serde_json::from_value(value.clone()).unwrap();
+
+        assert_eq!(
+            entry,
+            FileEntry::binary(b"\0asm".to_vec()).with_executable(true)
+        );
+        assert_eq!(serde_json::to_value(&entry).unwrap(), value);
+    }
+
+    #[test]
+    fn file_entry_executable_text_json() {
+        let value = json!({"content": "#!/bin/sh\n", "encoding": "utf8", "executable": true});
+
+        let entry: FileEntry = serde_json::from_value(value.clone()).unwrap();
+
+        assert_eq!(entry, FileEntry::text("#!/bin/sh\n").with_executable(true));
+        assert_eq!(serde_json::to_value(&entry).unwrap(), value);
+    }
+
+    #[test]
+    fn file_entry_defaults_json() {
+        let entry: FileEntry = serde_json::from_value(json!({"content": "x"})).unwrap();
+
+        assert_eq!(entry, FileEntry::text("x"));
+    }
+
+    #[test]
+    fn file_entry_invalid_base64_json() {
+        let value = json!({"content": "not base64!", "encoding": "base64"});
+
+        assert!(serde_json::from_value::<FileEntry>(value).is_err());
+    }
+
+    #[test]
+    fn file_entry_from_bytes() {
+        assert_eq!(
+            FileEntry::from_bytes(b"text".to_vec()),
+            FileEntry::text("text")
+        );
+        assert_eq!(
+            FileEntry::from_bytes(vec![0xff]),
+            FileEntry::binary(vec![0xff])
+        );
+    }
+}
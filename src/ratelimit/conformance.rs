@@ -0,0 +1,49 @@
+//! # Rate limiter conformance tests
+//!
+//! Behaviour expected from every rate limiter backend.
+
+use super::*;
+use chrono::TimeZone;
+
+pub fn get_now() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2023, 4, 26, 12, 0, 0).unwrap()
+}
+
+pub async fn take(limiter: &dyn RateLimiter) {
+    let bucket = TokenBucket::per_minute(2);
+    let now = get_now();
+
+    // The bucket starts full
+    assert_eq!(limiter.take("key", &bucket, now).await.unwrap(), None);
+    assert_eq!(limiter.take("key", &bucket, now).await.unwrap(), None);
+
+    // Once empty, a token is available after the refill time
+    assert_eq!(
+        limiter.take("key", &bucket, now).await.unwrap(),
+        Some(Duration::seconds(30))
+    );
+    let later = now + Duration::seconds(30);
+    assert_eq!(limiter.take("key", &bucket, later).await.unwrap(), None);
+
+    // Other keys have their own bucket
+    assert_eq!(limiter.take("other", &bucket, later).await.unwrap(), None);
+}
+
+pub async fn count(limiter: &dyn RateLimiter) {
+    let resets_at = Utc::now() + Duration::days(1);
+
+    assert!(limiter.count("key", 2, resets_at).await.unwrap());
+    assert!(limiter.count("key", 2, resets_at).await.unwrap());
+    assert!(!limiter.count("key", 2, resets_at).await.unwrap());
+    assert!(limiter.count("other", 2, resets_at).await.unwrap());
+}
+
+pub async fn uncount(limiter: &dyn RateLimiter) {
+    let resets_at = Utc::now() + Duration::days(1);
+
+    // A use given back can be counted again
+    assert!(limiter.count("key", 1, resets_at).await.unwrap());
+    limiter.uncount("key").await.unwrap();
+    assert!(limiter.count("key", 1, resets_at).await.unwrap());
+    assert!(!limiter.count("key", 1, resets_at).await.unwrap());
+}
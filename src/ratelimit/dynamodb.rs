@@ -0,0 +1,346 @@
+//! # DynamoDB rate limiter
+//!
+//! Rate limiter sharing its buckets and counters through a DynamoDB table,
+//! keyed by `limitKey`.
+//!
+//! Quotas are atomic counters, incremented with a conditional update. Buckets
+//! hold the time at which they are full again in `fullAt`, and are updated
+//! with a conditional write on the value that was read, retried when another
+//! instance took a token first. Both expire through the `ttl` attribute.
+
+use super::{RateLimiter, TokenBucket};
+use crate::error::Error;
+use async_trait::async_trait;
+use aws_sdk_dynamodb::{model::AttributeValue, types::SdkError, Client};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use tracing::{instrument, warn};
+
+/// Attribute holding the expiry of an item, in seconds since the epoch
+const TTL: &str = "ttl";
+
+/// Attempts at updating a bucket that other instances keep updating
+const MAX_ATTEMPTS: usize = 5;
+
+/// DynamoDB rate limiter
+pub struct DynamoDBRateLimiter {
+    client: Client,
+    table_name: String,
+}
+
+impl DynamoDBRateLimiter {
+    pub fn new(client: Client, table_name: String) -> DynamoDBRateLimiter {
+        DynamoDBRateLimiter { client, table_name }
+    }
+
+    /// Read when a bucket is full again
+    async fn get_full_at(&self, key: &str) -> Result<Option<DateTime<Utc>>, Error> {
+        let res = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("limitKey", AttributeValue::S(key.to_owned()))
+            .consistent_read(true)
+            .send()
+            .await?;
+
+        Ok(res
+            .item
+            .as_ref()
+            .and_then(|item| item.get("fullAt"))
+            .and_then(|value| value.as_n().ok())
+            .and_then(|value| value.parse().ok())
+            .and_then(|millis| Utc.timestamp_millis_opt(millis).single()))
+    }
+
+    /// Write when a bucket is full again, if it still holds `previous`
+    ///
+    /// Returns whether the bucket was written.
+    async fn put_full_at(
+        &self,
+        key: &str,
+        previous: Option<DateTime<Utc>>,
+        full_at: DateTime<Utc>,
+    ) -> Result<bool, Error> {
+        let req = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("limitKey", AttributeValue::S(key.to_owned()))
+            .item(
+                "fullAt",
+                AttributeValue::N(full_at.timestamp_millis().to_string()),
+            )
+            // The bucket is full again once expired
+            .item(
+                TTL,
+                AttributeValue::N((full_at.timestamp() + 1).to_string()),
+            )
+            .expression_attribute_names("#key", "limitKey")
+            .expression_attribute_names("#fullAt", "fullAt");
+        let req = match previous {
+            Some(previous) => req
+                .condition_expression("#fullAt = :previous")
+                .expression_attribute_values(
+                    ":previous",
+                    AttributeValue::N(previous.timestamp_millis().to_string()),
+                ),
+            None => req.condition_expression(
+                "attribute_not_exists(#key) OR attribute_not_exists(#fullAt)",
+            ),
+        };
+
+        match req.send().await {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[async_trait]
+impl RateLimiter for DynamoDBRateLimiter {
+    /// Take a token
+    #[instrument(skip(self))]
+    async fn take(
+        &self,
+        key: &str,
+        bucket: &TokenBucket,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Duration>, Error> {
+        for _ in 0..MAX_ATTEMPTS {
+            let previous = self.get_full_at(key).await?;
+            let full_at = match bucket.take(previous, now) {
+                Ok(full_at) => full_at,
+                Err(wait) => return Ok(Some(wait)),
+            };
+            if self.put_full_at(key, previous, full_at).await? {
+                return Ok(None);
+            }
+            warn!("Bucket '{}' changed while taking a token", key);
+        }
+
+        Err(Error::ConflictError("Bucket keeps changing"))
+    }
+
+    /// Count a use of a quota
+    #[instrument(skip(self))]
+    async fn count(&self, key: &str, limit: u32, resets_at: DateTime<Utc>) -> Result<bool, Error> {
+        let res = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("limitKey", AttributeValue::S(key.to_owned()))
+            .update_expression("SET #count = if_not_exists(#count, :zero) + :one, #ttl = :ttl")
+            .condition_expression("attribute_not_exists(#count) OR #count < :limit")
+            .expression_attribute_names("#count", "count")
+            .expression_attribute_names("#ttl", TTL)
+            .expression_attribute_values(":zero", AttributeValue::N("0".to_owned()))
+            .expression_attribute_values(":one", AttributeValue::N("1".to_owned()))
+            .expression_attribute_values(":limit", AttributeValue::N(limit.to_string()))
+            .expression_attribute_values(
+                ":ttl",
+                AttributeValue::N(resets_at.timestamp().to_string()),
+            )
+            .send()
+            .await;
+
+        match res {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Give back a use of a quota
+    ///
+    /// Counters that are already gone, or at zero, are left as they are.
+    #[instrument(skip(self))]
+    async fn uncount(&self, key: &str) -> Result<(), Error> {
+        let res = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("limitKey", AttributeValue::S(key.to_owned()))
+            .update_expression("SET #count = #count - :one")
+            .condition_expression("#count > :zero")
+            .expression_attribute_names("#count", "count")
+            .expression_attribute_values(":zero", AttributeValue::N("0".to_owned()))
+            .expression_attribute_values(":one", AttributeValue::N("1".to_owned()))
+            .send()
+            .await;
+
+        match res {
+            Ok(_) => Ok(()),
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() =>
+            {
+                Ok(())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ratelimit::conformance,
+        store::dynamodb::tests::{get_mock_config, get_request_builder, ConnectEvent},
+    };
+    use aws_smithy_client::test_connection::TestConnection;
+    use aws_smithy_http::body::SdkBody;
+
+    const CONFLICT: &str = r#"{
+        "__type": "com.amazonaws.dynamodb.v20120810#ConditionalCheckFailedException",
+        "message": "The conditional request failed"
+    }"#;
+
+    async fn get_limiter(
+        events: Vec<ConnectEvent>,
+    ) -> (DynamoDBRateLimiter, TestConnection<SdkBody>) {
+        let conn = TestConnection::new(events);
+        let client = Client::from_conf(get_mock_config(conn.clone()).await);
+
+        (DynamoDBRateLimiter::new(client, "limits".to_owned()), conn)
+    }
+
+    /// Event answering a request with the given status and body
+    fn event(status: u16, body: &str) -> ConnectEvent {
+        (
+            get_request_builder().body(SdkBody::from("{}")).unwrap(),
+            http::Response::builder()
+                .status(status)
+                .body(SdkBody::from(body.to_owned()))
+                .unwrap(),
+        )
+    }
+
+    /// GetItem response for a bucket full at `full_at`
+    fn bucket_item(full_at: DateTime<Utc>) -> String {
+        format!(
+            r#"{{"Item": {{"limitKey": {{"S": "key"}}, "fullAt": {{"N": "{}"}}}}}}"#,
+            full_at.timestamp_millis()
+        )
+    }
+
+    fn request_body(conn: &TestConnection<SdkBody>, index: usize) -> serde_json::Value {
+        serde_json::from_slice(conn.requests()[index].actual.body().bytes().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_take() {
+        // GIVEN a table replaying the conformance scenario
+        let now = conformance::get_now();
+        let refill = Duration::seconds(30);
+        let (limiter, conn) = get_limiter(vec![
+            event(200, "{}"),
+            event(200, "{}"),
+            event(200, &bucket_item(now + refill)),
+            event(200, "{}"),
+            event(200, &bucket_item(now + refill * 2)),
+            event(200, &bucket_item(now + refill * 2)),
+            event(200, "{}"),
+            event(200, "{}"),
+            event(200, "{}"),
+        ])
+        .await;
+
+        // WHEN taking tokens
+        // THEN the bucket limits them
+        conformance::take(&limiter).await;
+
+        // AND the bucket is written on the value that was read
+        let first = request_body(&conn, 1);
+        assert_eq!(first["TableName"], "limits");
+        assert_eq!(
+            first["Item"]["fullAt"]["N"],
+            (now + refill).timestamp_millis().to_string()
+        );
+        let second = request_body(&conn, 3);
+        assert_eq!(
+            second["ExpressionAttributeValues"][":previous"]["N"],
+            (now + refill).timestamp_millis().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_take_contended() {
+        // GIVEN a bucket that another instance writes first
+        let now = conformance::get_now();
+        let (limiter, conn) = get_limiter(vec![
+            event(200, "{}"),
+            event(400, CONFLICT),
+            event(200, &bucket_item(now + Duration::seconds(30))),
+            event(200, "{}"),
+        ])
+        .await;
+
+        // WHEN taking a token
+        let res = limiter
+            .take("key", &TokenBucket::per_minute(2), now)
+            .await
+            .unwrap();
+
+        // THEN it is taken on the updated bucket
+        assert_eq!(res, None);
+        assert_eq!(
+            request_body(&conn, 3)["Item"]["fullAt"]["N"],
+            (now + Duration::seconds(60)).timestamp_millis().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_count() {
+        let (limiter, conn) = get_limiter(vec![
+            event(200, "{}"),
+            event(200, "{}"),
+            event(400, CONFLICT),
+            event(200, "{}"),
+        ])
+        .await;
+
+        conformance::count(&limiter).await;
+
+        let body = request_body(&conn, 0);
+        assert_eq!(body["Key"]["limitKey"]["S"], "key");
+        assert_eq!(body["ExpressionAttributeValues"][":limit"]["N"], "2");
+    }
+
+    #[tokio::test]
+    async fn test_uncount() {
+        let (limiter, conn) = get_limiter(vec![
+            event(200, "{}"),
+            event(200, "{}"),
+            event(200, "{}"),
+            event(400, CONFLICT),
+        ])
+        .await;
+
+        conformance::uncount(&limiter).await;
+
+        let body = request_body(&conn, 1);
+        assert_eq!(body["Key"]["limitKey"]["S"], "key");
+        assert_eq!(body["UpdateExpression"], "SET #count = #count - :one");
+    }
+
+    #[tokio::test]
+    async fn test_uncount_missing() {
+        // GIVEN a counter that expired
+        let (limiter, _) = get_limiter(vec![event(400, CONFLICT)]).await;
+
+        // WHEN giving back a use
+        let res = limiter.uncount("key").await;
+
+        // THEN there is nothing to give back
+        assert!(res.is_ok());
+    }
+}
@@ -0,0 +1,119 @@
+//! # In-memory rate limiter
+//!
+//! Rate limiter keeping its buckets and counters in a `HashMap`, for tests and
+//! single instance deployments.
+
+use super::{RateLimiter, TokenBucket};
+use crate::error::Error;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use std::{collections::HashMap, sync::Mutex};
+use tracing::instrument;
+
+/// In-memory rate limiter
+#[derive(Default)]
+pub struct MemoryRateLimiter {
+    /// When each bucket is full again
+    buckets: Mutex<HashMap<String, DateTime<Utc>>>,
+    /// Count of each quota, and when it resets
+    counters: Mutex<HashMap<String, (u32, DateTime<Utc>)>>,
+}
+
+impl MemoryRateLimiter {
+    pub fn new() -> MemoryRateLimiter {
+        MemoryRateLimiter::default()
+    }
+}
+
+#[async_trait]
+impl RateLimiter for MemoryRateLimiter {
+    /// Take a token
+    #[instrument(skip(self))]
+    async fn take(
+        &self,
+        key: &str,
+        bucket: &TokenBucket,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Duration>, Error> {
+        let mut buckets = self
+            .buckets
+            .lock()
+            .map_err(|_| Error::InternalError("Rate limiter lock poisoned"))?;
+        // Full buckets hold no information
+        buckets.retain(|_, full_at| *full_at > now);
+
+        match bucket.take(buckets.get(key).copied(), now) {
+            Ok(full_at) => {
+                buckets.insert(key.to_owned(), full_at);
+                Ok(None)
+            }
+            Err(wait) => Ok(Some(wait)),
+        }
+    }
+
+    /// Count a use of a quota
+    #[instrument(skip(self))]
+    async fn count(&self, key: &str, limit: u32, resets_at: DateTime<Utc>) -> Result<bool, Error> {
+        let mut counters = self
+            .counters
+            .lock()
+            .map_err(|_| Error::InternalError("Rate limiter lock poisoned"))?;
+        let now = Utc::now();
+        counters.retain(|_, (_, resets_at)| *resets_at > now);
+
+        let (count, _) = counters.entry(key.to_owned()).or_insert((0, resets_at));
+        if *count >= limit {
+            return Ok(false);
+        }
+        *count += 1;
+
+        Ok(true)
+    }
+
+    /// Give back a use of a quota
+    #[instrument(skip(self))]
+    async fn uncount(&self, key: &str) -> Result<(), Error> {
+        let mut counters = self
+            .counters
+            .lock()
+            .map_err(|_| Error::InternalError("Rate limiter lock poisoned"))?;
+        if let Some((count, _)) = counters.get_mut(key) {
+            *count = count.saturating_sub(1);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ratelimit::conformance;
+
+    #[tokio::test]
+    async fn test_take() {
+        conformance::take(&MemoryRateLimiter::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_count() {
+        conformance::count(&MemoryRateLimiter::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_uncount() {
+        conformance::uncount(&MemoryRateLimiter::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_count_resets() {
+        let limiter = MemoryRateLimiter::new();
+        let past = Utc::now() - Duration::seconds(1);
+
+        assert!(limiter.count("key", 1, past).await.unwrap());
+        assert!(limiter
+            .count("key", 1, Utc::now() + Duration::days(1))
+            .await
+            .unwrap());
+    }
+}
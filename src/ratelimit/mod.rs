@@ -0,0 +1,121 @@
+//! # Rate limiting
+//!
+//! Token buckets and counters shared by all instances of the API, used to
+//! throttle submissions and enforce quotas.
+//!
+//! Token buckets are implemented with the generic cell rate algorithm (GCRA):
+//! instead of a token count, a bucket only holds the time at which it is full
+//! again, which makes a bucket a single value to update.
+
+use crate::error::Error;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+
+#[cfg(test)]
+pub(crate) mod conformance;
+mod dynamodb;
+mod memory;
+
+pub use dynamodb::DynamoDBRateLimiter;
+pub use memory::MemoryRateLimiter;
+
+/// Size and refill rate of a token bucket
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TokenBucket {
+    /// Tokens the bucket holds when full
+    pub capacity: u32,
+    /// Time to refill a single token
+    pub refill: Duration,
+}
+
+impl TokenBucket {
+    /// Bucket allowing `per_minute` requests a minute, in bursts of up to as
+    /// many requests
+    pub fn per_minute(per_minute: u32) -> Self {
+        TokenBucket {
+            capacity: per_minute.max(1),
+            refill: Duration::minutes(1) / i32::try_from(per_minute.max(1)).unwrap_or(i32::MAX),
+        }
+    }
+
+    /// Take a token from a bucket that is full at `full_at`, if any
+    ///
+    /// Returns when the bucket is full again once the token is taken, or how
+    /// long to wait for a token if the bucket is empty.
+    pub fn take(
+        &self,
+        full_at: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Result<DateTime<Utc>, Duration> {
+        let full_at = full_at.filter(|full_at| *full_at > now).unwrap_or(now) + self.refill;
+        let tolerance = self.refill * i32::try_from(self.capacity).unwrap_or(i32::MAX);
+
+        match full_at - now {
+            wait if wait > tolerance => Err(wait - tolerance),
+            _ => Ok(full_at),
+        }
+    }
+}
+
+/// Trait for rate limiter backends
+#[async_trait]
+pub trait RateLimiter: Send + Sync {
+    /// Take a token from the bucket under `key`
+    ///
+    /// Returns `None` if a token was taken, or how long to wait for one.
+    async fn take(
+        &self,
+        key: &str,
+        bucket: &TokenBucket,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Duration>, Error>;
+
+    /// Count one use of the quota under `key`, unless `limit` uses were
+    /// already counted
+    ///
+    /// Returns whether the use was counted. Counters are dropped once
+    /// `resets_at` is past.
+    async fn count(&self, key: &str, limit: u32, resets_at: DateTime<Utc>) -> Result<bool, Error>;
+
+    /// Give back a use of the quota under `key` that was counted
+    async fn uncount(&self, key: &str) -> Result<(), Error>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_refills() {
+        let bucket = TokenBucket::per_minute(3);
+        let now = conformance::get_now();
+
+        // GIVEN an empty bucket
+        let mut full_at = None;
+        for _ in 0..3 {
+            full_at = Some(bucket.take(full_at, now).unwrap());
+        }
+        assert_eq!(bucket.take(full_at, now), Err(Duration::seconds(20)));
+
+        // WHEN it stays unused for a long time
+        let later = now + Duration::hours(1);
+
+        // THEN it is full again, but not more
+        for _ in 0..3 {
+            full_at = Some(bucket.take(full_at, later).unwrap());
+        }
+        assert!(bucket.take(full_at, later).is_err());
+    }
+
+    #[test]
+    fn bucket_per_minute() {
+        assert_eq!(
+            TokenBucket::per_minute(60),
+            TokenBucket {
+                capacity: 60,
+                refill: Duration::seconds(1)
+            }
+        );
+        assert_eq!(TokenBucket::per_minute(0).capacity, 1);
+    }
+}
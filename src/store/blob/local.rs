@@ -0,0 +1,115 @@
+//! # Local filesystem blob store
+//!
+//! Blob store keeping each blob in a file under a root directory, for tests
+//! and local development.
+
+use super::BlobStore;
+use crate::error::Error;
+use async_trait::async_trait;
+use std::{io::ErrorKind, path::PathBuf};
+use tracing::{info, instrument};
+
+/// Local filesystem blob store implementation.
+pub struct LocalBlobStore {
+    root: PathBuf,
+}
+
+impl LocalBlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> LocalBlobStore {
+        LocalBlobStore { root: root.into() }
+    }
+
+    /// Path of the file holding a blob
+    ///
+    /// Keys are only ever created by `blob_key`, but are still checked so
+    /// that a corrupted reference cannot read outside of the root.
+    fn path(&self, key: &str) -> Result<PathBuf, Error> {
+        if key
+            .split('/')
+            .any(|part| part.is_empty() || part == "." || part == "..")
+            || key.contains('\\')
+        {
+            return Err(Error::BlobError(format!("invalid blob key '{}'", key)));
+        }
+
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+    /// Write blob
+    #[instrument(skip(self, data))]
+    async fn put(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        info!("Writing blob '{}' to {:?}", key, self.root);
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await.map_err(io_error)?;
+        }
+        tokio::fs::write(path, data).await.map_err(io_error)?;
+
+        Ok(())
+    }
+
+    /// Read blob
+    #[instrument(skip(self))]
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        info!("Reading blob '{}' from {:?}", key, self.root);
+        match tokio::fs::read(self.path(key)?).await {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(io_error(err)),
+        }
+    }
+}
+
+fn io_error(err: std::io::Error) -> Error {
+    Error::BlobError(format!("{}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn local_put_get() {
+        // GIVEN an empty local blob store
+        let dir = tempfile::tempdir().unwrap();
+        let blobs = LocalBlobStore::new(dir.path());
+
+        // WHEN putting a blob
+        blobs.put("sha256/abc", b"hello").await.unwrap();
+
+        // THEN it can be read back
+        assert_eq!(
+            blobs.get("sha256/abc").await.unwrap(),
+            Some(b"hello".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn local_get_missing() {
+        // GIVEN an empty local blob store
+        let dir = tempfile::tempdir().unwrap();
+        let blobs = LocalBlobStore::new(dir.path());
+
+        // WHEN getting a blob that was never written
+        let res = blobs.get("sha256/abc").await.unwrap();
+
+        // THEN there is no blob
+        assert_eq!(res, None);
+    }
+
+    #[tokio::test]
+    async fn local_invalid_key() {
+        // GIVEN an empty local blob store
+        let dir = tempfile::tempdir().unwrap();
+        let blobs = LocalBlobStore::new(dir.path());
+
+        // WHEN getting a blob outside of the root
+        let res = blobs.get("../secret").await;
+
+        // THEN the key is refused
+        assert!(matches!(res, Err(Error::BlobError(_))));
+    }
+}
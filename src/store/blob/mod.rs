@@ -0,0 +1,51 @@
+//! # Blob stores
+//!
+//! Content-addressed storage for payloads too large to keep in a store item,
+//! such as the `files` of a large project. Blobs are keyed by the SHA-256 of
+//! their contents, so writing the same payload twice stores it once.
+
+use crate::error::Error;
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+
+mod local;
+mod s3;
+
+pub use local::LocalBlobStore;
+pub use s3::S3BlobStore;
+
+/// Trait for storing and retrieving blobs by key
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    /// Store a blob, replacing any blob with the same key
+    async fn put(&self, key: &str, data: &[u8]) -> Result<(), Error>;
+
+    /// Retrieve a blob, or `None` if there is no blob with that key
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error>;
+}
+
+/// Content-addressed key of a blob
+pub fn blob_key(data: &[u8]) -> String {
+    let digest = Sha256::digest(data);
+    let hex = digest
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+
+    format!("sha256/{}", hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blob_key_content_addressed() {
+        assert_eq!(
+            blob_key(b"hello"),
+            "sha256/2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        assert_eq!(blob_key(b"hello"), blob_key(b"hello"));
+        assert_ne!(blob_key(b"hello"), blob_key(b"world"));
+    }
+}
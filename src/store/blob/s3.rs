@@ -0,0 +1,72 @@
+//! # S3 blob store
+//!
+//! Blob store implementation using the AWS SDK for S3.
+
+use super::BlobStore;
+use crate::error::Error;
+use async_trait::async_trait;
+use aws_sdk_s3::{
+    types::{ByteStream, SdkError},
+    Client,
+};
+use tracing::{info, instrument};
+
+/// S3 blob store implementation.
+pub struct S3BlobStore {
+    client: Client,
+    bucket_name: String,
+}
+
+impl S3BlobStore {
+    pub fn new(client: Client, bucket_name: String) -> S3BlobStore {
+        S3BlobStore {
+            client,
+            bucket_name,
+        }
+    }
+}
+
+#[async_trait]
+impl BlobStore for S3BlobStore {
+    /// Put object
+    #[instrument(skip(self, data))]
+    async fn put(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        info!("Putting object '{}' into S3 bucket", key);
+        self.client
+            .put_object()
+            .bucket(&self.bucket_name)
+            .key(key)
+            .body(ByteStream::from(data.to_vec()))
+            .send()
+            .await
+            .map_err(|err| Error::BlobError(format!("{}", err)))?;
+
+        Ok(())
+    }
+
+    /// Get object
+    #[instrument(skip(self))]
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        info!("Getting object '{}' from S3 bucket", key);
+        let res = self
+            .client
+            .get_object()
+            .bucket(&self.bucket_name)
+            .key(key)
+            .send()
+            .await;
+
+        let res = match res {
+            Ok(res) => res,
+            Err(SdkError::ServiceError(err)) if err.err().is_no_such_key() => return Ok(None),
+            Err(err) => return Err(Error::BlobError(format!("{}", err))),
+        };
+        let data = res
+            .body
+            .collect()
+            .await
+            .map_err(|err| Error::BlobError(format!("{}", err)))?;
+
+        Ok(Some(data.into_bytes().to_vec()))
+    }
+}
@@ -0,0 +1,362 @@
+//! # Store conformance suite
+//!
+//! Scenarios that every `Store` implementation must pass. Each backend runs
+//! them from its own test module, with whatever setup it needs.
+
+use super::{IdempotencyRecord, RetentionPolicy, Store, TestRunQuery};
+use crate::{
+    error::Error,
+    model::{Test, TestRun, TestRunStatus, TestStatus},
+};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use std::collections::{BTreeMap, HashMap};
+
+/// Testrun used by the scenarios
+pub fn get_testrun() -> TestRun {
+    TestRun {
+        id: "1".to_owned(),
+        user_id: Some("user".to_owned()),
+        challenge_id: Some("challenge".to_owned()),
+        files: HashMap::from([("main.py".to_owned(), "print('hello')".into())]),
+        language: "python".to_owned(),
+        status: TestRunStatus::Queued,
+        tests: vec![Test {
+            message: "".to_owned(),
+            name: "test_hello".to_owned(),
+            status: TestStatus::Passed,
+            actual_output: "hello".to_owned(),
+            expected_output: "hello".to_owned(),
+        }],
+        version: 1,
+        created_at: Some(Utc.with_ymd_and_hms(2023, 4, 12, 9, 30, 0).unwrap()),
+    }
+}
+
+/// `get_testrun()` in `status`, without test results
+pub fn get_testrun_with_status(status: TestRunStatus) -> TestRun {
+    TestRun {
+        status,
+        tests: vec![],
+        ..get_testrun()
+    }
+}
+
+/// Updated version of `get_testrun()`, used to check overwrites
+pub fn get_updated_testrun() -> TestRun {
+    TestRun {
+        status: TestRunStatus::Running,
+        version: 2,
+        ..get_testrun()
+    }
+}
+
+/// Getting an unknown id returns `None`
+pub async fn get_missing(store: &dyn Store) {
+    assert_eq!(store.get("1").await.unwrap(), None);
+}
+
+/// A testrun can be read back after being stored
+pub async fn put_get(store: &dyn Store) {
+    store.put(&get_testrun()).await.unwrap();
+
+    assert_eq!(store.get("1").await.unwrap(), Some(get_testrun()));
+}
+
+/// Storing a testrun with an existing id replaces it
+pub async fn put_overwrite(store: &dyn Store) {
+    store.put(&get_testrun()).await.unwrap();
+    store.put(&get_updated_testrun()).await.unwrap();
+
+    assert_eq!(store.get("1").await.unwrap(), Some(get_updated_testrun()));
+}
+
+/// A write is rejected unless it follows the stored version
+pub async fn put_conflict(store: &dyn Store) {
+    store.put(&get_testrun()).await.unwrap();
+
+    // Creating the same testrun again
+    let res = store.put(&get_testrun()).await;
+    assert!(matches!(res, Err(Error::ConflictError(_))));
+
+    // Skipping a version
+    let skipped = TestRun {
+        version: 3,
+        ..get_updated_testrun()
+    };
+    let res = store.put(&skipped).await;
+    assert!(matches!(res, Err(Error::ConflictError(_))));
+
+    assert_eq!(store.get("1").await.unwrap(), Some(get_testrun()));
+}
+
+/// Updating a testrun that doesn't exist is rejected
+pub async fn put_missing_conflict(store: &dyn Store) {
+    let res = store.put(&get_updated_testrun()).await;
+
+    assert!(matches!(res, Err(Error::ConflictError(_))));
+    assert_eq!(store.get("1").await.unwrap(), None);
+}
+
+/// A deleted testrun can no longer be read
+pub async fn delete(store: &dyn Store) {
+    store.put(&get_testrun()).await.unwrap();
+    store.delete("1").await.unwrap();
+
+    assert_eq!(store.get("1").await.unwrap(), None);
+}
+
+/// Deleting an unknown id succeeds
+pub async fn delete_missing(store: &dyn Store) {
+    store.delete("1").await.unwrap();
+}
+
+/// Testruns with various owners, challenges, languages and statuses
+pub fn get_query_testruns() -> Vec<TestRun> {
+    let testrun = |id: &str, user: &str, challenge: &str, language: &str, status| TestRun {
+        id: id.to_owned(),
+        user_id: Some(user.to_owned()),
+        challenge_id: Some(challenge.to_owned()),
+        language: language.to_owned(),
+        status,
+        ..get_testrun()
+    };
+
+    vec![
+        testrun("a", "alice", "c1", "python", TestRunStatus::Queued),
+        testrun("b", "alice", "c2", "rust", TestRunStatus::Queued),
+        testrun("c", "bob", "c1", "python", TestRunStatus::Running),
+        testrun("d", "alice", "c1", "python", TestRunStatus::Running),
+    ]
+}
+
+/// Querying returns testruns matching every filter
+pub async fn query_filters(store: &dyn Store) {
+    for testrun in get_query_testruns() {
+        store.put(&testrun).await.unwrap();
+    }
+    let ids = |page: super::TestRunPage| {
+        page.testruns
+            .into_iter()
+            .map(|testrun| testrun.id)
+            .collect::<Vec<_>>()
+    };
+
+    let query = TestRunQuery {
+        user_id: Some("alice".to_owned()),
+        limit: 10,
+        ..Default::default()
+    };
+    assert_eq!(ids(store.query(&query).await.unwrap()), ["a", "b", "d"]);
+
+    let query = TestRunQuery {
+        challenge_id: Some("c1".to_owned()),
+        status: Some(TestRunStatus::Running),
+        limit: 10,
+        ..Default::default()
+    };
+    assert_eq!(ids(store.query(&query).await.unwrap()), ["c", "d"]);
+
+    let query = TestRunQuery {
+        user_id: Some("alice".to_owned()),
+        language: Some("rust".to_owned()),
+        limit: 10,
+        ..Default::default()
+    };
+    assert_eq!(ids(store.query(&query).await.unwrap()), ["b"]);
+
+    let query = TestRunQuery {
+        user_id: Some("carol".to_owned()),
+        limit: 10,
+        ..Default::default()
+    };
+    assert_eq!(
+        ids(store.query(&query).await.unwrap()),
+        Vec::<String>::new()
+    );
+}
+
+/// Querying pages through results with a cursor
+pub async fn query_pagination(store: &dyn Store) {
+    for testrun in get_query_testruns() {
+        store.put(&testrun).await.unwrap();
+    }
+
+    let mut query = TestRunQuery {
+        user_id: Some("alice".to_owned()),
+        limit: 2,
+        ..Default::default()
+    };
+    let page = store.query(&query).await.unwrap();
+    assert_eq!(page.testruns.len(), 2);
+    assert_eq!(page.testruns[0].id, "a");
+    assert_eq!(page.testruns[1].id, "b");
+    assert!(page.cursor.is_some());
+
+    query.cursor = page.cursor;
+    let page = store.query(&query).await.unwrap();
+    assert_eq!(page.testruns.len(), 1);
+    assert_eq!(page.testruns[0].id, "d");
+    assert_eq!(page.cursor, None);
+}
+
+/// Expired testruns are purged according to the default `RetentionPolicy`
+pub async fn purge(store: &dyn Store) {
+    let now = Utc::now();
+    let pending = TestRun {
+        id: "pending".to_owned(),
+        ..get_testrun()
+    };
+    let done = TestRun {
+        id: "done".to_owned(),
+        status: TestRunStatus::Passed,
+        ..get_testrun()
+    };
+    store.put(&pending).await.unwrap();
+    store.put(&done).await.unwrap();
+
+    // Nothing has expired yet
+    assert_eq!(store.purge(now).await.unwrap(), 0);
+
+    // Pending testruns expire first
+    assert_eq!(store.purge(now + Duration::days(2)).await.unwrap(), 1);
+    assert_eq!(store.get("pending").await.unwrap(), None);
+    assert_eq!(store.get("done").await.unwrap(), Some(done));
+
+    // Final testruns are kept longer
+    assert_eq!(store.purge(now + Duration::days(91)).await.unwrap(), 1);
+    assert_eq!(store.get("done").await.unwrap(), None);
+}
+
+/// Testruns written without a retention policy never expire
+pub async fn purge_without_retention(store: &dyn Store) {
+    store.put(&get_testrun()).await.unwrap();
+
+    let purged = store
+        .purge(Utc::now() + Duration::days(3650))
+        .await
+        .unwrap();
+
+    assert_eq!(purged, 0);
+    assert_eq!(store.get("1").await.unwrap(), Some(get_testrun()));
+}
+
+/// Retention policy under which testruns expire as soon as they are written
+pub fn get_immediate_retention() -> RetentionPolicy {
+    RetentionPolicy {
+        final_ttl: Duration::zero(),
+        pending_ttl: Duration::zero(),
+    }
+}
+
+/// Expired testruns are hidden before being purged, and can be created again
+///
+/// `store` must expire testruns as soon as they are written, as with
+/// `get_immediate_retention()`.
+pub async fn expired(store: &dyn Store) {
+    store.put(&get_testrun()).await.unwrap();
+
+    assert_eq!(store.get("1").await.unwrap(), None);
+    let query = TestRunQuery {
+        limit: 10,
+        ..Default::default()
+    };
+    assert_eq!(store.query(&query).await.unwrap().testruns, vec![]);
+
+    store.put(&get_testrun()).await.unwrap();
+}
+
+/// Idempotency record used by the scenarios, expiring at `expires_at`
+pub fn get_idempotency_record(expires_at: DateTime<Utc>) -> IdempotencyRecord {
+    IdempotencyRecord {
+        key: "key".to_owned(),
+        request_hash: "hash".to_owned(),
+        status: 201,
+        headers: BTreeMap::from([("etag".to_owned(), "\"1\"".to_owned())]),
+        body: r#"{"message":"Testrun queued"}"#.to_owned(),
+        expires_at,
+    }
+}
+
+/// Expiry one hour from now, truncated to the second like stored expiries
+pub fn in_one_hour() -> DateTime<Utc> {
+    Utc.timestamp_opt(Utc::now().timestamp() + 3600, 0).unwrap()
+}
+
+/// A recorded response can be read back until it expires, and is not
+/// overwritten
+///
+/// The record expires at `expires_at`, such as `in_one_hour()`.
+pub async fn idempotency(store: &dyn Store, expires_at: DateTime<Utc>) {
+    let now = Utc::now();
+    let record = get_idempotency_record(expires_at);
+    assert_eq!(store.get_idempotency("key", now).await.unwrap(), None);
+
+    store.put_idempotency(&record).await.unwrap();
+    assert_eq!(
+        store.get_idempotency("key", now).await.unwrap(),
+        Some(record.clone())
+    );
+
+    let res = store.put_idempotency(&record).await;
+    assert!(matches!(res, Err(Error::ConflictError(_))));
+
+    let later = record.expires_at + Duration::seconds(1);
+    assert_eq!(store.get_idempotency("key", later).await.unwrap(), None);
+}
+
+/// An expired record can be replaced
+pub async fn idempotency_expired(store: &dyn Store) {
+    let expired = get_idempotency_record(Utc.timestamp_opt(0, 0).unwrap());
+    store.put_idempotency(&expired).await.unwrap();
+
+    let record = IdempotencyRecord {
+        request_hash: "other".to_owned(),
+        ..get_idempotency_record(in_one_hour())
+    };
+    store.put_idempotency(&record).await.unwrap();
+
+    assert_eq!(
+        store.get_idempotency("key", Utc::now()).await.unwrap(),
+        Some(record)
+    );
+}
+
+/// A reserved key gets its response once handled, and can only be updated or
+/// released for the request that reserved it
+///
+/// The reservation and the record expire at `expires_at`, such as
+/// `in_one_hour()`.
+pub async fn idempotency_reservation(store: &dyn Store, expires_at: DateTime<Utc>) {
+    let record = get_idempotency_record(expires_at);
+    let reservation =
+        IdempotencyRecord::in_progress("key".to_owned(), "hash".to_owned(), expires_at);
+    store.put_idempotency(&reservation).await.unwrap();
+    assert_eq!(
+        store.get_idempotency("key", Utc::now()).await.unwrap(),
+        Some(reservation)
+    );
+
+    let other = IdempotencyRecord {
+        request_hash: "other".to_owned(),
+        ..record.clone()
+    };
+    let res = store.update_idempotency(&other).await;
+    assert!(matches!(res, Err(Error::ConflictError(_))));
+    store.delete_idempotency("key", "other").await.unwrap();
+
+    store.update_idempotency(&record).await.unwrap();
+    assert_eq!(
+        store.get_idempotency("key", Utc::now()).await.unwrap(),
+        Some(record)
+    );
+
+    store.delete_idempotency("key", "hash").await.unwrap();
+    assert_eq!(
+        store.get_idempotency("key", Utc::now()).await.unwrap(),
+        None
+    );
+    let res = store
+        .update_idempotency(&get_idempotency_record(expires_at))
+        .await;
+    assert!(matches!(res, Err(Error::ConflictError(_))));
+}
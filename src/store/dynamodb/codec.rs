@@ -0,0 +1,850 @@
+//! # DynamoDB item codec
+//!
+//! Conversion between a `TestRun` and a DynamoDB item.
+//!
+//! The AWS SDK and the DynamoDB Streams event model each have their own
+//! `AttributeValue` type. The codec is written against the `Attribute` trait
+//! so that both the store and the stream entrypoint share the same mapping.
+//!
+//! `files` and `tests` can optionally be stored compressed. The `compression`
+//! attribute then records the algorithm, and items without it are read as
+//! uncompressed native attributes.
+
+use crate::{
+    error::CodecError,
+    model::{FileContents, FileEntry, Test, TestRun, TestRunSummary},
+};
+use aws_sdk_dynamodb::{model::AttributeValue, types::Blob};
+use flate2::{read::GzDecoder, write::GzEncoder};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    fmt,
+    io::{Read, Write},
+    str::FromStr,
+};
+
+/// Attribute holding the blob key of `files`, when spilled to a blob store
+pub const FILES_REF: &str = "filesRef";
+/// Attribute holding the blob key of `tests`, when spilled to a blob store
+pub const TESTS_REF: &str = "testsRef";
+
+/// Attribute recording the compression of `files` and `tests`
+pub const COMPRESSION: &str = "compression";
+
+/// Maximum size of a decompressed attribute, against decompression bombs
+const MAX_DECOMPRESSED_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Compression of the `files` and `tests` attributes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Native DynamoDB maps and lists
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    /// Compress a payload
+    ///
+    /// Only fails on I/O errors, which cannot happen when writing to memory.
+    fn compress(&self, data: &[u8]) -> Vec<u8> {
+        let compressed = match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data).and_then(|_| encoder.finish())
+            }
+            Compression::Zstd => zstd::encode_all(data, 0),
+        };
+
+        compressed.expect("compression in memory cannot fail")
+    }
+
+    /// Decompress a payload, up to `MAX_DECOMPRESSED_SIZE` bytes
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        let reader: Box<dyn Read> = match self {
+            Compression::None => return Ok(data.to_vec()),
+            Compression::Gzip => Box::new(GzDecoder::new(data)),
+            Compression::Zstd => Box::new(zstd::Decoder::new(data).map_err(|err| err.to_string())?),
+        };
+
+        let mut decompressed = Vec::new();
+        reader
+            .take(MAX_DECOMPRESSED_SIZE + 1)
+            .read_to_end(&mut decompressed)
+            .map_err(|err| err.to_string())?;
+        if decompressed.len() as u64 > MAX_DECOMPRESSED_SIZE {
+            return Err(format!(
+                "larger than {} bytes decompressed",
+                MAX_DECOMPRESSED_SIZE
+            ));
+        }
+
+        Ok(decompressed)
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Compression {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Trait abstracting over the different `AttributeValue` shapes
+pub trait Attribute: Sized {
+    fn from_s(value: String) -> Self;
+    fn from_n(value: String) -> Self;
+    fn from_b(value: Vec<u8>) -> Self;
+    fn from_bool(value: bool) -> Self;
+    fn from_l(value: Vec<Self>) -> Self;
+    fn from_m(value: HashMap<String, Self>) -> Self;
+
+    fn try_s(&self) -> Option<&str>;
+    fn try_n(&self) -> Option<&str>;
+    fn try_b(&self) -> Option<Cow<'_, [u8]>>;
+    fn try_bool(&self) -> Option<bool>;
+    fn try_l(&self) -> Option<&[Self]>;
+    fn try_m(&self) -> Option<&HashMap<String, Self>>;
+}
+
+impl Attribute for AttributeValue {
+    fn from_s(value: String) -> Self {
+        AttributeValue::S(value)
+    }
+
+    fn from_n(value: String) -> Self {
+        AttributeValue::N(value)
+    }
+
+    fn from_b(value: Vec<u8>) -> Self {
+        AttributeValue::B(Blob::new(value))
+    }
+
+    fn from_bool(value: bool) -> Self {
+        AttributeValue::Bool(value)
+    }
+
+    fn from_l(value: Vec<Self>) -> Self {
+        AttributeValue::L(value)
+    }
+
+    fn from_m(value: HashMap<String, Self>) -> Self {
+        AttributeValue::M(value)
+    }
+
+    fn try_s(&self) -> Option<&str> {
+        self.as_s().ok().map(String::as_str)
+    }
+
+    fn try_n(&self) -> Option<&str> {
+        self.as_n().ok().map(String::as_str)
+    }
+
+    fn try_b(&self) -> Option<Cow<'_, [u8]>> {
+        self.as_b().ok().map(|blob| Cow::Borrowed(blob.as_ref()))
+    }
+
+    fn try_bool(&self) -> Option<bool> {
+        self.as_bool().ok().copied()
+    }
+
+    fn try_l(&self) -> Option<&[Self]> {
+        self.as_l().ok().map(Vec::as_slice)
+    }
+
+    fn try_m(&self) -> Option<&HashMap<String, Self>> {
+        self.as_m().ok()
+    }
+}
+
+/// Convert a `TestRun` into a DynamoDB item
+///
+/// `files` is stored as a map of path to contents and `tests` as a list of
+/// maps, so that the item can be inspected natively in DynamoDB.
+pub fn encode<A: Attribute>(testrun: &TestRun) -> HashMap<String, A> {
+    encode_with(testrun, Compression::None)
+}
+
+/// Convert a `TestRun` into a DynamoDB item, compressing its payload
+///
+/// Unless `compression` is `None`, `files` and `tests` are stored as their
+/// compressed JSON in binary attributes.
+pub fn encode_with<A: Attribute>(
+    testrun: &TestRun,
+    compression: Compression,
+) -> HashMap<String, A> {
+    let (files, tests) = match compression {
+        Compression::None => {
+            let files = testrun
+                .files
+                .iter()
+                .map(|(path, file)| (path.clone(), encode_file(file)))
+                .collect();
+            let tests = testrun.tests.iter().map(encode_test).collect();
+            (A::from_m(files), A::from_l(tests))
+        }
+        _ => {
+            // Sorted so that the same files always compress the same way
+            let files = testrun.files.iter().collect::<BTreeMap<_, _>>();
+            (
+                encode_compressed(&files, compression),
+                encode_compressed(&testrun.tests, compression),
+            )
+        }
+    };
+
+    let mut item = HashMap::new();
+    item.insert("id".to_owned(), A::from_s(testrun.id.clone()));
+    // Only set when present, as index keys cannot be empty
+    if let Some(user_id) = &testrun.user_id {
+        item.insert("userId".to_owned(), A::from_s(user_id.clone()));
+    }
+    if let Some(challenge_id) = &testrun.challenge_id {
+        item.insert("challengeId".to_owned(), A::from_s(challenge_id.clone()));
+    }
+    item.insert("files".to_owned(), files);
+    item.insert("language".to_owned(), A::from_s(testrun.language.clone()));
+    item.insert("status".to_owned(), A::from_s(testrun.status.to_string()));
+    item.insert("tests".to_owned(), tests);
+    item.insert("version".to_owned(), A::from_n(testrun.version.to_string()));
+    if let Some(created_at) = &testrun.created_at {
+        item.insert("createdAt".to_owned(), A::from_s(created_at.to_rfc3339()));
+    }
+    if compression != Compression::None {
+        item.insert(COMPRESSION.to_owned(), A::from_s(compression.to_string()));
+    }
+
+    item
+}
+
+/// Try to convert a DynamoDB item into a `TestRun`
+///
+/// This could fail as the DynamoDB item might be missing some fields or hold
+/// values of the wrong type.
+///
+/// `files` and `tests` are left empty when they were spilled to a blob store,
+/// use `decode_ref` to find their blob keys.
+pub fn decode<A: Attribute>(item: &HashMap<String, A>) -> Result<TestRun, CodecError> {
+    let compression = match item.get(COMPRESSION) {
+        Some(_) => get_parsed(item, COMPRESSION, COMPRESSION)?,
+        None => Compression::None,
+    };
+
+    let files = match (item.contains_key(FILES_REF), compression) {
+        (true, _) => HashMap::new(),
+        (false, Compression::None) => get_m(item, "files", "files")?
+            .iter()
+            .map(|(path, file)| Ok((path.clone(), decode_file(file, &format!("files.{}", path))?)))
+            .collect::<Result<HashMap<_, _>, CodecError>>()?,
+        (false, _) => decode_compressed(item, "files", compression)?,
+    };
+
+    let tests = match (item.contains_key(TESTS_REF), compression) {
+        (true, _) => Vec::new(),
+        (false, Compression::None) => get_l(item, "tests", "tests")?
+            .iter()
+            .enumerate()
+            .map(|(index, test)| {
+                let path = format!("tests[{}]", index);
+                let test = test.try_m().ok_or_else(|| CodecError::InvalidType {
+                    attribute: path.clone(),
+                    expected: "M",
+                })?;
+                decode_test(test, &path)
+            })
+            .collect::<Result<Vec<_>, CodecError>>()?,
+        (false, _) => decode_compressed(item, "tests", compression)?,
+    };
+
+    let summary = decode_summary(item)?;
+    Ok(TestRun {
+        id: summary.id,
+        user_id: summary.user_id,
+        challenge_id: summary.challenge_id,
+        files,
+        language: summary.language,
+        status: summary.status,
+        tests,
+        version: summary.version,
+        created_at: summary.created_at,
+    })
+}
+
+/// Attributes read by `decode_summary`
+///
+/// The `userId-index` and `challengeId-index` of `template.yaml` project these
+/// attributes and `ttl`, so keep them in sync.
+pub const SUMMARY_ATTRIBUTES: [&str; 7] = [
+    "id",
+    "userId",
+    "challengeId",
+    "language",
+    "status",
+    "version",
+    "createdAt",
+];
+
+/// Try to convert a DynamoDB item into a `TestRunSummary`
+///
+/// Only reads `SUMMARY_ATTRIBUTES`, so the item can be projected on them.
+pub fn decode_summary<A: Attribute>(
+    item: &HashMap<String, A>,
+) -> Result<TestRunSummary, CodecError> {
+    Ok(TestRunSummary {
+        id: get_s(item, "id", "id")?,
+        user_id: get_opt_s(item, "userId", "userId")?,
+        challenge_id: get_opt_s(item, "challengeId", "challengeId")?,
+        language: get_s(item, "language", "language")?,
+        status: get_parsed(item, "status", "status")?,
+        // Items written before versioning was introduced count as version 0
+        version: get_opt_version(item, "version", "version")?.unwrap_or_default(),
+        created_at: match item.get("createdAt") {
+            Some(_) => Some(get_parsed(item, "createdAt", "createdAt")?),
+            None => None,
+        },
+    })
+}
+
+/// Blob key stored in a reference attribute, such as `FILES_REF`
+pub fn decode_ref<A: Attribute>(
+    item: &HashMap<String, A>,
+    key: &str,
+) -> Result<Option<String>, CodecError> {
+    get_opt_s(item, key, key)
+}
+
+/// Convert a payload into a binary attribute holding its compressed JSON
+fn encode_compressed<A: Attribute, T: Serialize>(value: &T, compression: Compression) -> A {
+    let json = serde_json::to_vec(value).expect("payloads always serialize to JSON");
+    A::from_b(compression.compress(&json))
+}
+
+fn decode_compressed<A: Attribute, T: DeserializeOwned>(
+    item: &HashMap<String, A>,
+    key: &str,
+    compression: Compression,
+) -> Result<T, CodecError> {
+    let data = get(item, key, key)?
+        .try_b()
+        .ok_or_else(|| CodecError::InvalidType {
+            attribute: key.to_owned(),
+            expected: "B",
+        })?;
+    let invalid = |reason: String| CodecError::InvalidPayload {
+        attribute: key.to_owned(),
+        reason,
+    };
+
+    let json = compression.decompress(&data).map_err(invalid)?;
+    serde_json::from_slice(&json).map_err(|err| invalid(err.to_string()))
+}
+
+/// Convert a file into an attribute
+///
+/// Text files that aren't executable are stored as a plain string, as they
+/// were before binary files were supported. Other files are stored as a map
+/// with the `content` as a string or binary, and the `executable` bit.
+fn encode_file<A: Attribute>(file: &FileEntry) -> A {
+    let content = match &file.contents {
+        FileContents::Text(text) if !file.executable => return A::from_s(text.clone()),
+        FileContents::Text(text) => A::from_s(text.clone()),
+        FileContents::Binary(bytes) => A::from_b(bytes.clone()),
+    };
+
+    let mut item = HashMap::new();
+    item.insert("content".to_owned(), content);
+    item.insert("executable".to_owned(), A::from_bool(file.executable));
+
+    A::from_m(item)
+}
+
+fn decode_file<A: Attribute>(file: &A, path: &str) -> Result<FileEntry, CodecError> {
+    if let Some(text) = file.try_s() {
+        return Ok(FileEntry::text(text));
+    }
+
+    let item = file.try_m().ok_or_else(|| CodecError::InvalidType {
+        attribute: path.to_owned(),
+        expected: "S or M",
+    })?;
+    let content_path = format!("{}.content", path);
+    let content = get(item, "content", &content_path)?;
+    let entry = match (content.try_s(), content.try_b()) {
+        (Some(text), _) => FileEntry::text(text),
+        (None, Some(bytes)) => FileEntry::binary(bytes.into_owned()),
+        (None, None) => {
+            return Err(CodecError::InvalidType {
+                attribute: content_path,
+                expected: "S or B",
+            })
+        }
+    };
+    let executable = match item.get("executable") {
+        Some(executable) => executable
+            .try_bool()
+            .ok_or_else(|| CodecError::InvalidType {
+                attribute: format!("{}.executable", path),
+                expected: "BOOL",
+            })?,
+        None => false,
+    };
+
+    Ok(entry.with_executable(executable))
+}
+
+fn encode_test<A: Attribute>(test: &Test) -> A {
+    let mut item = HashMap::new();
+    item.insert("message".to_owned(), A::from_s(test.message.clone()));
+    item.insert("name".to_owned(), A::from_s(test.name.clone()));
+    item.insert("status".to_owned(), A::from_s(test.status.to_string()));
+    item.insert(
+        "actualOutput".to_owned(),
+        A::from_s(test.actual_output.clone()),
+    );
+    item.insert(
+        "expectedOutput".to_owned(),
+        A::from_s(test.expected_output.clone()),
+    );
+
+    A::from_m(item)
+}
+
+fn decode_test<A: Attribute>(item: &HashMap<String, A>, path: &str) -> Result<Test, CodecError> {
+    let field = |key: &str| get_s(item, key, &format!("{}.{}", path, key));
+
+    Ok(Test {
+        message: field("message")?,
+        name: field("name")?,
+        status: get_parsed(item, "status", &format!("{}.status", path))?,
+        actual_output: field("actualOutput")?,
+        expected_output: field("expectedOutput")?,
+    })
+}
+
+fn get<'a, A: Attribute>(
+    item: &'a HashMap<String, A>,
+    key: &str,
+    path: &str,
+) -> Result<&'a A, CodecError> {
+    item.get(key)
+        .ok_or_else(|| CodecError::MissingAttribute(path.to_owned()))
+}
+
+fn get_s<A: Attribute>(
+    item: &HashMap<String, A>,
+    key: &str,
+    path: &str,
+) -> Result<String, CodecError> {
+    get(item, key, path)?
+        .try_s()
+        .map(str::to_owned)
+        .ok_or_else(|| CodecError::InvalidType {
+            attribute: path.to_owned(),
+            expected: "S",
+        })
+}
+
+fn get_opt_s<A: Attribute>(
+    item: &HashMap<String, A>,
+    key: &str,
+    path: &str,
+) -> Result<Option<String>, CodecError> {
+    match item.get(key) {
+        Some(_) => get_s(item, key, path).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn get_opt_version<A: Attribute>(
+    item: &HashMap<String, A>,
+    key: &str,
+    path: &str,
+) -> Result<Option<u64>, CodecError> {
+    let value = match item.get(key) {
+        Some(value) => value.try_n().ok_or_else(|| CodecError::InvalidType {
+            attribute: path.to_owned(),
+            expected: "N",
+        })?,
+        None => return Ok(None),
+    };
+
+    value
+        .parse()
+        .map(Some)
+        .map_err(|_| CodecError::InvalidValue {
+            attribute: path.to_owned(),
+            value: value.to_owned(),
+        })
+}
+
+fn get_parsed<A: Attribute, T: FromStr>(
+    item: &HashMap<String, A>,
+    key: &str,
+    path: &str,
+) -> Result<T, CodecError> {
+    let value = get_s(item, key, path)?;
+    value.parse().map_err(|_| CodecError::InvalidValue {
+        attribute: path.to_owned(),
+        value,
+    })
+}
+
+fn get_l<'a, A: Attribute>(
+    item: &'a HashMap<String, A>,
+    key: &str,
+    path: &str,
+) -> Result<&'a [A], CodecError> {
+    get(item, key, path)?
+        .try_l()
+        .ok_or_else(|| CodecError::InvalidType {
+            attribute: path.to_owned(),
+            expected: "L",
+        })
+}
+
+fn get_m<'a, A: Attribute>(
+    item: &'a HashMap<String, A>,
+    key: &str,
+    path: &str,
+) -> Result<&'a HashMap<String, A>, CodecError> {
+    get(item, key, path)?
+        .try_m()
+        .ok_or_else(|| CodecError::InvalidType {
+            attribute: path.to_owned(),
+            expected: "M",
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "lambda")]
+    use crate::entrypoints::lambda::dynamodb::model::AttributeValue as StreamAttributeValue;
+    use crate::model::{FileEntry, TestRunStatus, TestStatus};
+
+    fn get_testrun() -> TestRun {
+        TestRun {
+            id: "1".to_owned(),
+            user_id: Some("user".to_owned()),
+            challenge_id: Some("challenge".to_owned()),
+            files: HashMap::from([
+                ("main.py".to_owned(), "print('hello')\n".into()),
+                ("src/lib.py".to_owned(), "".into()),
+                (
+                    "run.sh".to_owned(),
+                    FileEntry::text("#!/bin/sh\n").with_executable(true),
+                ),
+                (
+                    "data.bin".to_owned(),
+                    FileEntry::binary(vec![0, 159, 146, 150]),
+                ),
+                (
+                    "main.wasm".to_owned(),
+                    FileEntry::binary(b"\0asm".to_vec()).with_executable(true),
+                ),
+            ]),
+            language: "python".to_owned(),
+            status: TestRunStatus::Failed,
+            tests: vec![
+                Test {
+                    message: "".to_owned(),
+                    name: "test_hello".to_owned(),
+                    status: TestStatus::Passed,
+                    actual_output: "hello".to_owned(),
+                    expected_output: "hello".to_owned(),
+                },
+                Test {
+                    message: "output mismatch".to_owned(),
+                    name: "test_world".to_owned(),
+                    status: TestStatus::Failed,
+                    actual_output: "hello".to_owned(),
+                    expected_output: "world".to_owned(),
+                },
+            ],
+            version: 3,
+            created_at: None,
+        }
+    }
+
+    #[test]
+    fn codec_round_trip_sdk() {
+        let testrun = get_testrun();
+
+        let item: HashMap<String, AttributeValue> = encode(&testrun);
+
+        assert_eq!(decode(&item), Ok(testrun));
+    }
+
+    #[cfg(feature = "lambda")]
+    #[test]
+    fn codec_round_trip_stream() {
+        let testrun = get_testrun();
+
+        let item: HashMap<String, StreamAttributeValue> = encode(&testrun);
+
+        assert_eq!(decode(&item), Ok(testrun));
+    }
+
+    #[test]
+    fn codec_round_trip_compressed() {
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let testrun = get_testrun();
+
+            let item: HashMap<String, AttributeValue> = encode_with(&testrun, compression);
+
+            assert_eq!(
+                item.get(COMPRESSION),
+                Some(&AttributeValue::S(compression.to_string()))
+            );
+            assert!(item.get("files").unwrap().is_b());
+            assert!(item.get("tests").unwrap().is_b());
+            assert_eq!(decode(&item), Ok(testrun), "{}", compression);
+        }
+    }
+
+    #[cfg(feature = "lambda")]
+    #[test]
+    fn codec_round_trip_compressed_stream() {
+        let testrun = get_testrun();
+
+        let item: HashMap<String, StreamAttributeValue> = encode_with(&testrun, Compression::Zstd);
+
+        assert_eq!(decode(&item), Ok(testrun));
+    }
+
+    #[test]
+    fn codec_decode_unknown_compression() {
+        let mut item: HashMap<String, AttributeValue> =
+            encode_with(&get_testrun(), Compression::Gzip);
+        item.insert(COMPRESSION.to_owned(), AttributeValue::S("lz4".to_owned()));
+
+        assert_eq!(
+            decode(&item),
+            Err(CodecError::InvalidValue {
+                attribute: COMPRESSION.to_owned(),
+                value: "lz4".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn codec_decode_invalid_payload() {
+        let mut item: HashMap<String, AttributeValue> =
+            encode_with(&get_testrun(), Compression::Zstd);
+        item.insert(
+            "files".to_owned(),
+            AttributeValue::from_b(b"not zstd".to_vec()),
+        );
+
+        assert!(matches!(
+            decode(&item),
+            Err(CodecError::InvalidPayload { attribute, .. }) if attribute == "files"
+        ));
+    }
+
+    #[test]
+    fn codec_decode_compressed_wrong_type() {
+        let mut item: HashMap<String, AttributeValue> =
+            encode_with(&get_testrun(), Compression::Gzip);
+        item.insert("tests".to_owned(), AttributeValue::L(Vec::new()));
+
+        assert_eq!(
+            decode(&item),
+            Err(CodecError::InvalidType {
+                attribute: "tests".to_owned(),
+                expected: "B",
+            })
+        );
+    }
+
+    #[test]
+    fn codec_round_trip_empty() {
+        let testrun = TestRun {
+            files: HashMap::new(),
+            tests: Vec::new(),
+            ..get_testrun()
+        };
+
+        let item: HashMap<String, AttributeValue> = encode(&testrun);
+
+        assert_eq!(decode(&item), Ok(testrun));
+    }
+
+    #[test]
+    fn codec_round_trip_without_owner() {
+        let testrun = TestRun {
+            user_id: None,
+            challenge_id: None,
+            ..get_testrun()
+        };
+
+        let item: HashMap<String, AttributeValue> = encode(&testrun);
+
+        assert!(!item.contains_key("userId"));
+        assert!(!item.contains_key("challengeId"));
+        assert_eq!(decode(&item), Ok(testrun));
+    }
+
+    #[test]
+    fn codec_decode_without_version() {
+        let mut item: HashMap<String, AttributeValue> = encode(&get_testrun());
+        item.remove("version");
+
+        assert_eq!(decode(&item).map(|testrun| testrun.version), Ok(0));
+    }
+
+    #[test]
+    fn codec_decode_spilled() {
+        let mut item: HashMap<String, AttributeValue> = encode(&get_testrun());
+        item.remove("files");
+        item.remove("tests");
+        item.insert(
+            FILES_REF.to_owned(),
+            AttributeValue::S("sha256/a".to_owned()),
+        );
+        item.insert(
+            TESTS_REF.to_owned(),
+            AttributeValue::S("sha256/b".to_owned()),
+        );
+
+        let testrun = decode(&item).unwrap();
+
+        assert!(testrun.files.is_empty());
+        assert!(testrun.tests.is_empty());
+        assert_eq!(
+            decode_ref(&item, FILES_REF),
+            Ok(Some("sha256/a".to_owned()))
+        );
+        assert_eq!(
+            decode_ref(&item, TESTS_REF),
+            Ok(Some("sha256/b".to_owned()))
+        );
+    }
+
+    #[test]
+    fn codec_encode_native_types() {
+        let item: HashMap<String, AttributeValue> = encode(&get_testrun());
+
+        assert_eq!(
+            item.get("files").unwrap().as_m().unwrap().get("main.py"),
+            Some(&AttributeValue::S("print('hello')\n".to_owned()))
+        );
+        let files = item.get("files").unwrap().as_m().unwrap();
+        let wasm = files.get("main.wasm").unwrap().as_m().unwrap();
+        assert_eq!(
+            wasm.get("content"),
+            Some(&AttributeValue::B(Blob::new(b"\0asm".to_vec())))
+        );
+        assert_eq!(wasm.get("executable"), Some(&AttributeValue::Bool(true)));
+        assert_eq!(
+            item.get("version"),
+            Some(&AttributeValue::N("3".to_owned()))
+        );
+        let tests = item.get("tests").unwrap().as_l().unwrap();
+        assert_eq!(tests.len(), 2);
+        assert_eq!(
+            tests[1].as_m().unwrap().get("expectedOutput"),
+            Some(&AttributeValue::S("world".to_owned()))
+        );
+    }
+
+    #[test]
+    fn codec_decode_missing_attribute() {
+        let mut item: HashMap<String, AttributeValue> = encode(&get_testrun());
+        item.remove("tests");
+
+        assert_eq!(
+            decode(&item),
+            Err(CodecError::MissingAttribute("tests".to_owned()))
+        );
+    }
+
+    #[test]
+    fn codec_decode_invalid_type() {
+        let mut item: HashMap<String, AttributeValue> = encode(&get_testrun());
+        item.insert("files".to_owned(), AttributeValue::S("{}".to_owned()));
+
+        assert_eq!(
+            decode(&item),
+            Err(CodecError::InvalidType {
+                attribute: "files".to_owned(),
+                expected: "M",
+            })
+        );
+    }
+
+    #[test]
+    fn codec_decode_invalid_file() {
+        let mut item: HashMap<String, AttributeValue> = encode(&get_testrun());
+        let file = HashMap::from([("content".to_owned(), AttributeValue::N("1".to_owned()))]);
+        item.insert(
+            "files".to_owned(),
+            AttributeValue::M(HashMap::from([(
+                "main.py".to_owned(),
+                AttributeValue::M(file),
+            )])),
+        );
+
+        assert_eq!(
+            decode(&item),
+            Err(CodecError::InvalidType {
+                attribute: "files.main.py.content".to_owned(),
+                expected: "S or B",
+            })
+        );
+    }
+
+    #[test]
+    fn codec_decode_invalid_status() {
+        let mut item: HashMap<String, AttributeValue> = encode(&get_testrun());
+        item.insert("status".to_owned(), AttributeValue::S("done".to_owned()));
+
+        assert_eq!(
+            decode(&item),
+            Err(CodecError::InvalidValue {
+                attribute: "status".to_owned(),
+                value: "done".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn codec_decode_invalid_nested_test() {
+        let mut item: HashMap<String, AttributeValue> = encode(&get_testrun());
+        let mut test = HashMap::new();
+        test.insert("name".to_owned(), AttributeValue::S("test".to_owned()));
+        item.insert(
+            "tests".to_owned(),
+            AttributeValue::L(vec![AttributeValue::M(test)]),
+        );
+
+        assert_eq!(
+            decode(&item),
+            Err(CodecError::MissingAttribute("tests[0].message".to_owned()))
+        );
+    }
+}
@@ -1,55 +1,198 @@
 //! # DynamoDB store implementation
 //!
 //! Store implementation using the AWS SDK for DynamoDB.
+//!
+//! DynamoDB items are capped at 400 KB. With a blob store configured, `files`
+//! and `tests` larger than a threshold are written to the blob store instead,
+//! and the item only holds their content-addressed key.
+//!
+//! `files` and `tests` can also be compressed, see `codec::Compression`.
+//!
+//! With a retention policy, items are written with a `ttl` attribute, which
+//! DynamoDB uses to delete them once expired.
+//!
+//! Idempotency records live in a separate table, keyed by `idempotencyKey`,
+//! which expires them through the same `ttl` attribute.
 
-use super::{Store, StoreDelete, StoreGet, StorePut};
+use super::{
+    blob::{blob_key, BlobStore},
+    expected_version, IdempotencyRecord, RetentionPolicy, Store, StoreDelete, StoreGet,
+    StoreIdempotency, StorePurge, StorePut, StoreQuery, TestRunPage, TestRunQuery,
+};
 use crate::{
-    error::Error,
-    model::{Test, TestRun},
+    error::{CodecError, Error},
+    model::TestRun,
 };
 use async_trait::async_trait;
-use aws_sdk_dynamodb::{model::AttributeValue, Client};
-use lambda_http::aws_lambda_events::serde_json::Value;
-use serde::{de::value::MapDeserializer, Deserialize};
-use std::collections::HashMap;
+use aws_sdk_dynamodb::{model::AttributeValue, types::SdkError, Client};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, TimeZone, Utc};
+use codec::{Attribute, Compression, FILES_REF, TESTS_REF};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use tracing::{info, instrument};
 
-mod ext;
-use ext::AttributeValuesExt;
+pub(crate) mod codec;
 
-trait ToStringStringMap {
-    fn to_string_string_map(&self) -> HashMap<String, String>;
-}
-
-impl ToStringStringMap for HashMap<String, Value> {
-    fn to_string_string_map(&self) -> HashMap<String, String> {
-        self.iter()
-            .map(|(k, v)| {
-                let v = match v.clone() {
-                    e @ Value::Number(_) | e @ Value::Bool(_) => e.to_string(),
-                    Value::String(s) => s,
-                    _ => {
-                        println!(r#"Warning : Can not convert field : "{}'s value to String, It will be empty string."#, k);
-                        "".to_string()
-                    }
-                };
-
-                (k.clone(), v)
-            })
-            .collect()
-    }
-}
+/// Attribute holding the expiry of an item, in seconds since the epoch
+///
+/// The table TTL must be enabled on this attribute.
+const TTL: &str = "ttl";
 
 /// DynamoDB store implementation.
 pub struct DynamoDBStore {
     client: Client,
     table_name: String,
+    compression: Compression,
+    retention: Option<RetentionPolicy>,
+    blobs: Option<BlobSpill>,
+    idempotency_table: Option<String>,
+}
+
+/// Blob store for large attributes, and the size above which they are spilled
+struct BlobSpill {
+    store: Box<dyn BlobStore>,
+    threshold: usize,
 }
 
 impl DynamoDBStore {
     pub fn new(client: Client, table_name: String) -> DynamoDBStore {
-        DynamoDBStore { client, table_name }
+        DynamoDBStore {
+            client,
+            table_name,
+            compression: Compression::None,
+            retention: None,
+            blobs: None,
+            idempotency_table: None,
+        }
+    }
+
+    /// Record idempotency keys in `table_name`
+    ///
+    /// Without it, idempotency keys cannot be used and every call of
+    /// `StoreIdempotency` fails, rather than let a retry be handled twice.
+    pub fn with_idempotency_table(mut self, table_name: String) -> Self {
+        self.idempotency_table = Some(table_name);
+        self
+    }
+
+    /// Table recording idempotency keys, see `with_idempotency_table`
+    fn idempotency_table(&self) -> Result<&str, Error> {
+        self.idempotency_table
+            .as_deref()
+            .ok_or(Error::InternalError("No idempotency table is configured"))
+    }
+
+    /// Write a TTL on items according to `retention`
+    pub fn with_retention(mut self, retention: RetentionPolicy) -> Self {
+        self.retention = Some(retention);
+        self
+    }
+
+    /// Compress `files` and `tests` when writing items
+    ///
+    /// Items are always readable whatever their compression.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Spill `files` and `tests` to a blob store when their stored size is
+    /// larger than `threshold` bytes
+    pub fn with_blob_store(mut self, store: Box<dyn BlobStore>, threshold: usize) -> Self {
+        self.blobs = Some(BlobSpill { store, threshold });
+        self
+    }
+
+    /// Replace an attribute of the item by a blob key if it is too large
+    ///
+    /// The size of a compressed attribute is its compressed size, while the
+    /// blob always holds the uncompressed JSON.
+    ///
+    /// The blob is written before the item, so a failed conditional write can
+    /// leave an unreferenced blob behind, but never an item without its blob.
+    async fn spill<T: Serialize>(
+        &self,
+        item: &mut HashMap<String, AttributeValue>,
+        key: &str,
+        ref_key: &str,
+        value: &T,
+    ) -> Result<(), Error> {
+        let blobs = match &self.blobs {
+            Some(blobs) => blobs,
+            None => return Ok(()),
+        };
+
+        let data = serde_json::to_vec(value)
+            .map_err(|_| Error::InternalError("Unable to serialize attribute"))?;
+        let size = match item.get(key).map(AttributeValue::as_b) {
+            Some(Ok(blob)) => blob.as_ref().len(),
+            _ => data.len(),
+        };
+        if size <= blobs.threshold {
+            return Ok(());
+        }
+
+        let blob_key = blob_key(&data);
+        info!("Spilling '{}' to blob '{}'", key, blob_key);
+        blobs.store.put(&blob_key, &data).await?;
+        item.remove(key);
+        item.insert(ref_key.to_owned(), AttributeValue::S(blob_key));
+
+        Ok(())
     }
+
+    /// Convert an item into a `TestRun`, reading spilled attributes back
+    async fn decode(&self, item: HashMap<String, AttributeValue>) -> Result<TestRun, Error> {
+        let mut testrun = TestRun::try_from(&item)?;
+        let blobs = self.blobs.as_ref().map(|blobs| blobs.store.as_ref());
+        rehydrate(&mut testrun, &item, blobs).await?;
+
+        Ok(testrun)
+    }
+}
+
+/// Read back the attributes of an item spilled by `DynamoDBStore::spill`
+/// into `testrun`, decoded from the same item
+///
+/// Shared with the stream entrypoint, whose images reference the same blobs.
+pub(crate) async fn rehydrate<A: Attribute>(
+    testrun: &mut TestRun,
+    item: &HashMap<String, A>,
+    blobs: Option<&dyn BlobStore>,
+) -> Result<(), Error> {
+    if let Some(files) = read_blob(item, FILES_REF, blobs).await? {
+        testrun.files = files;
+    }
+    if let Some(tests) = read_blob(item, TESTS_REF, blobs).await? {
+        testrun.tests = tests;
+    }
+
+    Ok(())
+}
+
+/// Read back an attribute spilled to a blob, if any
+async fn read_blob<A: Attribute, T: DeserializeOwned>(
+    item: &HashMap<String, A>,
+    ref_key: &str,
+    blobs: Option<&dyn BlobStore>,
+) -> Result<Option<T>, Error> {
+    let blob_key = match codec::decode_ref(item, ref_key)? {
+        Some(blob_key) => blob_key,
+        None => return Ok(None),
+    };
+    let blobs = blobs.ok_or(Error::InitError(
+        "TestRun references a blob but no blob store is configured",
+    ))?;
+
+    let data = blobs
+        .get(&blob_key)
+        .await?
+        .ok_or_else(|| Error::BlobError(format!("missing blob '{}'", blob_key)))?;
+    let value = serde_json::from_slice(&data)
+        .map_err(|err| Error::BlobError(format!("invalid blob '{}': {}", blob_key, err)))?;
+
+    Ok(Some(value))
 }
 
 impl Store for DynamoDBStore {}
@@ -69,8 +212,8 @@ impl StoreGet for DynamoDBStore {
             .await?;
 
         Ok(match res.item {
-            Some(item) => Some(item.try_into()?),
-            None => None,
+            Some(item) if !is_expired(&item, Utc::now()) => Some(self.decode(item).await?),
+            _ => None,
         })
     }
 }
@@ -78,17 +221,56 @@ impl StoreGet for DynamoDBStore {
 #[async_trait]
 impl StorePut for DynamoDBStore {
     /// Create or update an item
-    #[instrument(skip(self))]
+    #[instrument(skip(self, testrun), fields(id = %testrun.id))]
     async fn put(&self, testrun: &TestRun) -> Result<(), Error> {
         info!("Putting item with id '{}' into DynamoDB table", testrun.id);
-        self.client
+        let expected = expected_version(testrun);
+        // Items written before versioning was introduced have no version, and
+        // expired items can linger until DynamoDB deletes them
+        let condition = match expected {
+            0 => "attribute_not_exists(#version) OR #version = :expected OR #ttl <= :now",
+            _ => "#version = :expected",
+        };
+        let mut item = codec::encode_with(testrun, self.compression);
+        if let Some(retention) = &self.retention {
+            let expires_at = retention.expires_at(testrun, Utc::now());
+            item.insert(
+                TTL.to_owned(),
+                AttributeValue::N(expires_at.timestamp().to_string()),
+            );
+        }
+        // Sorted so that the same files always give the same blob key
+        let files = testrun.files.iter().collect::<BTreeMap<_, _>>();
+        self.spill(&mut item, "files", FILES_REF, &files).await?;
+        self.spill(&mut item, "tests", TESTS_REF, &testrun.tests)
+            .await?;
+        let mut request = self
+            .client
             .put_item()
             .table_name(&self.table_name)
-            .set_item(Some(testrun.into()))
-            .send()
-            .await?;
+            .set_item(Some(item))
+            .condition_expression(condition)
+            .expression_attribute_names("#version", "version")
+            .expression_attribute_values(":expected", AttributeValue::N(expected.to_string()));
+        if expected == 0 {
+            request = request
+                .expression_attribute_names("#ttl", TTL)
+                .expression_attribute_values(
+                    ":now",
+                    AttributeValue::N(Utc::now().timestamp().to_string()),
+                );
+        }
+        let res = request.send().await;
 
-        Ok(())
+        match res {
+            Ok(_) => Ok(()),
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() =>
+            {
+                Err(Error::ConflictError("TestRun version mismatch"))
+            }
+            Err(err) => Err(err.into()),
+        }
     }
 }
 
@@ -109,24 +291,373 @@ impl StoreDelete for DynamoDBStore {
     }
 }
 
-impl From<&TestRun> for HashMap<String, AttributeValue> {
-    /// Convert a &Product into a DynamoDB item
-    fn from(value: &TestRun) -> HashMap<String, AttributeValue> {
-        let payload: String = serde_json::to_string(&value.files).unwrap();
+#[async_trait]
+impl StoreQuery for DynamoDBStore {
+    /// Query items
+    ///
+    /// Queries by user or challenge use the matching global secondary index,
+    /// other queries fall back to a scan of the table. The remaining filters
+    /// are applied by DynamoDB after reading `limit` items, so a page may hold
+    /// fewer testruns than the limit even when more results exist.
+    ///
+    /// Items are projected on the attributes of a summary, so neither their
+    /// payload nor spilled blobs are read.
+    #[instrument(skip(self))]
+    async fn query(&self, query: &TestRunQuery) -> Result<TestRunPage, Error> {
+        let start_key = query.cursor.as_deref().map(decode_key).transpose()?;
+        let limit = i32::try_from(query.limit).unwrap_or(i32::MAX);
 
-        let mut retval = HashMap::new();
-        retval.insert("id".to_owned(), AttributeValue::S(value.id.clone()));
-        retval.insert(
-            "language".to_owned(),
-            AttributeValue::S(value.language.to_owned()),
-        );
-        retval.insert("payload".to_owned(), AttributeValue::S(payload));
-        retval.insert(
+        // Pick the index to query, if any
+        let index = match (&query.user_id, &query.challenge_id) {
+            (Some(user_id), _) => Some(("userId-index", "userId", user_id)),
+            (None, Some(challenge_id)) => Some(("challengeId-index", "challengeId", challenge_id)),
+            (None, None) => None,
+        };
+
+        // Build the filter expression for the remaining filters
+        let mut names = HashMap::new();
+        let mut values = HashMap::new();
+        let mut filters = Vec::new();
+        let mut add_filter = |key: &str, value: Option<String>| {
+            if let Some(value) = value {
+                names.insert(format!("#{}", key), key.to_owned());
+                values.insert(format!(":{}", key), AttributeValue::S(value));
+                filters.push(format!("#{0} = :{0}", key));
+            }
+        };
+        if index.map(|(_, key, _)| key) != Some("userId") {
+            add_filter("userId", query.user_id.clone());
+        }
+        if index.map(|(_, key, _)| key) != Some("challengeId") {
+            add_filter("challengeId", query.challenge_id.clone());
+        }
+        add_filter("language", query.language.clone());
+        add_filter("status", query.status.map(|status| status.to_string()));
+        let filter = (!filters.is_empty()).then(|| filters.join(" AND "));
+
+        // Only read what a summary needs, and the TTL to skip expired items
+        let mut projection = Vec::new();
+        for key in codec::SUMMARY_ATTRIBUTES.into_iter().chain([TTL]) {
+            names.insert(format!("#{}", key), key.to_owned());
+            projection.push(format!("#{}", key));
+        }
+        let projection = projection.join(", ");
+
+        let (items, last_key) = match index {
+            Some((index_name, key, value)) => {
+                info!("Querying items from DynamoDB index '{}'", index_name);
+                let mut names = names;
+                let mut values = values;
+                names.insert("#key".to_owned(), key.to_owned());
+                values.insert(":key".to_owned(), AttributeValue::S(value.clone()));
+                let res = self
+                    .client
+                    .query()
+                    .table_name(&self.table_name)
+                    .index_name(index_name)
+                    .key_condition_expression("#key = :key")
+                    .projection_expression(projection)
+                    .set_filter_expression(filter)
+                    .set_expression_attribute_names(Some(names))
+                    .set_expression_attribute_values(Some(values))
+                    .set_exclusive_start_key(start_key)
+                    .limit(limit)
+                    .send()
+                    .await?;
+                (res.items, res.last_evaluated_key)
+            }
+            None => {
+                info!("Scanning items from DynamoDB table");
+                let has_filter = filter.is_some();
+                let res = self
+                    .client
+                    .scan()
+                    .table_name(&self.table_name)
+                    .projection_expression(projection)
+                    .set_filter_expression(filter)
+                    .set_expression_attribute_names(Some(names))
+                    .set_expression_attribute_values(has_filter.then_some(values))
+                    .set_exclusive_start_key(start_key)
+                    .limit(limit)
+                    .send()
+                    .await?;
+                (res.items, res.last_evaluated_key)
+            }
+        };
+
+        let now = Utc::now();
+        let testruns = items
+            .unwrap_or_default()
+            .iter()
+            .filter(|item| !is_expired(item, now))
+            .map(codec::decode_summary)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(TestRunPage {
+            testruns,
+            cursor: last_key.as_ref().map(encode_key),
+        })
+    }
+}
+
+#[async_trait]
+impl StorePurge for DynamoDBStore {
+    /// Nothing to do, as DynamoDB deletes expired items itself
+    async fn purge(&self, _now: DateTime<Utc>) -> Result<usize, Error> {
+        Ok(0)
+    }
+}
+
+#[async_trait]
+impl StoreIdempotency for DynamoDBStore {
+    /// Get idempotency record
+    #[instrument(skip(self))]
+    async fn get_idempotency(
+        &self,
+        key: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<IdempotencyRecord>, Error> {
+        let table_name = self.idempotency_table()?;
+
+        let res = self
+            .client
+            .get_item()
+            .table_name(table_name)
+            .key("idempotencyKey", AttributeValue::S(key.to_owned()))
+            .send()
+            .await?;
+
+        match res.item {
+            Some(item) if !is_expired(&item, now) => Ok(Some(decode_idempotency(&item)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Create idempotency record
+    #[instrument(skip(self, record), fields(key = %record.key))]
+    async fn put_idempotency(&self, record: &IdempotencyRecord) -> Result<(), Error> {
+        let table_name = self.idempotency_table()?;
+
+        let res = self
+            .client
+            .put_item()
+            .table_name(table_name)
+            .set_item(Some(encode_idempotency(record)))
+            // Expired items can linger until DynamoDB deletes them
+            .condition_expression("attribute_not_exists(#key) OR #ttl <= :now")
+            .expression_attribute_names("#key", "idempotencyKey")
+            .expression_attribute_names("#ttl", TTL)
+            .expression_attribute_values(
+                ":now",
+                AttributeValue::N(Utc::now().timestamp().to_string()),
+            )
+            .send()
+            .await;
+
+        match res {
+            Ok(_) => Ok(()),
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() =>
+            {
+                Err(Error::ConflictError("Idempotency key already recorded"))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Replace idempotency record
+    #[instrument(skip(self, record), fields(key = %record.key))]
+    async fn update_idempotency(&self, record: &IdempotencyRecord) -> Result<(), Error> {
+        let table_name = self.idempotency_table()?;
+
+        let res = self
+            .client
+            .put_item()
+            .table_name(table_name)
+            .set_item(Some(encode_idempotency(record)))
+            .condition_expression("#hash = :hash")
+            .expression_attribute_names("#hash", "requestHash")
+            .expression_attribute_values(":hash", AttributeValue::S(record.request_hash.clone()))
+            .send()
+            .await;
+
+        match res {
+            Ok(_) => Ok(()),
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() =>
+            {
+                Err(Error::ConflictError(
+                    "Idempotency key held by another request",
+                ))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Delete idempotency record
+    #[instrument(skip(self))]
+    async fn delete_idempotency(&self, key: &str, request_hash: &str) -> Result<(), Error> {
+        let table_name = self.idempotency_table()?;
+
+        let res = self
+            .client
+            .delete_item()
+            .table_name(table_name)
+            .key("idempotencyKey", AttributeValue::S(key.to_owned()))
+            .condition_expression("#hash = :hash")
+            .expression_attribute_names("#hash", "requestHash")
+            .expression_attribute_values(":hash", AttributeValue::S(request_hash.to_owned()))
+            .send()
+            .await;
+
+        match res {
+            Ok(_) => Ok(()),
+            // Held by another request, which must keep it
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() =>
+            {
+                Ok(())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// Convert an `IdempotencyRecord` into a DynamoDB item
+fn encode_idempotency(record: &IdempotencyRecord) -> HashMap<String, AttributeValue> {
+    let headers = record
+        .headers
+        .iter()
+        .map(|(name, value)| (name.clone(), AttributeValue::S(value.clone())))
+        .collect();
+
+    HashMap::from([
+        (
+            "idempotencyKey".to_owned(),
+            AttributeValue::S(record.key.clone()),
+        ),
+        (
+            "requestHash".to_owned(),
+            AttributeValue::S(record.request_hash.clone()),
+        ),
+        (
             "status".to_owned(),
-            AttributeValue::S(value.status.to_owned()),
-        );
+            AttributeValue::N(record.status.to_string()),
+        ),
+        ("headers".to_owned(), AttributeValue::M(headers)),
+        ("body".to_owned(), AttributeValue::S(record.body.clone())),
+        (
+            TTL.to_owned(),
+            AttributeValue::N(record.expires_at.timestamp().to_string()),
+        ),
+    ])
+}
+
+/// Try to convert a DynamoDB item into an `IdempotencyRecord`
+fn decode_idempotency(
+    item: &HashMap<String, AttributeValue>,
+) -> Result<IdempotencyRecord, CodecError> {
+    let get = |key: &str| {
+        item.get(key)
+            .ok_or_else(|| CodecError::MissingAttribute(key.to_owned()))
+    };
+    let get_s = |key: &str| {
+        get(key)?
+            .as_s()
+            .cloned()
+            .map_err(|_| CodecError::InvalidType {
+                attribute: key.to_owned(),
+                expected: "S",
+            })
+    };
+    let get_n = |key: &str| {
+        let value = get(key)?.as_n().map_err(|_| CodecError::InvalidType {
+            attribute: key.to_owned(),
+            expected: "N",
+        })?;
+        value.parse::<i64>().map_err(|_| CodecError::InvalidValue {
+            attribute: key.to_owned(),
+            value: value.clone(),
+        })
+    };
+
+    let status = get_n("status")?;
+    let ttl = get_n(TTL)?;
+    let headers = get("headers")?
+        .as_m()
+        .map_err(|_| CodecError::InvalidType {
+            attribute: "headers".to_owned(),
+            expected: "M",
+        })?
+        .iter()
+        .map(|(name, value)| {
+            let value = value.as_s().map_err(|_| CodecError::InvalidType {
+                attribute: format!("headers.{}", name),
+                expected: "S",
+            })?;
+            Ok((name.clone(), value.clone()))
+        })
+        .collect::<Result<_, CodecError>>()?;
+
+    Ok(IdempotencyRecord {
+        key: get_s("idempotencyKey")?,
+        request_hash: get_s("requestHash")?,
+        status: u16::try_from(status).map_err(|_| CodecError::InvalidValue {
+            attribute: "status".to_owned(),
+            value: status.to_string(),
+        })?,
+        headers,
+        body: get_s("body")?,
+        expires_at: Utc
+            .timestamp_opt(ttl, 0)
+            .single()
+            .ok_or_else(|| CodecError::InvalidValue {
+                attribute: TTL.to_owned(),
+                value: ttl.to_string(),
+            })?,
+    })
+}
+
+/// Whether an item is past its TTL
+///
+/// DynamoDB can take a few days to delete expired items, reads skip them in
+/// the meantime.
+fn is_expired(item: &HashMap<String, AttributeValue>, now: DateTime<Utc>) -> bool {
+    item.get(TTL)
+        .and_then(|ttl| ttl.as_n().ok())
+        .and_then(|ttl| ttl.parse::<i64>().ok())
+        .is_some_and(|ttl| ttl <= now.timestamp())
+}
 
-        retval
+/// Encode a `LastEvaluatedKey` as an opaque cursor
+///
+/// Keys in this table only ever hold string attributes.
+fn encode_key(key: &HashMap<String, AttributeValue>) -> String {
+    let key = key
+        .iter()
+        .filter_map(|(name, value)| Some((name, value.as_s().ok()?)))
+        .collect::<HashMap<_, _>>();
+    URL_SAFE_NO_PAD.encode(serde_json::to_vec(&key).unwrap_or_default())
+}
+
+/// Decode a cursor created by `encode_key` into an `ExclusiveStartKey`
+fn decode_key(cursor: &str) -> Result<HashMap<String, AttributeValue>, Error> {
+    let key: HashMap<String, String> = URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|key| serde_json::from_slice(&key).ok())
+        .ok_or(Error::ClientError("Invalid cursor"))?;
+
+    Ok(key
+        .into_iter()
+        .map(|(name, value)| (name, AttributeValue::S(value)))
+        .collect())
+}
+
+impl From<&TestRun> for HashMap<String, AttributeValue> {
+    /// Convert a &TestRun into a DynamoDB item
+    fn from(value: &TestRun) -> HashMap<String, AttributeValue> {
+        codec::encode(value)
     }
 }
 
@@ -137,38 +668,38 @@ impl TryFrom<HashMap<String, AttributeValue>> for TestRun {
     ///
     /// This could fail as the DynamoDB item might be missing some fields.
     fn try_from(value: HashMap<String, AttributeValue>) -> Result<Self, Self::Error> {
-        let files: HashMap<String, String> =
-            serde_json::from_str(value.get("files").unwrap().as_s().unwrap()).unwrap();
-
-        let tests: Vec<Test> =
-            serde_json::from_str(value.get("tests").unwrap().as_s().unwrap()).unwrap();
-
-        Ok(TestRun {
-            id: value
-                .get_s("id")
-                .ok_or(Error::InternalError("Missing id"))?,
-            language: value
-                .get_s("language")
-                .ok_or(Error::InternalError("Missing language"))?,
-            files,
-            status: value
-                .get_s("status")
-                .ok_or(Error::InternalError("Missing status"))?,
-            tests,
-        })
+        TestRun::try_from(&value)
+    }
+}
+
+impl TryFrom<&HashMap<String, AttributeValue>> for TestRun {
+    type Error = Error;
+
+    /// Try to convert a DynamoDB item into a TestRun
+    ///
+    /// This could fail as the DynamoDB item might be missing some fields.
+    fn try_from(value: &HashMap<String, AttributeValue>) -> Result<Self, Self::Error> {
+        Ok(codec::decode(value)?)
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
-    use crate::error::Error;
-    use aws_sdk_dynamodb::{Client, Config, Credentials, Region};
+    use crate::{
+        model::TestRunSummary,
+        store::{blob::LocalBlobStore, conformance},
+    };
+    use aws_sdk_dynamodb::{config::Builder, Client, Config, Credentials, Region};
     use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
     use aws_smithy_http::body::SdkBody;
+    use base64::engine::general_purpose::STANDARD;
+    use serde_json::json;
+
+    pub type ConnectEvent = (http::Request<SdkBody>, http::Response<SdkBody>);
 
     /// Config for mocking DynamoDB
-    async fn get_mock_config() -> Config {
+    pub async fn get_mock_config(conn: TestConnection<SdkBody>) -> Config {
         let cfg = aws_config::from_env()
             .region(Region::new("eu-west-1"))
             .credentials_provider(Credentials::new(
@@ -181,14 +712,831 @@ mod tests {
             .load()
             .await;
 
-        Config::new(&cfg)
+        Builder::from(&cfg)
+            .http_connector(DynConnector::new(conn))
+            .build()
     }
 
-    fn get_request_builder() -> http::request::Builder {
+    pub fn get_request_builder() -> http::request::Builder {
         http::Request::builder()
             .header("content-type", "application/x-amz-json-1.0")
             .uri(http::uri::Uri::from_static(
                 "https://dynamodb.eu-west-1.amazonaws.com/",
             ))
     }
+
+    /// Store replaying the given requests and responses
+    async fn get_store(events: Vec<ConnectEvent>) -> (DynamoDBStore, TestConnection<SdkBody>) {
+        let conn = TestConnection::new(events);
+        let client = Client::from_conf(get_mock_config(conn.clone()).await);
+
+        (DynamoDBStore::new(client, "test".to_string()), conn)
+    }
+
+    /// Assert that `conn` received the expected requests
+    ///
+    /// The `:now` bound by conditional creates depends on the clock, so it is
+    /// checked against the current time and left out of the expected bodies.
+    fn assert_requests_match(conn: &TestConnection<SdkBody>) {
+        for request in conn.requests().iter() {
+            let target = http::header::HeaderName::from_static("x-amz-target");
+            assert_eq!(
+                request.actual.headers().get(&target),
+                request.expected.headers().get(&target)
+            );
+            let mut actual: serde_json::Value =
+                serde_json::from_slice(request.actual.body().bytes().unwrap()).unwrap();
+            let expected: serde_json::Value =
+                serde_json::from_slice(request.expected.body().bytes().unwrap()).unwrap();
+            if let Some(values) = actual
+                .get_mut("ExpressionAttributeValues")
+                .and_then(|values| values.as_object_mut())
+            {
+                if let Some(now) = values.remove(":now") {
+                    let now: i64 = now["N"].as_str().unwrap().parse().unwrap();
+                    assert!((Utc::now().timestamp() - now).abs() < 5);
+                }
+            }
+            assert_eq!(actual, expected);
+        }
+    }
+
+    /// DynamoDB JSON for the conformance testrun with the given status and version
+    fn get_item(status: &str, version: u64) -> String {
+        format!(
+            r#"{{
+                "id": {{"S": "1"}},
+                "userId": {{"S": "user"}},
+                "challengeId": {{"S": "challenge"}},
+                "files": {{"M": {{"main.py": {{"S": "print('hello')"}}}}}},
+                "language": {{"S": "python"}},
+                "status": {{"S": "{}"}},
+                "tests": {{"L": [{{"M": {{
+                    "message": {{"S": ""}},
+                    "name": {{"S": "test_hello"}},
+                    "status": {{"S": "passed"}},
+                    "actualOutput": {{"S": "hello"}},
+                    "expectedOutput": {{"S": "hello"}}
+                }}}}]}},
+                "version": {{"N": "{}"}},
+                "createdAt": {{"S": "2023-04-12T09:30:00+00:00"}}
+            }}"#,
+            status, version
+        )
+    }
+
+    fn get_item_event(item: Option<&str>) -> ConnectEvent {
+        (
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                .body(SdkBody::from(
+                    r#"{"TableName": "test", "Key": {"id": {"S": "1"}}}"#,
+                ))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(match item {
+                    Some(item) => format!(r#"{{"Item": {}}}"#, item),
+                    None => "{}".to_owned(),
+                }))
+                .unwrap(),
+        )
+    }
+
+    /// Conditional put of `item`, see `assert_requests_match` for `:now`
+    fn put_item_request(item: &str, expected: u64) -> http::Request<SdkBody> {
+        let (condition, names) = match expected {
+            0 => (
+                "attribute_not_exists(#version) OR #version = :expected OR #ttl <= :now",
+                r##"{"#version": "version", "#ttl": "ttl"}"##,
+            ),
+            _ => ("#version = :expected", r##"{"#version": "version"}"##),
+        };
+        get_request_builder()
+            .header("x-amz-target", "DynamoDB_20120810.PutItem")
+            .body(SdkBody::from(format!(
+                r##"{{
+                    "TableName": "test",
+                    "Item": {},
+                    "ConditionExpression": "{}",
+                    "ExpressionAttributeNames": {},
+                    "ExpressionAttributeValues": {{":expected": {{"N": "{}"}}}}
+                }}"##,
+                item, condition, names, expected
+            )))
+            .unwrap()
+    }
+
+    fn put_item_event(item: &str, expected: u64) -> ConnectEvent {
+        (
+            put_item_request(item, expected),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )
+    }
+
+    fn put_item_conflict_event(item: &str, expected: u64) -> ConnectEvent {
+        (
+            put_item_request(item, expected),
+            http::Response::builder()
+                .status(400)
+                .body(SdkBody::from(
+                    r#"{
+                        "__type": "com.amazonaws.dynamodb.v20120810#ConditionalCheckFailedException",
+                        "message": "The conditional request failed"
+                    }"#,
+                ))
+                .unwrap(),
+        )
+    }
+
+    fn delete_item_event() -> ConnectEvent {
+        (
+            get_request_builder()
+                .header("x-amz-target", "DynamoDB_20120810.DeleteItem")
+                .body(SdkBody::from(
+                    r#"{"TableName": "test", "Key": {"id": {"S": "1"}}}"#,
+                ))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )
+    }
+
+    /// Query or scan projected on the attributes of a summary
+    fn query_event(
+        operation: &str,
+        mut request: serde_json::Value,
+        response: serde_json::Value,
+    ) -> ConnectEvent {
+        let names = request
+            .as_object_mut()
+            .unwrap()
+            .entry("ExpressionAttributeNames")
+            .or_insert_with(|| json!({}));
+        let mut projection = Vec::new();
+        for key in codec::SUMMARY_ATTRIBUTES.into_iter().chain([TTL]) {
+            names[format!("#{}", key)] = json!(key);
+            projection.push(format!("#{}", key));
+        }
+        request["ProjectionExpression"] = json!(projection.join(", "));
+
+        (
+            get_request_builder()
+                .header("x-amz-target", format!("DynamoDB_20120810.{}", operation))
+                .body(SdkBody::from(request.to_string()))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(response.to_string()))
+                .unwrap(),
+        )
+    }
+
+    /// DynamoDB JSON for a testrun sharing the payload of the conformance one
+    fn get_testrun_item(testrun: &TestRun) -> serde_json::Value {
+        let mut item: serde_json::Value =
+            serde_json::from_str(&get_item(testrun.status.as_str(), testrun.version)).unwrap();
+        item["id"] = json!({"S": testrun.id});
+        item["userId"] = json!({"S": testrun.user_id});
+        item["challengeId"] = json!({"S": testrun.challenge_id});
+        item["language"] = json!({"S": testrun.language});
+        item
+    }
+
+    /// `get_testrun_item` projected on the attributes of a summary
+    fn get_summary_item(testrun: &TestRun) -> serde_json::Value {
+        let mut item = get_testrun_item(testrun);
+        item.as_object_mut()
+            .unwrap()
+            .retain(|key, _| codec::SUMMARY_ATTRIBUTES.contains(&key.as_str()));
+        item
+    }
+
+    /// Puts of `conformance::get_query_testruns()`
+    fn put_query_testrun_events() -> Vec<ConnectEvent> {
+        conformance::get_query_testruns()
+            .iter()
+            .map(|testrun| put_item_event(&get_testrun_item(testrun).to_string(), 0))
+            .collect()
+    }
+
+    /// Response listing the summaries of the query testruns with these ids
+    fn query_response(ids: &[&str], last_key: Option<serde_json::Value>) -> serde_json::Value {
+        let items = conformance::get_query_testruns()
+            .iter()
+            .filter(|testrun| ids.contains(&testrun.id.as_str()))
+            .map(get_summary_item)
+            .collect::<Vec<_>>();
+        match last_key {
+            Some(last_key) => json!({"Items": items, "LastEvaluatedKey": last_key}),
+            None => json!({"Items": items}),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_missing() {
+        let (store, conn) = get_store(vec![get_item_event(None)]).await;
+
+        conformance::get_missing(&store).await;
+
+        assert_requests_match(&conn);
+    }
+
+    #[tokio::test]
+    async fn test_put_get() {
+        let item = get_item("queued", 1);
+        let (store, conn) =
+            get_store(vec![put_item_event(&item, 0), get_item_event(Some(&item))]).await;
+
+        conformance::put_get(&store).await;
+
+        assert_requests_match(&conn);
+    }
+
+    #[tokio::test]
+    async fn test_put_overwrite() {
+        let item = get_item("queued", 1);
+        let updated = get_item("running", 2);
+        let (store, conn) = get_store(vec![
+            put_item_event(&item, 0),
+            put_item_event(&updated, 1),
+            get_item_event(Some(&updated)),
+        ])
+        .await;
+
+        conformance::put_overwrite(&store).await;
+
+        assert_requests_match(&conn);
+    }
+
+    #[tokio::test]
+    async fn test_put_conflict() {
+        let item = get_item("queued", 1);
+        let (store, conn) = get_store(vec![
+            put_item_event(&item, 0),
+            put_item_conflict_event(&item, 0),
+            put_item_conflict_event(&get_item("running", 3), 2),
+            get_item_event(Some(&item)),
+        ])
+        .await;
+
+        conformance::put_conflict(&store).await;
+
+        assert_requests_match(&conn);
+    }
+
+    #[tokio::test]
+    async fn test_put_missing_conflict() {
+        let (store, conn) = get_store(vec![
+            put_item_conflict_event(&get_item("running", 2), 1),
+            get_item_event(None),
+        ])
+        .await;
+
+        conformance::put_missing_conflict(&store).await;
+
+        assert_requests_match(&conn);
+    }
+
+    #[tokio::test]
+    async fn test_delete() {
+        let item = get_item("queued", 1);
+        let (store, conn) = get_store(vec![
+            put_item_event(&item, 0),
+            delete_item_event(),
+            get_item_event(None),
+        ])
+        .await;
+
+        conformance::delete(&store).await;
+
+        assert_requests_match(&conn);
+    }
+
+    #[tokio::test]
+    async fn test_delete_missing() {
+        let (store, conn) = get_store(vec![delete_item_event()]).await;
+
+        conformance::delete_missing(&store).await;
+
+        assert_requests_match(&conn);
+    }
+
+    #[tokio::test]
+    async fn test_query_index() {
+        // GIVEN a query by user with a language filter and a cursor
+        let cursor = encode_key(&HashMap::from([
+            ("id".to_owned(), AttributeValue::S("0".to_owned())),
+            ("userId".to_owned(), AttributeValue::S("user".to_owned())),
+        ]));
+        let item = get_summary_item(&conformance::get_testrun());
+        let (store, conn) = get_store(vec![query_event(
+            "Query",
+            json!({
+                "TableName": "test",
+                "IndexName": "userId-index",
+                "KeyConditionExpression": "#key = :key",
+                "FilterExpression": "#language = :language",
+                "ExpressionAttributeNames": {"#key": "userId", "#language": "language"},
+                "ExpressionAttributeValues": {
+                    ":key": {"S": "user"},
+                    ":language": {"S": "python"}
+                },
+                "ExclusiveStartKey": {"id": {"S": "0"}, "userId": {"S": "user"}},
+                "Limit": 1
+            }),
+            json!({
+                "Items": [item],
+                "LastEvaluatedKey": {"id": {"S": "1"}, "userId": {"S": "user"}}
+            }),
+        )])
+        .await;
+
+        // WHEN querying the store
+        let page = store
+            .query(&TestRunQuery {
+                user_id: Some("user".to_owned()),
+                language: Some("python".to_owned()),
+                limit: 1,
+                cursor: Some(cursor),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        // THEN it returns the summary of the item and a cursor for the last key
+        assert_eq!(
+            page.testruns,
+            vec![TestRunSummary::from(&conformance::get_testrun())]
+        );
+        assert_eq!(
+            decode_key(&page.cursor.unwrap()).unwrap(),
+            HashMap::from([
+                ("id".to_owned(), AttributeValue::S("1".to_owned())),
+                ("userId".to_owned(), AttributeValue::S("user".to_owned())),
+            ])
+        );
+        assert_requests_match(&conn);
+    }
+
+    #[tokio::test]
+    async fn test_query_scan() {
+        // GIVEN a query without filters
+        let (store, conn) = get_store(vec![query_event(
+            "Scan",
+            json!({"TableName": "test", "Limit": 20}),
+            json!({"Items": []}),
+        )])
+        .await;
+
+        // WHEN querying the store
+        let page = store
+            .query(&TestRunQuery {
+                limit: 20,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        // THEN it scans the table and returns an empty last page
+        assert_eq!(page, TestRunPage::default());
+        assert_requests_match(&conn);
+    }
+
+    #[tokio::test]
+    async fn test_query_filters() {
+        let index_query = |index: &str, key: &str, value: &str, filter: Option<(&str, &str)>| {
+            let mut request = json!({
+                "TableName": "test",
+                "IndexName": index,
+                "KeyConditionExpression": "#key = :key",
+                "ExpressionAttributeNames": {"#key": key},
+                "ExpressionAttributeValues": {":key": {"S": value}},
+                "Limit": 10
+            });
+            if let Some((name, value)) = filter {
+                request["FilterExpression"] = json!(format!("#{0} = :{0}", name));
+                request["ExpressionAttributeNames"][format!("#{}", name)] = json!(name);
+                request["ExpressionAttributeValues"][format!(":{}", name)] = json!({"S": value});
+            }
+            request
+        };
+        let mut events = put_query_testrun_events();
+        events.extend([
+            query_event(
+                "Query",
+                index_query("userId-index", "userId", "alice", None),
+                query_response(&["a", "b", "d"], None),
+            ),
+            query_event(
+                "Query",
+                index_query(
+                    "challengeId-index",
+                    "challengeId",
+                    "c1",
+                    Some(("status", "running")),
+                ),
+                query_response(&["c", "d"], None),
+            ),
+            query_event(
+                "Query",
+                index_query(
+                    "userId-index",
+                    "userId",
+                    "alice",
+                    Some(("language", "rust")),
+                ),
+                query_response(&["b"], None),
+            ),
+            query_event(
+                "Query",
+                index_query("userId-index", "userId", "carol", None),
+                query_response(&[], None),
+            ),
+        ]);
+        let (store, conn) = get_store(events).await;
+
+        conformance::query_filters(&store).await;
+
+        assert_requests_match(&conn);
+    }
+
+    #[tokio::test]
+    async fn test_query_pagination() {
+        let last_key = json!({"id": {"S": "b"}, "userId": {"S": "alice"}});
+        let query = json!({
+            "TableName": "test",
+            "IndexName": "userId-index",
+            "KeyConditionExpression": "#key = :key",
+            "ExpressionAttributeNames": {"#key": "userId"},
+            "ExpressionAttributeValues": {":key": {"S": "alice"}},
+            "Limit": 2
+        });
+        let mut next_query = query.clone();
+        next_query["ExclusiveStartKey"] = last_key.clone();
+        let mut events = put_query_testrun_events();
+        events.extend([
+            query_event("Query", query, query_response(&["a", "b"], Some(last_key))),
+            query_event("Query", next_query, query_response(&["d"], None)),
+        ]);
+        let (store, conn) = get_store(events).await;
+
+        conformance::query_pagination(&store).await;
+
+        assert_requests_match(&conn);
+    }
+
+    #[tokio::test]
+    async fn test_query_invalid_cursor() {
+        let (store, _conn) = get_store(vec![]).await;
+
+        let res = store
+            .query(&TestRunQuery {
+                limit: 20,
+                cursor: Some("not a cursor".to_owned()),
+                ..Default::default()
+            })
+            .await;
+
+        assert!(matches!(res, Err(Error::ClientError(_))));
+    }
+
+    /// DynamoDB JSON for the conformance testrun, with `files` and `tests`
+    /// spilled to a blob store
+    fn get_spilled_item() -> String {
+        let testrun = conformance::get_testrun();
+        let files = testrun.files.iter().collect::<BTreeMap<_, _>>();
+        let files_ref = blob_key(&serde_json::to_vec(&files).unwrap());
+        let tests_ref = blob_key(&serde_json::to_vec(&testrun.tests).unwrap());
+
+        format!(
+            r#"{{
+                "id": {{"S": "1"}},
+                "userId": {{"S": "user"}},
+                "challengeId": {{"S": "challenge"}},
+                "filesRef": {{"S": "{}"}},
+                "language": {{"S": "python"}},
+                "status": {{"S": "queued"}},
+                "testsRef": {{"S": "{}"}},
+                "version": {{"N": "1"}},
+                "createdAt": {{"S": "2023-04-12T09:30:00+00:00"}}
+            }}"#,
+            files_ref, tests_ref
+        )
+    }
+
+    #[tokio::test]
+    async fn test_put_get_spilled() {
+        // GIVEN a store spilling every attribute to a local blob store
+        let item = get_spilled_item();
+        let (store, conn) =
+            get_store(vec![put_item_event(&item, 0), get_item_event(Some(&item))]).await;
+        let dir = tempfile::tempdir().unwrap();
+        let store = store.with_blob_store(Box::new(LocalBlobStore::new(dir.path())), 0);
+
+        // WHEN putting and getting a testrun
+        // THEN the item only holds blob keys, and the testrun is read back
+        conformance::put_get(&store).await;
+
+        assert_requests_match(&conn);
+    }
+
+    #[tokio::test]
+    async fn test_put_below_threshold() {
+        // GIVEN a store with a blob store and a large threshold
+        let item = get_item("queued", 1);
+        let (store, conn) =
+            get_store(vec![put_item_event(&item, 0), get_item_event(Some(&item))]).await;
+        let dir = tempfile::tempdir().unwrap();
+        let store = store.with_blob_store(Box::new(LocalBlobStore::new(dir.path())), 1024);
+
+        // WHEN putting and getting a small testrun
+        // THEN nothing is spilled
+        conformance::put_get(&store).await;
+
+        assert_requests_match(&conn);
+        assert!(std::fs::read_dir(dir.path()).unwrap().next().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_put_get_compressed() {
+        // GIVEN a store compressing with zstd
+        let testrun = conformance::get_testrun();
+        let item: HashMap<String, AttributeValue> = codec::encode_with(&testrun, Compression::Zstd);
+        let item = format!(
+            r#"{{
+                "id": {{"S": "1"}},
+                "userId": {{"S": "user"}},
+                "challengeId": {{"S": "challenge"}},
+                "files": {{"B": "{}"}},
+                "language": {{"S": "python"}},
+                "status": {{"S": "queued"}},
+                "tests": {{"B": "{}"}},
+                "version": {{"N": "1"}},
+                "createdAt": {{"S": "2023-04-12T09:30:00+00:00"}},
+                "compression": {{"S": "zstd"}}
+            }}"#,
+            STANDARD.encode(item["files"].as_b().unwrap()),
+            STANDARD.encode(item["tests"].as_b().unwrap()),
+        );
+        let (store, conn) =
+            get_store(vec![put_item_event(&item, 0), get_item_event(Some(&item))]).await;
+        let store = store.with_compression(Compression::Zstd);
+
+        // WHEN putting and getting a testrun
+        // THEN the payload is stored compressed, and the testrun is read back
+        conformance::put_get(&store).await;
+
+        assert_requests_match(&conn);
+    }
+
+    #[tokio::test]
+    async fn test_put_ttl() {
+        // GIVEN a store with the default retention policy
+        let (store, conn) = get_store(vec![put_item_event("{}", 0)]).await;
+        let store = store.with_retention(RetentionPolicy::default());
+
+        // WHEN putting a queued testrun
+        let before = Utc::now();
+        store.put(&conformance::get_testrun()).await.unwrap();
+
+        // THEN the item expires after the pending retention
+        let requests = conn.requests();
+        let body: serde_json::Value =
+            serde_json::from_slice(requests[0].actual.body().bytes().unwrap()).unwrap();
+        let ttl: i64 = body["Item"]["ttl"]["N"].as_str().unwrap().parse().unwrap();
+        let expected = (before + chrono::Duration::days(1)).timestamp();
+        assert!((expected..expected + 5).contains(&ttl));
+    }
+
+    #[tokio::test]
+    async fn test_get_expired() {
+        // GIVEN an item past its TTL, not yet deleted by DynamoDB
+        let item = get_item("queued", 1).replacen('{', r#"{"ttl": {"N": "1"},"#, 1);
+        let (store, conn) = get_store(vec![get_item_event(Some(&item))]).await;
+
+        // WHEN getting the testrun
+        let res = store.get("1").await.unwrap();
+
+        // THEN it is treated as deleted
+        assert_eq!(res, None);
+        assert_requests_match(&conn);
+    }
+
+    #[tokio::test]
+    async fn test_expired() {
+        // GIVEN items past their TTL, not yet deleted by DynamoDB
+        let item = get_item("queued", 1);
+        let expired = item.replacen('{', r#"{"ttl": {"N": "1"},"#, 1);
+        let mut summary = get_summary_item(&conformance::get_testrun());
+        summary["ttl"] = json!({"N": "1"});
+        let (store, conn) = get_store(vec![
+            put_item_event(&item, 0),
+            get_item_event(Some(&expired)),
+            query_event(
+                "Scan",
+                json!({"TableName": "test", "Limit": 10}),
+                json!({"Items": [summary]}),
+            ),
+            put_item_event(&item, 0),
+        ])
+        .await;
+
+        // WHEN reading and creating the testrun again
+        // THEN it is hidden, and created over the expired item
+        conformance::expired(&store).await;
+
+        assert_requests_match(&conn);
+    }
+
+    #[tokio::test]
+    async fn test_get_spilled_without_blob_store() {
+        // GIVEN a spilled item and a store without a blob store
+        let (store, conn) = get_store(vec![get_item_event(Some(&get_spilled_item()))]).await;
+
+        // WHEN getting the testrun
+        let res = store.get("1").await;
+
+        // THEN the spilled attributes cannot be read
+        assert!(matches!(res, Err(Error::InitError(_))));
+        assert_requests_match(&conn);
+    }
+
+    #[tokio::test]
+    async fn test_get_missing_blob() {
+        // GIVEN a spilled item whose blobs are missing
+        let (store, conn) = get_store(vec![get_item_event(Some(&get_spilled_item()))]).await;
+        let dir = tempfile::tempdir().unwrap();
+        let store = store.with_blob_store(Box::new(LocalBlobStore::new(dir.path())), 0);
+
+        // WHEN getting the testrun
+        let res = store.get("1").await;
+
+        // THEN the missing blob is reported
+        assert!(matches!(res, Err(Error::BlobError(_))));
+        assert_requests_match(&conn);
+    }
+
+    #[tokio::test]
+    async fn test_get_invalid_item() {
+        let (store, conn) = get_store(vec![get_item_event(Some(r#"{"id": {"S": "1"}}"#))]).await;
+
+        let res = store.get("1").await;
+
+        assert!(matches!(res, Err(Error::CodecError(_))));
+        assert_requests_match(&conn);
+    }
+
+    /// Event answering any request to the idempotency table
+    fn idempotency_event(target: &str, status: u16, body: &str) -> ConnectEvent {
+        (
+            get_request_builder()
+                .header("x-amz-target", format!("DynamoDB_20120810.{}", target))
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(status)
+                .body(SdkBody::from(body.to_owned()))
+                .unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_idempotency() {
+        // GIVEN an idempotency table holding the record once it is put
+        let expires_at = conformance::in_one_hour();
+        let record = conformance::get_idempotency_record(expires_at);
+        let item = format!(
+            r#"{{"Item": {{
+                "idempotencyKey": {{"S": "key"}},
+                "requestHash": {{"S": "hash"}},
+                "status": {{"N": "201"}},
+                "headers": {{"M": {{"etag": {{"S": "\"1\""}}}}}},
+                "body": {{"S": "{{\"message\":\"Testrun queued\"}}"}},
+                "ttl": {{"N": "{}"}}
+            }}}}"#,
+            record.expires_at.timestamp()
+        );
+        let conflict = r#"{
+            "__type": "com.amazonaws.dynamodb.v20120810#ConditionalCheckFailedException",
+            "message": "The conditional request failed"
+        }"#;
+        let (store, conn) = get_store(vec![
+            idempotency_event("GetItem", 200, "{}"),
+            idempotency_event("PutItem", 200, "{}"),
+            idempotency_event("GetItem", 200, &item),
+            idempotency_event("PutItem", 400, conflict),
+            idempotency_event("GetItem", 200, &item),
+        ])
+        .await;
+        let store = store.with_idempotency_table("idempotency".to_owned());
+
+        // WHEN recording and reading back a response
+        // THEN it is replayed until it expires
+        conformance::idempotency(&store, expires_at).await;
+
+        // AND the record is written to the idempotency table, unless one exists
+        let requests = conn.requests();
+        let body: serde_json::Value =
+            serde_json::from_slice(requests[1].actual.body().bytes().unwrap()).unwrap();
+        assert_eq!(body["TableName"], "idempotency");
+        assert_eq!(
+            body["Item"],
+            serde_json::from_str::<serde_json::Value>(&item).unwrap()["Item"]
+        );
+        assert_eq!(
+            body["ConditionExpression"],
+            "attribute_not_exists(#key) OR #ttl <= :now"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_reservation() {
+        // GIVEN an idempotency table holding a reservation, then the response
+        let expires_at = conformance::in_one_hour();
+        let record = conformance::get_idempotency_record(expires_at);
+        let item = |request_hash: &str, status: &str, headers: &str, body: &str| {
+            format!(
+                r#"{{
+                    "idempotencyKey": {{"S": "key"}},
+                    "requestHash": {{"S": "{}"}},
+                    "status": {{"N": "{}"}},
+                    "headers": {{"M": {{{}}}}},
+                    "body": {{"S": "{}"}},
+                    "ttl": {{"N": "{}"}}
+                }}"#,
+                request_hash,
+                status,
+                headers,
+                body,
+                record.expires_at.timestamp()
+            )
+        };
+        let reservation = item("hash", "0", "", "");
+        let response = item(
+            "hash",
+            "201",
+            r#""etag": {"S": "\"1\""}"#,
+            r#"{\"message\":\"Testrun queued\"}"#,
+        );
+        let conflict = r#"{
+            "__type": "com.amazonaws.dynamodb.v20120810#ConditionalCheckFailedException",
+            "message": "The conditional request failed"
+        }"#;
+        let get = |item: &str| format!(r#"{{"Item": {}}}"#, item);
+        let (store, conn) = get_store(vec![
+            idempotency_event("PutItem", 200, "{}"),
+            idempotency_event("GetItem", 200, &get(&reservation)),
+            idempotency_event("PutItem", 400, conflict),
+            idempotency_event("DeleteItem", 400, conflict),
+            idempotency_event("PutItem", 200, "{}"),
+            idempotency_event("GetItem", 200, &get(&response)),
+            idempotency_event("DeleteItem", 200, "{}"),
+            idempotency_event("GetItem", 200, "{}"),
+            idempotency_event("PutItem", 400, conflict),
+        ])
+        .await;
+        let store = store.with_idempotency_table("idempotency".to_owned());
+
+        // WHEN reserving a key, then recording and releasing it
+        // THEN only the request that reserved it can update or release it
+        conformance::idempotency_reservation(&store, expires_at).await;
+
+        // AND updates and deletes are conditional on the request hash
+        let requests = conn.requests();
+        let body: serde_json::Value =
+            serde_json::from_slice(requests[4].actual.body().bytes().unwrap()).unwrap();
+        assert_eq!(
+            body["Item"],
+            serde_json::from_str::<serde_json::Value>(&response).unwrap()
+        );
+        assert_eq!(body["ConditionExpression"], "#hash = :hash");
+        assert_eq!(body["ExpressionAttributeValues"][":hash"]["S"], "hash");
+        let body: serde_json::Value =
+            serde_json::from_slice(requests[6].actual.body().bytes().unwrap()).unwrap();
+        assert_eq!(body["Key"]["idempotencyKey"]["S"], "key");
+        assert_eq!(body["ConditionExpression"], "#hash = :hash");
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_without_table() {
+        let (store, conn) = get_store(vec![]).await;
+        let record = conformance::get_idempotency_record(conformance::in_one_hour());
+
+        // Keys can't be honoured, so requests with one fail
+        assert!(matches!(
+            store.put_idempotency(&record).await,
+            Err(Error::InternalError(_))
+        ));
+        assert!(matches!(
+            store.get_idempotency("key", Utc::now()).await,
+            Err(Error::InternalError(_))
+        ));
+        assert!(conn.requests().is_empty());
+    }
 }
@@ -0,0 +1,370 @@
+//! # In-memory store implementation
+//!
+//! Store implementation keeping testruns in a `HashMap`, for tests and local
+//! development.
+
+use super::{
+    decode_cursor, encode_cursor, expected_version, IdempotencyRecord, RetentionPolicy, Store,
+    StoreDelete, StoreGet, StoreIdempotency, StorePurge, StorePut, StoreQuery, TestRunPage,
+    TestRunQuery,
+};
+use crate::{
+    error::Error,
+    model::{TestRun, TestRunSummary},
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::{collections::HashMap, sync::RwLock};
+use tracing::{info, instrument};
+
+/// In-memory store implementation.
+#[derive(Default)]
+pub struct MemoryStore {
+    testruns: RwLock<HashMap<String, TestRun>>,
+    /// Expiry of testruns written with a retention policy
+    expiries: RwLock<HashMap<String, DateTime<Utc>>>,
+    retention: Option<RetentionPolicy>,
+    idempotency: RwLock<HashMap<String, IdempotencyRecord>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    /// Expire testruns according to `retention`, see `StorePurge`
+    pub fn with_retention(mut self, retention: RetentionPolicy) -> Self {
+        self.retention = Some(retention);
+        self
+    }
+}
+
+impl Store for MemoryStore {}
+
+#[async_trait]
+impl StoreGet for MemoryStore {
+    /// Get item
+    #[instrument(skip(self))]
+    async fn get(&self, id: &str) -> Result<Option<TestRun>, Error> {
+        info!("Getting item with id '{}' from memory", id);
+        let testruns = self
+            .testruns
+            .read()
+            .map_err(|_| Error::InternalError("Memory store lock poisoned"))?;
+        let expiries = self
+            .expiries
+            .read()
+            .map_err(|_| Error::InternalError("Memory store lock poisoned"))?;
+
+        // Expired testruns are hidden until purged
+        Ok(testruns
+            .get(id)
+            .filter(|_| !is_expired(&expiries, id, Utc::now()))
+            .cloned())
+    }
+}
+
+#[async_trait]
+impl StorePut for MemoryStore {
+    /// Create or update an item
+    #[instrument(skip(self, testrun), fields(id = %testrun.id))]
+    async fn put(&self, testrun: &TestRun) -> Result<(), Error> {
+        info!("Putting item with id '{}' into memory", testrun.id);
+        let mut testruns = self
+            .testruns
+            .write()
+            .map_err(|_| Error::InternalError("Memory store lock poisoned"))?;
+
+        // Updated while still holding the testruns lock, so that a concurrent
+        // purge cannot see the new testrun with its old expiry
+        let mut expiries = self
+            .expiries
+            .write()
+            .map_err(|_| Error::InternalError("Memory store lock poisoned"))?;
+
+        // An expired testrun can be created again before it is purged
+        let current = testruns
+            .get(&testrun.id)
+            .filter(|_| !is_expired(&expiries, &testrun.id, Utc::now()))
+            .map_or(0, |current| current.version);
+        if current != expected_version(testrun) {
+            return Err(Error::ConflictError("TestRun version mismatch"));
+        }
+        testruns.insert(testrun.id.clone(), testrun.clone());
+
+        match &self.retention {
+            Some(retention) => {
+                let expires_at = retention.expires_at(testrun, Utc::now());
+                expiries.insert(testrun.id.clone(), expires_at);
+            }
+            None => {
+                expiries.remove(&testrun.id);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StoreDelete for MemoryStore {
+    /// Delete item
+    #[instrument(skip(self))]
+    async fn delete(&self, id: &str) -> Result<(), Error> {
+        info!("Deleting item with id '{}' from memory", id);
+        let mut testruns = self
+            .testruns
+            .write()
+            .map_err(|_| Error::InternalError("Memory store lock poisoned"))?;
+        testruns.remove(id);
+        self.expiries
+            .write()
+            .map_err(|_| Error::InternalError("Memory store lock poisoned"))?
+            .remove(id);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StoreQuery for MemoryStore {
+    /// Query items
+    #[instrument(skip(self))]
+    async fn query(&self, query: &TestRunQuery) -> Result<TestRunPage, Error> {
+        info!("Querying items from memory");
+        let after = query.cursor.as_deref().map(decode_cursor).transpose()?;
+        let testruns = self
+            .testruns
+            .read()
+            .map_err(|_| Error::InternalError("Memory store lock poisoned"))?;
+        let expiries = self
+            .expiries
+            .read()
+            .map_err(|_| Error::InternalError("Memory store lock poisoned"))?;
+
+        let now = Utc::now();
+        let mut matching = testruns
+            .values()
+            .filter(|testrun| !is_expired(&expiries, &testrun.id, now))
+            .filter(|testrun| query.matches(testrun))
+            .filter(|testrun| after.as_ref().is_none_or(|after| &testrun.id > after))
+            .collect::<Vec<_>>();
+        matching.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let has_more = matching.len() > query.limit;
+        let testruns = matching
+            .into_iter()
+            .take(query.limit)
+            .map(TestRunSummary::from)
+            .collect::<Vec<_>>();
+        let cursor = match has_more {
+            true => testruns.last().map(|testrun| encode_cursor(&testrun.id)),
+            false => None,
+        };
+
+        Ok(TestRunPage { testruns, cursor })
+    }
+}
+
+#[async_trait]
+impl StorePurge for MemoryStore {
+    /// Delete expired items
+    #[instrument(skip(self))]
+    async fn purge(&self, now: DateTime<Utc>) -> Result<usize, Error> {
+        info!("Purging expired items from memory");
+        let mut testruns = self
+            .testruns
+            .write()
+            .map_err(|_| Error::InternalError("Memory store lock poisoned"))?;
+        let mut expiries = self
+            .expiries
+            .write()
+            .map_err(|_| Error::InternalError("Memory store lock poisoned"))?;
+
+        let expired = expiries
+            .iter()
+            .filter(|(_, expires_at)| **expires_at <= now)
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>();
+        for id in &expired {
+            expiries.remove(id);
+            testruns.remove(id);
+        }
+
+        self.idempotency
+            .write()
+            .map_err(|_| Error::InternalError("Memory store lock poisoned"))?
+            .retain(|_, record| record.expires_at > now);
+
+        Ok(expired.len())
+    }
+}
+
+#[async_trait]
+impl StoreIdempotency for MemoryStore {
+    /// Get idempotency record
+    #[instrument(skip(self))]
+    async fn get_idempotency(
+        &self,
+        key: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<IdempotencyRecord>, Error> {
+        let records = self
+            .idempotency
+            .read()
+            .map_err(|_| Error::InternalError("Memory store lock poisoned"))?;
+
+        Ok(records
+            .get(key)
+            .filter(|record| record.expires_at > now)
+            .cloned())
+    }
+
+    /// Create idempotency record
+    #[instrument(skip(self, record), fields(key = %record.key))]
+    async fn put_idempotency(&self, record: &IdempotencyRecord) -> Result<(), Error> {
+        let mut records = self
+            .idempotency
+            .write()
+            .map_err(|_| Error::InternalError("Memory store lock poisoned"))?;
+
+        if records
+            .get(&record.key)
+            .is_some_and(|current| current.expires_at > Utc::now())
+        {
+            return Err(Error::ConflictError("Idempotency key already recorded"));
+        }
+        records.insert(record.key.clone(), record.clone());
+
+        Ok(())
+    }
+
+    /// Replace idempotency record
+    #[instrument(skip(self, record), fields(key = %record.key))]
+    async fn update_idempotency(&self, record: &IdempotencyRecord) -> Result<(), Error> {
+        let mut records = self
+            .idempotency
+            .write()
+            .map_err(|_| Error::InternalError("Memory store lock poisoned"))?;
+
+        match records.get_mut(&record.key) {
+            Some(current) if current.request_hash == record.request_hash => {
+                *current = record.clone();
+                Ok(())
+            }
+            _ => Err(Error::ConflictError(
+                "Idempotency key held by another request",
+            )),
+        }
+    }
+
+    /// Delete idempotency record
+    #[instrument(skip(self))]
+    async fn delete_idempotency(&self, key: &str, request_hash: &str) -> Result<(), Error> {
+        let mut records = self
+            .idempotency
+            .write()
+            .map_err(|_| Error::InternalError("Memory store lock poisoned"))?;
+
+        if records
+            .get(key)
+            .is_some_and(|current| current.request_hash == request_hash)
+        {
+            records.remove(key);
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether the testrun `id` expired at `now`
+fn is_expired(expiries: &HashMap<String, DateTime<Utc>>, id: &str, now: DateTime<Utc>) -> bool {
+    expiries
+        .get(id)
+        .is_some_and(|expires_at| *expires_at <= now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::conformance;
+
+    #[tokio::test]
+    async fn test_get_missing() {
+        conformance::get_missing(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_put_get() {
+        conformance::put_get(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_put_overwrite() {
+        conformance::put_overwrite(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_put_conflict() {
+        conformance::put_conflict(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_put_missing_conflict() {
+        conformance::put_missing_conflict(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_delete() {
+        conformance::delete(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_delete_missing() {
+        conformance::delete_missing(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_query_filters() {
+        conformance::query_filters(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_query_pagination() {
+        conformance::query_pagination(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_purge() {
+        let store = MemoryStore::new().with_retention(RetentionPolicy::default());
+
+        conformance::purge(&store).await;
+    }
+
+    #[tokio::test]
+    async fn test_purge_without_retention() {
+        conformance::purge_without_retention(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_expired() {
+        let store = MemoryStore::new().with_retention(conformance::get_immediate_retention());
+
+        conformance::expired(&store).await;
+    }
+
+    #[tokio::test]
+    async fn test_idempotency() {
+        conformance::idempotency(&MemoryStore::new(), conformance::in_one_hour()).await;
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_expired() {
+        conformance::idempotency_expired(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_reservation() {
+        conformance::idempotency_reservation(&MemoryStore::new(), conformance::in_one_hour()).await;
+    }
+}
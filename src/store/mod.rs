@@ -1,11 +1,31 @@
-use crate::{error::Error, model::TestRun};
+use crate::{
+    error::Error,
+    model::{TestRun, TestRunStatus, TestRunSummary},
+};
 use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
 
-mod dynamodb;
+pub mod blob;
+#[cfg(test)]
+pub(crate) mod conformance;
+pub(crate) mod dynamodb;
+mod memory;
+mod retention;
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+mod sql;
 
-pub use dynamodb::DynamoDBStore;
+pub use dynamodb::{codec::Compression, DynamoDBStore};
+pub use memory::MemoryStore;
+pub use retention::RetentionPolicy;
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+pub use sql::{DieselBackend, DieselStore};
 
-pub trait Store: StoreGet + StorePut + StoreDelete {}
+pub trait Store:
+    StoreGet + StorePut + StoreDelete + StoreQuery + StorePurge + StoreIdempotency
+{
+}
 
 /// Trait for retrieving a single testrun
 #[async_trait]
@@ -14,6 +34,10 @@ pub trait StoreGet: Send + Sync {
 }
 
 /// Trait for storing a single testrun
+///
+/// Writes are conditional on `testrun.version`: the stored testrun must be at
+/// `version - 1`, with a missing testrun counting as version 0. Otherwise the
+/// write is rejected with `Error::ConflictError` and nothing is stored.
 #[async_trait]
 pub trait StorePut: Send + Sync {
     async fn put(&self, testrun: &TestRun) -> Result<(), Error>;
@@ -24,3 +48,140 @@ pub trait StorePut: Send + Sync {
 pub trait StoreDelete: Send + Sync {
     async fn delete(&self, id: &str) -> Result<(), Error>;
 }
+
+/// Trait for listing testruns matching a query
+///
+/// Listings only return summaries, so that a page never has to read the
+/// files and test results of its testruns.
+#[async_trait]
+pub trait StoreQuery: Send + Sync {
+    async fn query(&self, query: &TestRunQuery) -> Result<TestRunPage, Error>;
+}
+
+/// Trait for deleting testruns past their retention
+///
+/// Only testruns written while a `RetentionPolicy` was set can expire.
+#[async_trait]
+pub trait StorePurge: Send + Sync {
+    /// Delete testruns that expired before `now`, returning how many were
+    /// deleted
+    ///
+    /// Expired idempotency records are deleted as well, but not counted.
+    async fn purge(&self, now: DateTime<Utc>) -> Result<usize, Error>;
+}
+
+/// Trait for recording responses to requests sent with an `Idempotency-Key`
+#[async_trait]
+pub trait StoreIdempotency: Send + Sync {
+    /// Get the record for a key, unless it expired before `now`
+    async fn get_idempotency(
+        &self,
+        key: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<IdempotencyRecord>, Error>;
+
+    /// Store a record
+    ///
+    /// Fails with `Error::ConflictError` if an unexpired record already
+    /// exists for the same key.
+    async fn put_idempotency(&self, record: &IdempotencyRecord) -> Result<(), Error>;
+
+    /// Replace the record stored for the same key and request hash
+    ///
+    /// Fails with `Error::ConflictError` if the key holds no record for that
+    /// request, such as when its reservation expired and another request
+    /// took the key.
+    async fn update_idempotency(&self, record: &IdempotencyRecord) -> Result<(), Error>;
+
+    /// Delete the record stored for a key, if it is for `request_hash`
+    async fn delete_idempotency(&self, key: &str, request_hash: &str) -> Result<(), Error>;
+}
+
+/// Response recorded for an `Idempotency-Key`
+///
+/// A `status` of 0 marks a request that is still being handled.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdempotencyRecord {
+    pub key: String,
+    /// Hash of the request that got the response
+    pub request_hash: String,
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl IdempotencyRecord {
+    /// Record reserving a key while its request is handled, without a response
+    pub fn in_progress(key: String, request_hash: String, expires_at: DateTime<Utc>) -> Self {
+        IdempotencyRecord {
+            key,
+            request_hash,
+            status: 0,
+            headers: BTreeMap::new(),
+            body: String::new(),
+            expires_at,
+        }
+    }
+
+    /// Whether the request is still being handled, see `in_progress`
+    pub fn is_in_progress(&self) -> bool {
+        self.status == 0
+    }
+}
+
+/// Filters and pagination for listing testruns
+///
+/// Filters that are `None` match every testrun.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TestRunQuery {
+    pub user_id: Option<String>,
+    pub challenge_id: Option<String>,
+    pub language: Option<String>,
+    pub status: Option<TestRunStatus>,
+    /// Maximum number of testruns to return
+    pub limit: usize,
+    /// Opaque cursor returned by the previous page
+    pub cursor: Option<String>,
+}
+
+impl TestRunQuery {
+    /// Whether a testrun matches all filters of the query
+    pub fn matches(&self, testrun: &TestRun) -> bool {
+        (self.user_id.is_none() || self.user_id == testrun.user_id)
+            && (self.challenge_id.is_none() || self.challenge_id == testrun.challenge_id)
+            && (self.language.is_none() || self.language.as_ref() == Some(&testrun.language))
+            && (self.status.is_none() || self.status == Some(testrun.status))
+    }
+}
+
+/// Page of testruns
+///
+/// The order is up to the backend: the memory and SQL stores order by id, and
+/// so does DynamoDB when querying the index of a user or challenge, but a
+/// DynamoDB table scan returns testruns in no particular order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TestRunPage {
+    pub testruns: Vec<TestRunSummary>,
+    /// Cursor for the next page, if there might be more results
+    pub cursor: Option<String>,
+}
+
+/// Version a testrun must be stored at for `testrun` to be written
+fn expected_version(testrun: &TestRun) -> u64 {
+    testrun.version.saturating_sub(1)
+}
+
+/// Encode the id of the last testrun of a page as a cursor
+fn encode_cursor(id: &str) -> String {
+    URL_SAFE_NO_PAD.encode(id)
+}
+
+/// Decode a cursor created by `encode_cursor`
+fn decode_cursor(cursor: &str) -> Result<String, Error> {
+    URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|id| String::from_utf8(id).ok())
+        .ok_or(Error::ClientError("Invalid cursor"))
+}
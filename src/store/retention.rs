@@ -0,0 +1,92 @@
+//! # Retention policy
+//!
+//! How long testruns are kept after their last write. DynamoDB deletes
+//! expired items itself through its TTL attribute, other stores delete them
+//! when `StorePurge::purge` runs.
+
+use crate::model::TestRun;
+use chrono::{DateTime, Duration, Utc};
+
+/// Time to keep a testrun after its last write, depending on its status
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Retention of testruns in a final status
+    pub final_ttl: Duration,
+    /// Retention of queued or running testruns, which were likely abandoned
+    /// if they are not updated for that long
+    pub pending_ttl: Duration,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy {
+            final_ttl: Duration::days(90),
+            pending_ttl: Duration::days(1),
+        }
+    }
+}
+
+impl RetentionPolicy {
+    /// Read the policy from the environment
+    ///
+    /// `RETENTION_FINAL_DAYS` and `RETENTION_PENDING_DAYS` override the
+    /// defaults, and must be a number of days when set.
+    pub fn from_env() -> Self {
+        let default = RetentionPolicy::default();
+        let var = |name: &str| {
+            std::env::var(name).ok().map(|value| {
+                let days: u32 = value
+                    .parse()
+                    .unwrap_or_else(|_| panic!("Unsupported {}: {}", name, value));
+                Duration::days(i64::from(days))
+            })
+        };
+
+        RetentionPolicy {
+            final_ttl: var("RETENTION_FINAL_DAYS").unwrap_or(default.final_ttl),
+            pending_ttl: var("RETENTION_PENDING_DAYS").unwrap_or(default.pending_ttl),
+        }
+    }
+
+    /// When a testrun written at `now` expires
+    pub fn expires_at(&self, testrun: &TestRun, now: DateTime<Utc>) -> DateTime<Utc> {
+        let ttl = match testrun.status.is_final() {
+            true => self.final_ttl,
+            false => self.pending_ttl,
+        };
+
+        now.checked_add_signed(ttl)
+            .unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{model::TestRunStatus, store::conformance::get_testrun_with_status};
+
+    #[test]
+    fn retention_by_status() {
+        let policy = RetentionPolicy::default();
+        let now = Utc::now();
+
+        for status in [TestRunStatus::Queued, TestRunStatus::Running] {
+            assert_eq!(
+                policy.expires_at(&get_testrun_with_status(status), now),
+                now + Duration::days(1)
+            );
+        }
+        for status in [
+            TestRunStatus::Passed,
+            TestRunStatus::Failed,
+            TestRunStatus::Errored,
+            TestRunStatus::TimedOut,
+            TestRunStatus::Cancelled,
+        ] {
+            assert_eq!(
+                policy.expires_at(&get_testrun_with_status(status), now),
+                now + Duration::days(90)
+            );
+        }
+    }
+}
@@ -0,0 +1,843 @@
+//! # Diesel store implementation
+//!
+//! Store implementation for SQLite and PostgreSQL using Diesel.
+//!
+//! A testrun is stored as a row in the `testruns` table, with its `files`
+//! serialized as JSON, and one row per test in the `tests` table. Migrations
+//! are embedded in the binary and run when the store is created.
+//!
+//! With a retention policy, each row records when it expires, and `purge`
+//! deletes expired rows.
+//!
+//! Idempotency records are kept in the `idempotency_keys` table, with their
+//! headers serialized as JSON.
+
+use super::{
+    decode_cursor, encode_cursor, expected_version, IdempotencyRecord, RetentionPolicy, Store,
+    StoreDelete, StoreGet, StoreIdempotency, StorePurge, StorePut, StoreQuery, TestRunPage,
+    TestRunQuery,
+};
+use crate::{
+    error::{CodecError, Error},
+    model::{Test, TestRun, TestRunStatus, TestRunSummary},
+};
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use diesel::{
+    prelude::*,
+    r2d2::{self, ManageConnection, Pool},
+};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use tracing::{info, instrument};
+
+mod schema;
+use schema::{idempotency_keys, testruns};
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+/// Database backend used by a `DieselStore`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DieselBackend {
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+    #[cfg(feature = "postgres")]
+    Postgres,
+}
+
+/// Connection to any of the enabled database backends
+#[derive(diesel::MultiConnection)]
+pub enum AnyConnection {
+    #[cfg(feature = "sqlite")]
+    Sqlite(diesel::SqliteConnection),
+    #[cfg(feature = "postgres")]
+    Postgres(diesel::PgConnection),
+}
+
+/// Connection manager for the r2d2 pool
+///
+/// `AnyConnection::establish` tries every backend in turn, which would let
+/// SQLite happily create a file named after a PostgreSQL URL. This manager
+/// always connects with the configured backend instead.
+#[derive(Debug)]
+struct AnyConnectionManager {
+    backend: DieselBackend,
+    database_url: String,
+}
+
+impl ManageConnection for AnyConnectionManager {
+    type Connection = AnyConnection;
+    type Error = r2d2::Error;
+
+    fn connect(&self) -> Result<AnyConnection, r2d2::Error> {
+        match self.backend {
+            #[cfg(feature = "sqlite")]
+            DieselBackend::Sqlite => {
+                diesel::SqliteConnection::establish(&self.database_url).map(AnyConnection::Sqlite)
+            }
+            #[cfg(feature = "postgres")]
+            DieselBackend::Postgres => {
+                diesel::PgConnection::establish(&self.database_url).map(AnyConnection::Postgres)
+            }
+        }
+        .map_err(r2d2::Error::ConnectionError)
+    }
+
+    fn is_valid(&self, conn: &mut AnyConnection) -> Result<(), r2d2::Error> {
+        r2d2::R2D2Connection::ping(conn).map_err(r2d2::Error::QueryError)
+    }
+
+    fn has_broken(&self, conn: &mut AnyConnection) -> bool {
+        r2d2::R2D2Connection::is_broken(conn)
+    }
+}
+
+#[derive(Insertable, Queryable, AsChangeset)]
+#[diesel(table_name = testruns, treat_none_as_null = true)]
+struct TestRunRow {
+    id: String,
+    language: String,
+    status: String,
+    files: String,
+    user_id: Option<String>,
+    challenge_id: Option<String>,
+    version: i64,
+    expires_at: Option<i64>,
+    /// RFC 3339 timestamp
+    created_at: Option<String>,
+}
+
+/// Columns of `testruns` returned by listings
+#[derive(Queryable)]
+struct SummaryRow {
+    id: String,
+    user_id: Option<String>,
+    challenge_id: Option<String>,
+    language: String,
+    status: String,
+    version: i64,
+    created_at: Option<String>,
+}
+
+#[derive(Insertable, Queryable)]
+#[diesel(table_name = schema::tests)]
+struct TestRow {
+    testrun_id: String,
+    position: i32,
+    name: String,
+    status: String,
+    message: String,
+    actual_output: String,
+    expected_output: String,
+}
+
+#[derive(Insertable, Queryable)]
+#[diesel(table_name = idempotency_keys)]
+struct IdempotencyRow {
+    idempotency_key: String,
+    request_hash: String,
+    status: i32,
+    headers: String,
+    body: String,
+    expires_at: i64,
+}
+
+/// Diesel store implementation.
+pub struct DieselStore {
+    pool: Pool<AnyConnectionManager>,
+    retention: Option<RetentionPolicy>,
+}
+
+impl DieselStore {
+    /// Connect to the database and run pending migrations
+    pub fn new(backend: DieselBackend, database_url: &str) -> Result<DieselStore, Error> {
+        let manager = AnyConnectionManager {
+            backend,
+            database_url: database_url.to_owned(),
+        };
+        let max_size = match backend {
+            // SQLite only supports a single writer at a time
+            #[cfg(feature = "sqlite")]
+            DieselBackend::Sqlite => 1,
+            #[cfg(feature = "postgres")]
+            DieselBackend::Postgres => 10,
+        };
+        let pool = Pool::builder().max_size(max_size).build(manager)?;
+
+        pool.get()?
+            .run_pending_migrations(MIGRATIONS)
+            .map_err(|err| Error::DatabaseError(format!("{}", err)))?;
+
+        Ok(DieselStore {
+            pool,
+            retention: None,
+        })
+    }
+
+    /// Expire testruns according to `retention`, see `StorePurge`
+    pub fn with_retention(mut self, retention: RetentionPolicy) -> Self {
+        self.retention = Some(retention);
+        self
+    }
+
+    /// Run a blocking database operation on a pooled connection
+    async fn run<F, T>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut AnyConnection) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let pool = self.pool.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = pool.get()?;
+            f(&mut conn)
+        })
+        .await
+        .map_err(|_| Error::InternalError("Database task failed"))?
+    }
+}
+
+impl Store for DieselStore {}
+
+#[async_trait]
+impl StoreGet for DieselStore {
+    /// Get item
+    #[instrument(skip(self))]
+    async fn get(&self, id: &str) -> Result<Option<TestRun>, Error> {
+        use schema::tests;
+
+        info!("Getting item with id '{}' from database", id);
+        let id = id.to_owned();
+        let now = Utc::now().timestamp();
+        self.run(move |conn| {
+            // Expired testruns are hidden until purged
+            let testrun = testruns::table
+                .find(&id)
+                .filter(
+                    testruns::expires_at
+                        .is_null()
+                        .or(testruns::expires_at.gt(now)),
+                )
+                .first::<TestRunRow>(conn)
+                .optional()?;
+            let testrun = match testrun {
+                Some(testrun) => testrun,
+                None => return Ok(None),
+            };
+
+            let tests = tests::table
+                .filter(tests::testrun_id.eq(&id))
+                .order(tests::position)
+                .load::<TestRow>(conn)?;
+
+            Ok(Some(from_rows(testrun, tests)?))
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl StorePut for DieselStore {
+    /// Create or update an item
+    #[instrument(skip(self, testrun), fields(id = %testrun.id))]
+    async fn put(&self, testrun: &TestRun) -> Result<(), Error> {
+        use schema::tests;
+
+        info!("Putting item with id '{}' into database", testrun.id);
+        let expires_at = self
+            .retention
+            .map(|retention| retention.expires_at(testrun, Utc::now()).timestamp());
+        let (row, test_rows) = to_rows(testrun, expires_at)?;
+        let expected = expected_version(testrun) as i64;
+        let now = Utc::now().timestamp();
+        self.run(move |conn| {
+            conn.transaction(|conn| {
+                // An expired testrun can be created again before it is purged
+                let updated = match expected {
+                    0 => diesel::update(
+                        testruns::table
+                            .find(&row.id)
+                            .filter(testruns::version.eq(0).or(testruns::expires_at.le(now))),
+                    )
+                    .set(&row)
+                    .execute(conn)?,
+                    _ => diesel::update(
+                        testruns::table
+                            .find(&row.id)
+                            .filter(testruns::version.eq(expected)),
+                    )
+                    .set(&row)
+                    .execute(conn)?,
+                };
+                if updated == 0 {
+                    if expected != 0 {
+                        return Err(Error::ConflictError("TestRun version mismatch"));
+                    }
+
+                    // A concurrent insert makes this fail on the primary key
+                    match diesel::insert_into(testruns::table)
+                        .values(&row)
+                        .execute(conn)
+                    {
+                        Ok(_) => (),
+                        Err(diesel::result::Error::DatabaseError(
+                            diesel::result::DatabaseErrorKind::UniqueViolation,
+                            _,
+                        )) => return Err(Error::ConflictError("TestRun version mismatch")),
+                        Err(err) => return Err(err.into()),
+                    }
+                }
+
+                diesel::delete(tests::table.filter(tests::testrun_id.eq(&row.id))).execute(conn)?;
+                for test_row in &test_rows {
+                    diesel::insert_into(tests::table)
+                        .values(test_row)
+                        .execute(conn)?;
+                }
+
+                Ok(())
+            })
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl StoreDelete for DieselStore {
+    /// Delete item
+    #[instrument(skip(self))]
+    async fn delete(&self, id: &str) -> Result<(), Error> {
+        use schema::tests;
+
+        info!("Deleting item with id '{}' from database", id);
+        let id = id.to_owned();
+        self.run(move |conn| {
+            conn.transaction(|conn| {
+                // SQLite does not enforce `ON DELETE CASCADE` by default
+                diesel::delete(tests::table.filter(tests::testrun_id.eq(&id))).execute(conn)?;
+                diesel::delete(testruns::table.find(&id)).execute(conn)?;
+
+                Ok(())
+            })
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl StoreQuery for DieselStore {
+    /// Query items
+    #[instrument(skip(self))]
+    async fn query(&self, query: &TestRunQuery) -> Result<TestRunPage, Error> {
+        info!("Querying items from database");
+        let after = query.cursor.as_deref().map(decode_cursor).transpose()?;
+        let query = query.clone();
+        let now = Utc::now().timestamp();
+        self.run(move |conn| {
+            let mut select = testruns::table
+                .filter(
+                    testruns::expires_at
+                        .is_null()
+                        .or(testruns::expires_at.gt(now)),
+                )
+                .into_boxed();
+            if let Some(user_id) = query.user_id {
+                select = select.filter(testruns::user_id.eq(user_id));
+            }
+            if let Some(challenge_id) = query.challenge_id {
+                select = select.filter(testruns::challenge_id.eq(challenge_id));
+            }
+            if let Some(language) = query.language {
+                select = select.filter(testruns::language.eq(language));
+            }
+            if let Some(status) = query.status {
+                select = select.filter(testruns::status.eq(status.to_string()));
+            }
+            if let Some(after) = after {
+                select = select.filter(testruns::id.gt(after));
+            }
+
+            // Fetch one extra row to know whether there is a next page
+            let mut rows = select
+                .order(testruns::id)
+                .limit(query.limit as i64 + 1)
+                .select((
+                    testruns::id,
+                    testruns::user_id,
+                    testruns::challenge_id,
+                    testruns::language,
+                    testruns::status,
+                    testruns::version,
+                    testruns::created_at,
+                ))
+                .load::<SummaryRow>(conn)?;
+            let has_more = rows.len() > query.limit;
+            rows.truncate(query.limit);
+
+            let testruns = rows
+                .into_iter()
+                .map(from_summary_row)
+                .collect::<Result<Vec<_>, Error>>()?;
+            let cursor = match has_more {
+                true => testruns.last().map(|testrun| encode_cursor(&testrun.id)),
+                false => None,
+            };
+
+            Ok(TestRunPage { testruns, cursor })
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl StorePurge for DieselStore {
+    /// Delete expired items
+    #[instrument(skip(self))]
+    async fn purge(&self, now: DateTime<Utc>) -> Result<usize, Error> {
+        use schema::tests;
+
+        info!("Purging expired items from database");
+        let now = now.timestamp();
+        self.run(move |conn| {
+            conn.transaction(|conn| {
+                let expired = testruns::table
+                    .filter(testruns::expires_at.le(now))
+                    .select(testruns::id);
+                // SQLite does not enforce `ON DELETE CASCADE` by default
+                diesel::delete(tests::table.filter(tests::testrun_id.eq_any(expired)))
+                    .execute(conn)?;
+                let purged = diesel::delete(testruns::table.filter(testruns::expires_at.le(now)))
+                    .execute(conn)?;
+
+                diesel::delete(
+                    idempotency_keys::table.filter(idempotency_keys::expires_at.le(now)),
+                )
+                .execute(conn)?;
+
+                Ok(purged)
+            })
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl StoreIdempotency for DieselStore {
+    /// Get idempotency record
+    #[instrument(skip(self))]
+    async fn get_idempotency(
+        &self,
+        key: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<IdempotencyRecord>, Error> {
+        let key = key.to_owned();
+        let now = now.timestamp();
+        self.run(move |conn| {
+            idempotency_keys::table
+                .find(&key)
+                .filter(idempotency_keys::expires_at.gt(now))
+                .first::<IdempotencyRow>(conn)
+                .optional()?
+                .map(from_idempotency_row)
+                .transpose()
+        })
+        .await
+    }
+
+    /// Create idempotency record
+    #[instrument(skip(self, record), fields(key = %record.key))]
+    async fn put_idempotency(&self, record: &IdempotencyRecord) -> Result<(), Error> {
+        let row = to_idempotency_row(record)?;
+        let now = Utc::now().timestamp();
+        self.run(move |conn| {
+            conn.transaction(|conn| {
+                diesel::delete(
+                    idempotency_keys::table
+                        .find(&row.idempotency_key)
+                        .filter(idempotency_keys::expires_at.le(now)),
+                )
+                .execute(conn)?;
+
+                // An unexpired record makes this fail on the primary key
+                match diesel::insert_into(idempotency_keys::table)
+                    .values(&row)
+                    .execute(conn)
+                {
+                    Ok(_) => Ok(()),
+                    Err(diesel::result::Error::DatabaseError(
+                        diesel::result::DatabaseErrorKind::UniqueViolation,
+                        _,
+                    )) => Err(Error::ConflictError("Idempotency key already recorded")),
+                    Err(err) => Err(err.into()),
+                }
+            })
+        })
+        .await
+    }
+
+    /// Replace idempotency record
+    #[instrument(skip(self, record), fields(key = %record.key))]
+    async fn update_idempotency(&self, record: &IdempotencyRecord) -> Result<(), Error> {
+        let row = to_idempotency_row(record)?;
+        self.run(move |conn| {
+            let updated = diesel::update(
+                idempotency_keys::table
+                    .find(&row.idempotency_key)
+                    .filter(idempotency_keys::request_hash.eq(&row.request_hash)),
+            )
+            .set((
+                idempotency_keys::status.eq(row.status),
+                idempotency_keys::headers.eq(&row.headers),
+                idempotency_keys::body.eq(&row.body),
+                idempotency_keys::expires_at.eq(row.expires_at),
+            ))
+            .execute(conn)?;
+
+            match updated {
+                0 => Err(Error::ConflictError(
+                    "Idempotency key held by another request",
+                )),
+                _ => Ok(()),
+            }
+        })
+        .await
+    }
+
+    /// Delete idempotency record
+    #[instrument(skip(self))]
+    async fn delete_idempotency(&self, key: &str, request_hash: &str) -> Result<(), Error> {
+        let key = key.to_owned();
+        let request_hash = request_hash.to_owned();
+        self.run(move |conn| {
+            diesel::delete(
+                idempotency_keys::table
+                    .find(&key)
+                    .filter(idempotency_keys::request_hash.eq(&request_hash)),
+            )
+            .execute(conn)?;
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+/// Convert an `IdempotencyRecord` into a database row
+fn to_idempotency_row(record: &IdempotencyRecord) -> Result<IdempotencyRow, Error> {
+    Ok(IdempotencyRow {
+        idempotency_key: record.key.clone(),
+        request_hash: record.request_hash.clone(),
+        status: i32::from(record.status),
+        headers: serde_json::to_string(&record.headers)
+            .map_err(|_| Error::InternalError("Couldn't serialize headers"))?,
+        body: record.body.clone(),
+        expires_at: record.expires_at.timestamp(),
+    })
+}
+
+/// Try to convert a database row into an `IdempotencyRecord`
+fn from_idempotency_row(row: IdempotencyRow) -> Result<IdempotencyRecord, Error> {
+    Ok(IdempotencyRecord {
+        headers: serde_json::from_str(&row.headers)
+            .map_err(|_| Error::InternalError("Couldn't parse headers"))?,
+        status: u16::try_from(row.status).map_err(|_| CodecError::InvalidValue {
+            attribute: "status".to_owned(),
+            value: row.status.to_string(),
+        })?,
+        expires_at: Utc
+            .timestamp_opt(row.expires_at, 0)
+            .single()
+            .ok_or_else(|| CodecError::InvalidValue {
+                attribute: "expires_at".to_owned(),
+                value: row.expires_at.to_string(),
+            })?,
+        key: row.idempotency_key,
+        request_hash: row.request_hash,
+        body: row.body,
+    })
+}
+
+/// Convert a `TestRun` into database rows
+fn to_rows(
+    testrun: &TestRun,
+    expires_at: Option<i64>,
+) -> Result<(TestRunRow, Vec<TestRow>), Error> {
+    let files = serde_json::to_string(&testrun.files)
+        .map_err(|_| Error::InternalError("Couldn't serialize files"))?;
+
+    let tests = testrun
+        .tests
+        .iter()
+        .enumerate()
+        .map(|(position, test)| TestRow {
+            testrun_id: testrun.id.clone(),
+            position: position as i32,
+            name: test.name.clone(),
+            status: test.status.to_string(),
+            message: test.message.clone(),
+            actual_output: test.actual_output.clone(),
+            expected_output: test.expected_output.clone(),
+        })
+        .collect();
+
+    Ok((
+        TestRunRow {
+            id: testrun.id.clone(),
+            language: testrun.language.clone(),
+            status: testrun.status.to_string(),
+            files,
+            user_id: testrun.user_id.clone(),
+            challenge_id: testrun.challenge_id.clone(),
+            version: i64::try_from(testrun.version)
+                .map_err(|_| Error::InternalError("Version out of range"))?,
+            expires_at,
+            created_at: testrun.created_at.map(|created_at| created_at.to_rfc3339()),
+        },
+        tests,
+    ))
+}
+
+/// Try to convert database rows into a `TestRun`
+fn from_rows(testrun: TestRunRow, tests: Vec<TestRow>) -> Result<TestRun, Error> {
+    let files = serde_json::from_str(&testrun.files)
+        .map_err(|_| Error::InternalError("Couldn't parse files"))?;
+
+    let tests = tests
+        .into_iter()
+        .map(|test| {
+            Ok(Test {
+                status: test.status.parse().map_err(|_| CodecError::InvalidValue {
+                    attribute: format!("tests[{}].status", test.position),
+                    value: test.status.clone(),
+                })?,
+                name: test.name,
+                message: test.message,
+                actual_output: test.actual_output,
+                expected_output: test.expected_output,
+            })
+        })
+        .collect::<Result<Vec<_>, CodecError>>()?;
+
+    Ok(TestRun {
+        status: parse_status(&testrun.status)?,
+        version: parse_version(testrun.version)?,
+        created_at: parse_created_at(testrun.created_at)?,
+        id: testrun.id,
+        user_id: testrun.user_id,
+        challenge_id: testrun.challenge_id,
+        files,
+        language: testrun.language,
+        tests,
+    })
+}
+
+/// Try to convert a listed row into a `TestRunSummary`
+fn from_summary_row(row: SummaryRow) -> Result<TestRunSummary, Error> {
+    Ok(TestRunSummary {
+        status: parse_status(&row.status)?,
+        version: parse_version(row.version)?,
+        created_at: parse_created_at(row.created_at)?,
+        id: row.id,
+        user_id: row.user_id,
+        challenge_id: row.challenge_id,
+        language: row.language,
+    })
+}
+
+fn parse_status(status: &str) -> Result<TestRunStatus, CodecError> {
+    status.parse().map_err(|_| CodecError::InvalidValue {
+        attribute: "status".to_owned(),
+        value: status.to_owned(),
+    })
+}
+
+fn parse_version(version: i64) -> Result<u64, CodecError> {
+    u64::try_from(version).map_err(|_| CodecError::InvalidValue {
+        attribute: "version".to_owned(),
+        value: version.to_string(),
+    })
+}
+
+fn parse_created_at(created_at: Option<String>) -> Result<Option<DateTime<Utc>>, CodecError> {
+    created_at
+        .map(|created_at| {
+            created_at.parse().map_err(|_| CodecError::InvalidValue {
+                attribute: "created_at".to_owned(),
+                value: created_at,
+            })
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::conformance;
+
+    #[cfg(feature = "sqlite")]
+    mod sqlite {
+        use super::*;
+        use tempfile::TempDir;
+
+        /// Store backed by a SQLite database in a temporary directory
+        fn get_store() -> (DieselStore, TempDir) {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("testruns.db");
+            let store = DieselStore::new(DieselBackend::Sqlite, path.to_str().unwrap()).unwrap();
+
+            (store, dir)
+        }
+
+        #[tokio::test]
+        async fn test_get_missing() {
+            let (store, _dir) = get_store();
+            conformance::get_missing(&store).await;
+        }
+
+        #[tokio::test]
+        async fn test_put_get() {
+            let (store, _dir) = get_store();
+            conformance::put_get(&store).await;
+        }
+
+        #[tokio::test]
+        async fn test_put_overwrite() {
+            let (store, _dir) = get_store();
+            conformance::put_overwrite(&store).await;
+        }
+
+        #[tokio::test]
+        async fn test_put_conflict() {
+            let (store, _dir) = get_store();
+            conformance::put_conflict(&store).await;
+        }
+
+        #[tokio::test]
+        async fn test_put_missing_conflict() {
+            let (store, _dir) = get_store();
+            conformance::put_missing_conflict(&store).await;
+        }
+
+        #[tokio::test]
+        async fn test_delete() {
+            let (store, _dir) = get_store();
+            conformance::delete(&store).await;
+        }
+
+        #[tokio::test]
+        async fn test_delete_missing() {
+            let (store, _dir) = get_store();
+            conformance::delete_missing(&store).await;
+        }
+
+        #[tokio::test]
+        async fn test_query_filters() {
+            let (store, _dir) = get_store();
+            conformance::query_filters(&store).await;
+        }
+
+        #[tokio::test]
+        async fn test_query_pagination() {
+            let (store, _dir) = get_store();
+            conformance::query_pagination(&store).await;
+        }
+
+        #[tokio::test]
+        async fn test_purge() {
+            let (store, _dir) = get_store();
+            let store = store.with_retention(RetentionPolicy::default());
+            conformance::purge(&store).await;
+        }
+
+        #[tokio::test]
+        async fn test_purge_without_retention() {
+            let (store, _dir) = get_store();
+            conformance::purge_without_retention(&store).await;
+        }
+
+        #[tokio::test]
+        async fn test_expired() {
+            let (store, _dir) = get_store();
+            let store = store.with_retention(conformance::get_immediate_retention());
+            conformance::expired(&store).await;
+        }
+
+        #[tokio::test]
+        async fn test_idempotency() {
+            let (store, _dir) = get_store();
+            conformance::idempotency(&store, conformance::in_one_hour()).await;
+        }
+
+        #[tokio::test]
+        async fn test_idempotency_expired() {
+            let (store, _dir) = get_store();
+            conformance::idempotency_expired(&store).await;
+        }
+
+        #[tokio::test]
+        async fn test_idempotency_reservation() {
+            let (store, _dir) = get_store();
+            conformance::idempotency_reservation(&store, conformance::in_one_hour()).await;
+        }
+
+        #[tokio::test]
+        async fn test_reopen() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("testruns.db");
+            let store = DieselStore::new(DieselBackend::Sqlite, path.to_str().unwrap()).unwrap();
+            store.put(&conformance::get_testrun()).await.unwrap();
+            drop(store);
+
+            let store = DieselStore::new(DieselBackend::Sqlite, path.to_str().unwrap()).unwrap();
+
+            assert_eq!(
+                store.get("1").await.unwrap(),
+                Some(conformance::get_testrun())
+            );
+        }
+    }
+
+    /// These tests need a running PostgreSQL server, whose URL is read from
+    /// `POSTGRES_URL`. The database is emptied before each test.
+    #[cfg(feature = "postgres")]
+    mod postgres {
+        use super::*;
+
+        fn get_store() -> DieselStore {
+            let url = std::env::var("POSTGRES_URL").expect("POSTGRES_URL must be set");
+            let store = DieselStore::new(DieselBackend::Postgres, &url).unwrap();
+            let mut conn = store.pool.get().unwrap();
+            diesel::delete(schema::tests::table)
+                .execute(&mut conn)
+                .unwrap();
+            diesel::delete(testruns::table).execute(&mut conn).unwrap();
+            diesel::delete(idempotency_keys::table)
+                .execute(&mut conn)
+                .unwrap();
+
+            store
+        }
+
+        #[tokio::test]
+        #[ignore = "requires a PostgreSQL server"]
+        async fn test_conformance() {
+            // Run sequentially, as all scenarios share the same database
+            conformance::get_missing(&get_store()).await;
+            conformance::put_get(&get_store()).await;
+            conformance::put_overwrite(&get_store()).await;
+            conformance::put_conflict(&get_store()).await;
+            conformance::put_missing_conflict(&get_store()).await;
+            conformance::delete(&get_store()).await;
+            conformance::delete_missing(&get_store()).await;
+            conformance::query_filters(&get_store()).await;
+            conformance::query_pagination(&get_store()).await;
+            conformance::purge(&get_store().with_retention(RetentionPolicy::default())).await;
+            conformance::purge_without_retention(&get_store()).await;
+            conformance::expired(
+                &get_store().with_retention(conformance::get_immediate_retention()),
+            )
+            .await;
+            conformance::idempotency(&get_store(), conformance::in_one_hour()).await;
+            conformance::idempotency_expired(&get_store()).await;
+            conformance::idempotency_reservation(&get_store(), conformance::in_one_hour()).await;
+        }
+    }
+}
@@ -0,0 +1,43 @@
+//! # Database schema
+//!
+//! Matches the embedded migrations in `migrations/`.
+
+diesel::table! {
+    testruns (id) {
+        id -> Text,
+        language -> Text,
+        status -> Text,
+        files -> Text,
+        user_id -> Nullable<Text>,
+        challenge_id -> Nullable<Text>,
+        version -> BigInt,
+        expires_at -> Nullable<BigInt>,
+        created_at -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    tests (testrun_id, position) {
+        testrun_id -> Text,
+        position -> Integer,
+        name -> Text,
+        status -> Text,
+        message -> Text,
+        actual_output -> Text,
+        expected_output -> Text,
+    }
+}
+
+diesel::table! {
+    idempotency_keys (idempotency_key) {
+        idempotency_key -> Text,
+        request_hash -> Text,
+        status -> Integer,
+        headers -> Text,
+        body -> Text,
+        expires_at -> BigInt,
+    }
+}
+
+diesel::joinable!(tests -> testruns (testrun_id));
+diesel::allow_tables_to_appear_in_same_query!(testruns, tests);
@@ -0,0 +1,619 @@
+//! # Uploaded archives
+//!
+//! Expansion of the archives users upload into the `files` map of a testrun.
+//! Files that are not valid UTF-8 are kept as binary files, and the
+//! executable bit is preserved where the format records it.
+//! The format is detected from the leading bytes, and every format goes
+//! through `Files`, which enforces the same limits and path checks.
+
+use std::{collections::HashMap, io::Read};
+
+use base64::{
+    alphabet,
+    engine::{self, general_purpose, DecodePaddingMode},
+    Engine as _,
+};
+use flate2::read::GzDecoder;
+
+use super::zip;
+use crate::{error::ArchiveError, model::FileEntry};
+
+/// URL-safe base64 engine accepting input with or without padding
+pub(crate) const URL_SAFE_INDIFFERENT: engine::GeneralPurpose = engine::GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    general_purpose::NO_PAD.with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Limits applied when expanding an uploaded archive
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ArchiveLimits {
+    /// Maximum number of entries, including directories
+    pub max_entries: usize,
+    /// Maximum uncompressed size of a single file, in bytes
+    pub max_file_size: u64,
+    /// Maximum uncompressed size of all files together, in bytes
+    pub max_total_size: u64,
+}
+
+impl Default for ArchiveLimits {
+    fn default() -> Self {
+        ArchiveLimits {
+            max_entries: 1000,
+            max_file_size: 1024 * 1024,
+            max_total_size: 10 * 1024 * 1024,
+        }
+    }
+}
+
+impl ArchiveLimits {
+    /// Read the limits from the environment
+    ///
+    /// `ARCHIVE_MAX_ENTRIES`, `ARCHIVE_MAX_FILE_SIZE` and
+    /// `ARCHIVE_MAX_TOTAL_SIZE` override the defaults, and must be a number
+    /// when set.
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(key: &str) -> Option<T> {
+            let value = std::env::var(key).ok()?;
+            Some(
+                value
+                    .parse()
+                    .unwrap_or_else(|_| panic!("Unsupported {}: {}", key, value)),
+            )
+        }
+
+        let default = ArchiveLimits::default();
+        ArchiveLimits {
+            max_entries: var("ARCHIVE_MAX_ENTRIES").unwrap_or(default.max_entries),
+            max_file_size: var("ARCHIVE_MAX_FILE_SIZE").unwrap_or(default.max_file_size),
+            max_total_size: var("ARCHIVE_MAX_TOTAL_SIZE").unwrap_or(default.max_total_size),
+        }
+    }
+}
+
+/// Supported archive formats
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    /// Detect the format of an archive from its magic bytes
+    ///
+    /// A gzip stream is assumed to hold a tar archive.
+    pub fn detect(bytes: &[u8]) -> Option<ArchiveFormat> {
+        if bytes.starts_with(b"PK\x03\x04") || bytes.starts_with(b"PK\x05\x06") {
+            Some(ArchiveFormat::Zip)
+        } else if bytes.starts_with(&[0x1f, 0x8b]) {
+            Some(ArchiveFormat::TarGz)
+        } else if bytes.get(257..262) == Some(b"ustar") {
+            Some(ArchiveFormat::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+/// Expand a URL-safe base64 archive of any supported format
+pub fn process_archive_string(
+    archive: &str,
+    limits: &ArchiveLimits,
+) -> Result<HashMap<String, FileEntry>, ArchiveError> {
+    let bytes = URL_SAFE_INDIFFERENT
+        .decode(archive.trim())
+        .map_err(|_| ArchiveError::InvalidEncoding)?;
+
+    expand(&bytes, limits)
+}
+
+/// Expand an archive of any supported format
+pub fn expand(
+    bytes: &[u8],
+    limits: &ArchiveLimits,
+) -> Result<HashMap<String, FileEntry>, ArchiveError> {
+    match ArchiveFormat::detect(bytes) {
+        Some(ArchiveFormat::Zip) => zip::expand_zip(bytes, limits),
+        Some(ArchiveFormat::Tar) => expand_tar(bytes, limits),
+        Some(ArchiveFormat::TarGz) => {
+            let reader = CappedReader::new(GzDecoder::new(bytes), max_tar_size(limits));
+            expand_tar(reader, limits)
+        }
+        None => Err(ArchiveError::UnknownFormat),
+    }
+}
+
+/// Largest tar stream that can hold files within `limits`
+///
+/// Leaves room for a header, an extension header and their padding around
+/// every entry, and for the blocks ending the archive.
+fn max_tar_size(limits: &ArchiveLimits) -> u64 {
+    let overhead = (limits.max_entries as u64).saturating_add(1) * 4 * 512;
+
+    limits.max_total_size.saturating_add(overhead)
+}
+
+/// Reader failing with `TooLarge` once more than `limit` bytes were read
+///
+/// Bounds decompression, as tar reads and discards the data of the entries
+/// it skips.
+struct CappedReader<R> {
+    inner: R,
+    remaining: u64,
+}
+
+impl<R: Read> CappedReader<R> {
+    fn new(inner: R, limit: u64) -> Self {
+        CappedReader {
+            inner,
+            remaining: limit,
+        }
+    }
+}
+
+impl<R: Read> Read for CappedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // Read one byte over the limit to detect larger streams
+        let max = usize::try_from(self.remaining.saturating_add(1)).unwrap_or(usize::MAX);
+        let max = buf.len().min(max);
+        let read = self.inner.read(&mut buf[..max])?;
+        if read as u64 > self.remaining {
+            return Err(std::io::Error::other(TooLarge));
+        }
+        self.remaining -= read as u64;
+
+        Ok(read)
+    }
+}
+
+/// Error of a `CappedReader` over its limit
+#[derive(Debug)]
+struct TooLarge;
+
+impl std::fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "archive is too large once uncompressed")
+    }
+}
+
+impl std::error::Error for TooLarge {}
+
+/// Expand a tar archive
+///
+/// Directories are skipped, as are metadata entries such as global pax
+/// headers, but the size they declare still counts towards the limits. Any
+/// kind of link is refused.
+fn expand_tar(
+    reader: impl Read,
+    limits: &ArchiveLimits,
+) -> Result<HashMap<String, FileEntry>, ArchiveError> {
+    let mut files = Files::new(limits);
+    let invalid = |err: std::io::Error| read_error(err, limits);
+
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries().map_err(invalid)? {
+        let entry = entry.map_err(invalid)?;
+        files.entry()?;
+
+        let path = entry.path_bytes();
+        let path = String::from_utf8_lossy(&path);
+        // Archives created with `tar -C dir .` prefix every path with `./`
+        let path = path.strip_prefix("./").unwrap_or(&path).to_owned();
+
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_symlink() || entry_type.is_hard_link() {
+            return Err(ArchiveError::Symlink(path));
+        }
+        let size = entry.header().size().map_err(invalid)?;
+        if !entry_type.is_file() {
+            files.skip(size)?;
+            continue;
+        }
+
+        let executable = entry.header().mode().is_ok_and(|mode| mode & 0o111 != 0);
+        files.add(path, Some(size), executable, entry)?;
+    }
+
+    Ok(files.into_inner())
+}
+
+/// Files expanded from an upload
+///
+/// Keeps track of the number of entries and the total size, and rejects
+/// anything over the limits.
+pub(crate) struct Files<'a> {
+    limits: &'a ArchiveLimits,
+    files: HashMap<String, FileEntry>,
+    entries: usize,
+    total_size: u64,
+}
+
+impl<'a> Files<'a> {
+    pub(crate) fn new(limits: &'a ArchiveLimits) -> Self {
+        Files {
+            limits,
+            files: HashMap::new(),
+            entries: 0,
+            total_size: 0,
+        }
+    }
+
+    /// Count an entry, whether or not it is a file
+    pub(crate) fn entry(&mut self) -> Result<(), ArchiveError> {
+        self.entries += 1;
+        match self.entries > self.limits.max_entries {
+            true => Err(ArchiveError::TooManyEntries {
+                limit: self.limits.max_entries,
+            }),
+            false => Ok(()),
+        }
+    }
+
+    /// Read a file
+    ///
+    /// `declared_size` is the size recorded in the archive, if any, which
+    /// allows rejecting large files early. The limits are still checked
+    /// against the bytes actually read, so a crafted header cannot bypass
+    /// them.
+    pub(crate) fn add(
+        &mut self,
+        path: String,
+        declared_size: Option<u64>,
+        executable: bool,
+        reader: impl Read,
+    ) -> Result<(), ArchiveError> {
+        check_path(&path)?;
+
+        let limit = self.limits.max_file_size;
+        let too_large = |path| ArchiveError::FileTooLarge { path, limit };
+        if declared_size.is_some_and(|size| size > limit) {
+            return Err(too_large(path));
+        }
+
+        // Read at most one byte over the limit to detect oversized files
+        let mut contents: Vec<u8> = Vec::new();
+        reader
+            .take(limit + 1)
+            .read_to_end(&mut contents)
+            .map_err(|err| read_error(err, self.limits))?;
+
+        let size = contents.len() as u64;
+        if size > limit {
+            return Err(too_large(path));
+        }
+        self.count(size)?;
+
+        let file = FileEntry::from_bytes(contents).with_executable(executable);
+        self.files.insert(path, file);
+
+        Ok(())
+    }
+
+    /// Count an entry that is not read, such as a directory
+    ///
+    /// `declared_size` is the size of its data recorded in the archive,
+    /// which may still have to be read through to reach the next entry.
+    pub(crate) fn skip(&mut self, declared_size: u64) -> Result<(), ArchiveError> {
+        self.count(declared_size)
+    }
+
+    /// Add `size` bytes to the total size
+    fn count(&mut self, size: u64) -> Result<(), ArchiveError> {
+        self.total_size = self.total_size.saturating_add(size);
+        match self.total_size > self.limits.max_total_size {
+            true => Err(ArchiveError::ArchiveTooLarge {
+                limit: self.limits.max_total_size,
+            }),
+            false => Ok(()),
+        }
+    }
+
+    pub(crate) fn into_inner(self) -> HashMap<String, FileEntry> {
+        self.files
+    }
+}
+
+/// Error for an archive that could not be read
+fn read_error(err: std::io::Error, limits: &ArchiveLimits) -> ArchiveError {
+    match err.get_ref().is_some_and(|err| err.is::<TooLarge>()) {
+        true => ArchiveError::ArchiveTooLarge {
+            limit: limits.max_total_size,
+        },
+        false => ArchiveError::InvalidArchive(err.to_string()),
+    }
+}
+
+/// Reject paths that are absolute or could escape the archive root
+///
+/// Both `/` and `\` are treated as separators, as archives created on
+/// Windows may use either.
+pub(crate) fn check_path(path: &str) -> Result<(), ArchiveError> {
+    let mut components = path.split(['/', '\\']);
+    let is_unsafe = path.is_empty()
+        || path.starts_with(['/', '\\'])
+        // Windows drive prefix, such as `C:`
+        || components.next().is_some_and(|first| first.contains(':'))
+        || path.split(['/', '\\']).any(|component| component == "..");
+
+    match is_unsafe {
+        true => Err(ArchiveError::UnsafePath(path.to_owned())),
+        false => Ok(()),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+
+    /// Build a tar archive from a list of path and contents
+    ///
+    /// Paths ending with `/` are added as directories.
+    pub(crate) fn get_tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let entries = files
+            .iter()
+            .map(|(path, contents)| (*path, *contents, 0o644))
+            .collect::<Vec<_>>();
+
+        get_tar_with_modes(&entries)
+    }
+
+    /// Build a tar archive from a list of path, contents and mode
+    fn get_tar_with_modes(files: &[(&str, &[u8], u32)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, contents, mode) in files {
+            let mut header = tar::Header::new_ustar();
+            header.set_mode(*mode);
+            header.set_size(contents.len() as u64);
+            if path.ends_with('/') {
+                header.set_entry_type(tar::EntryType::Directory);
+            }
+            // `append_data` refuses unsafe paths, so set them on the header
+            header.as_ustar_mut().unwrap().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_cksum();
+            builder.append(&header, *contents).unwrap();
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    pub(crate) fn get_tar_gz(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        std::io::Write::write_all(&mut encoder, &get_tar(files)).unwrap();
+
+        encoder.finish().unwrap()
+    }
+
+    fn expected() -> HashMap<String, FileEntry> {
+        HashMap::from([
+            ("src/main.py".to_owned(), "print('hello')\n".into()),
+            ("README.md".to_owned(), "".into()),
+        ])
+    }
+
+    const FILES: &[(&str, &[u8])] = &[
+        ("src/", b""),
+        ("src/main.py", b"print('hello')\n"),
+        ("README.md", b""),
+    ];
+
+    #[test]
+    fn detect_formats() {
+        let zip = zip::tests::get_zip(FILES);
+
+        assert_eq!(ArchiveFormat::detect(&zip), Some(ArchiveFormat::Zip));
+        assert_eq!(
+            ArchiveFormat::detect(&get_tar(FILES)),
+            Some(ArchiveFormat::Tar)
+        );
+        assert_eq!(
+            ArchiveFormat::detect(&get_tar_gz(FILES)),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(ArchiveFormat::detect(b"print('hello')"), None);
+    }
+
+    #[test]
+    fn expand_all_formats() {
+        let limits = ArchiveLimits::default();
+
+        for bytes in [
+            zip::tests::get_zip(FILES),
+            get_tar(FILES),
+            get_tar_gz(FILES),
+        ] {
+            assert_eq!(expand(&bytes, &limits), Ok(expected()));
+        }
+    }
+
+    #[test]
+    fn expand_archive_string() {
+        let archive = general_purpose::URL_SAFE_NO_PAD.encode(get_tar_gz(FILES));
+
+        let files = process_archive_string(&archive, &ArchiveLimits::default());
+
+        assert_eq!(files, Ok(expected()));
+    }
+
+    #[test]
+    fn expand_unknown_format() {
+        assert_eq!(
+            expand(b"not an archive", &ArchiveLimits::default()),
+            Err(ArchiveError::UnknownFormat)
+        );
+    }
+
+    #[test]
+    fn tar_strip_current_dir() {
+        let bytes = get_tar(&[("./", b""), ("./main.py", b"")]);
+
+        let files = expand(&bytes, &ArchiveLimits::default()).unwrap();
+
+        assert_eq!(files, HashMap::from([("main.py".to_owned(), "".into())]));
+    }
+
+    #[test]
+    fn tar_binary_and_executable() {
+        let bytes = get_tar_with_modes(&[
+            ("run.sh", b"#!/bin/sh\n", 0o755),
+            ("main.wasm", b"\0asm\xff", 0o644),
+        ]);
+
+        let files = expand(&bytes, &ArchiveLimits::default()).unwrap();
+
+        assert_eq!(
+            files,
+            HashMap::from([
+                (
+                    "run.sh".to_owned(),
+                    FileEntry::text("#!/bin/sh\n").with_executable(true)
+                ),
+                (
+                    "main.wasm".to_owned(),
+                    FileEntry::binary(b"\0asm\xff".to_vec())
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn tar_unsafe_path() {
+        let bytes = get_tar_gz(&[("../main.py", b"")]);
+
+        assert_eq!(
+            expand(&bytes, &ArchiveLimits::default()),
+            Err(ArchiveError::UnsafePath("../main.py".to_owned()))
+        );
+    }
+
+    #[test]
+    fn tar_symlink() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_ustar();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "main.py", "/etc/passwd")
+            .unwrap();
+        let bytes = builder.into_inner().unwrap();
+
+        assert_eq!(
+            expand(&bytes, &ArchiveLimits::default()),
+            Err(ArchiveError::Symlink("main.py".to_owned()))
+        );
+    }
+
+    #[test]
+    fn tar_gz_bomb() {
+        // Compresses to a few kilobytes
+        let contents = vec![b'a'; 4 * 1024 * 1024];
+        let bytes = get_tar_gz(&[("main.py", &contents)]);
+        let limits = ArchiveLimits {
+            max_file_size: 1024,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            expand(&bytes, &limits),
+            Err(ArchiveError::FileTooLarge {
+                path: "main.py".to_owned(),
+                limit: 1024
+            })
+        );
+    }
+
+    #[test]
+    fn tar_gz_large_directory() {
+        // A directory declaring 4 MiB of data, which compresses to a few kilobytes
+        let contents = vec![0; 4 * 1024 * 1024];
+        let bytes = get_tar_gz(&[("src/", &contents), ("main.py", b"")]);
+        let limits = ArchiveLimits {
+            max_total_size: 1024,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            expand(&bytes, &limits),
+            Err(ArchiveError::ArchiveTooLarge { limit: 1024 })
+        );
+    }
+
+    #[test]
+    fn tar_gz_large_extension() {
+        // A pax extension header, which tar reads on its own, holding 4 MiB
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_ustar();
+        header.set_entry_type(tar::EntryType::XHeader);
+        header.set_size(4 * 1024 * 1024);
+        header.set_cksum();
+        builder
+            .append(&header, &vec![b'a'; 4 * 1024 * 1024][..])
+            .unwrap();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        std::io::Write::write_all(&mut encoder, &builder.into_inner().unwrap()).unwrap();
+        let bytes = encoder.finish().unwrap();
+        let limits = ArchiveLimits {
+            max_entries: 10,
+            max_total_size: 1024,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            expand(&bytes, &limits),
+            Err(ArchiveError::ArchiveTooLarge { limit: 1024 })
+        );
+    }
+
+    #[test]
+    fn tar_gz_at_total_limit() {
+        let contents = vec![b'a'; 1024];
+        let bytes = get_tar_gz(&[("a.py", &contents), ("b.py", &contents)]);
+        let limits = ArchiveLimits {
+            max_entries: 2,
+            max_total_size: 2048,
+            ..Default::default()
+        };
+
+        assert_eq!(expand(&bytes, &limits).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn tar_too_many_entries() {
+        let bytes = get_tar(&[("a.py", b""), ("b.py", b""), ("c.py", b"")]);
+        let limits = ArchiveLimits {
+            max_entries: 2,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            expand(&bytes, &limits),
+            Err(ArchiveError::TooManyEntries { limit: 2 })
+        );
+    }
+
+    #[test]
+    fn safe_paths() {
+        for path in ["main.py", "./src/..main.py", ".github/ci.yml", "a/b/c"] {
+            assert_eq!(check_path(path), Ok(()), "{}", path);
+        }
+    }
+
+    #[test]
+    fn unsafe_paths() {
+        for path in [
+            "",
+            "../main.py",
+            "src/../../main.py",
+            "/etc/passwd",
+            "..\\main.py",
+            "C:\\main.py",
+        ] {
+            assert_eq!(
+                check_path(path),
+                Err(ArchiveError::UnsafePath(path.to_owned())),
+                "{}",
+                path
+            );
+        }
+    }
+}
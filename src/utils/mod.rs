@@ -1,18 +1,70 @@
-use crate::{events, events::eventbridge, model, store};
+use crate::{
+    entrypoints::auth::{Authenticator, JwtAuthenticator, NoAuthenticator},
+    events,
+    events::eventbridge,
+    model, ratelimit, store,
+    store::{blob, Compression},
+};
 use tracing::{info, instrument};
 
+pub mod archive;
+pub mod multipart;
 pub mod zip;
 
 /// Setup tracing
-pub fn setup_tracing() -> () {
+pub fn setup_tracing() {
     let subscriber = tracing_subscriber::fmt().json().finish();
 
     tracing::subscriber::set_global_default(subscriber).expect("failed to set tracing subscriber");
 }
 
 /// Initialize a store
+///
+/// The backend is selected with the `STORE_BACKEND` environment variable:
+///
+/// * `dynamodb` (default): DynamoDB table named by `TABLE_NAME`
+/// * `memory`: in-memory store, lost when the process exits
+/// * `sqlite` / `postgres`: database at `DATABASE_URL`, if the matching cargo
+///   feature is enabled
+///
+/// Testruns expire according to `RetentionPolicy::from_env`.
 #[instrument]
-pub async fn get_store() -> impl store::Store {
+pub async fn get_store() -> Box<dyn store::Store> {
+    let backend = std::env::var("STORE_BACKEND").unwrap_or_else(|_| "dynamodb".to_owned());
+    let retention = store::RetentionPolicy::from_env();
+
+    match backend.as_str() {
+        "dynamodb" => Box::new(get_dynamodb_store().await.with_retention(retention)),
+        "memory" => {
+            info!("Initializing in-memory store");
+            Box::new(store::MemoryStore::new().with_retention(retention))
+        }
+        #[cfg(feature = "sqlite")]
+        "sqlite" => {
+            Box::new(get_diesel_store(store::DieselBackend::Sqlite).with_retention(retention))
+        }
+        #[cfg(feature = "postgres")]
+        "postgres" => {
+            Box::new(get_diesel_store(store::DieselBackend::Postgres).with_retention(retention))
+        }
+        _ => panic!("Unsupported STORE_BACKEND: {}", backend),
+    }
+}
+
+/// Default size above which `files` and `tests` are spilled to a blob store
+///
+/// Both attributes can reach it while keeping the item under the 400 KB
+/// DynamoDB limit.
+const DEFAULT_BLOB_THRESHOLD: usize = 100 * 1024;
+
+/// Initialize a DynamoDB store
+///
+/// `STORE_COMPRESSION` (`none`, `gzip` or `zstd`) sets the compression of
+/// written items. Large attributes are spilled to the blob store from
+/// `get_blob_store`, if any, above `BLOB_THRESHOLD` bytes. Idempotency keys
+/// are recorded in the table named by `IDEMPOTENCY_TABLE_NAME`; without it,
+/// requests with a key fail.
+async fn get_dynamodb_store() -> store::DynamoDBStore {
     // Get AWS Configuration
     let config = aws_config::load_from_env().await;
 
@@ -23,20 +75,195 @@ pub async fn get_store() -> impl store::Store {
         table_name
     );
     let client = aws_sdk_dynamodb::Client::new(&config);
-    store::DynamoDBStore::new(client, table_name)
+    let compression = match std::env::var("STORE_COMPRESSION") {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("Unsupported STORE_COMPRESSION: {}", value)),
+        Err(_) => Compression::None,
+    };
+    let mut store = store::DynamoDBStore::new(client, table_name).with_compression(compression);
+    if let Ok(table_name) = std::env::var("IDEMPOTENCY_TABLE_NAME") {
+        info!("Recording idempotency keys in table: {}", table_name);
+        store = store.with_idempotency_table(table_name);
+    }
+
+    match get_blob_store().await {
+        Some(blob_store) => {
+            let threshold = match std::env::var("BLOB_THRESHOLD") {
+                Ok(value) => value
+                    .parse()
+                    .unwrap_or_else(|_| panic!("Unsupported BLOB_THRESHOLD: {}", value)),
+                Err(_) => DEFAULT_BLOB_THRESHOLD,
+            };
+            store.with_blob_store(blob_store, threshold)
+        }
+        None => store,
+    }
+}
+
+/// Initialize a blob store
+///
+/// The backend is selected with the `BLOB_BACKEND` environment variable:
+///
+/// * unset: no blob store
+/// * `s3`: S3 bucket named by `BLOB_BUCKET_NAME`
+/// * `local`: directory at `BLOB_DIR`
+#[instrument]
+pub async fn get_blob_store() -> Option<Box<dyn blob::BlobStore>> {
+    let backend = std::env::var("BLOB_BACKEND").ok()?;
+
+    match backend.as_str() {
+        "s3" => {
+            let config = aws_config::load_from_env().await;
+            let bucket_name =
+                std::env::var("BLOB_BUCKET_NAME").expect("BLOB_BUCKET_NAME must be set");
+            info!("Initializing S3 blob store with bucket: {}", bucket_name);
+            let client = aws_sdk_s3::Client::new(&config);
+            Some(Box::new(blob::S3BlobStore::new(client, bucket_name)))
+        }
+        "local" => {
+            let dir = std::env::var("BLOB_DIR").expect("BLOB_DIR must be set");
+            info!("Initializing local blob store in: {}", dir);
+            Some(Box::new(blob::LocalBlobStore::new(dir)))
+        }
+        _ => panic!("Unsupported BLOB_BACKEND: {}", backend),
+    }
+}
+
+/// Initialize a SQL database store
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+fn get_diesel_store(backend: store::DieselBackend) -> store::DieselStore {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    info!("Initializing {:?} store", backend);
+    store::DieselStore::new(backend, &database_url).expect("failed to initialize database")
+}
+
+/// Initialize a rate limiter
+///
+/// Buckets and counters are shared through the DynamoDB table named by
+/// `RATE_LIMIT_TABLE_NAME` if set, and kept in memory otherwise.
+#[instrument]
+pub async fn get_rate_limiter() -> Box<dyn ratelimit::RateLimiter> {
+    match std::env::var("RATE_LIMIT_TABLE_NAME") {
+        Ok(table_name) => {
+            let config = aws_config::load_from_env().await;
+            info!(
+                "Initializing DynamoDB rate limiter with table: {}",
+                table_name
+            );
+            let client = aws_sdk_dynamodb::Client::new(&config);
+            Box::new(ratelimit::DynamoDBRateLimiter::new(client, table_name))
+        }
+        Err(_) => {
+            info!("Initializing in-memory rate limiter");
+            Box::new(ratelimit::MemoryRateLimiter::new())
+        }
+    }
+}
+
+/// Initialize an authenticator
+///
+/// Callers must send a JWT bearer token as soon as one of these environment
+/// variables is set, and are anonymous otherwise:
+///
+/// * `JWT_HS256_SECRET`: shared secret of HS256 tokens
+/// * `JWT_RS256_PUBLIC_KEY_FILE`: PEM public key of RS256 tokens
+/// * `JWT_JWKS_FILE`: local JSON Web Key Set
+///
+/// `JWT_ISSUER` and `JWT_AUDIENCE` restrict the accepted tokens, if set.
+#[instrument]
+pub fn get_authenticator() -> Box<dyn Authenticator> {
+    let secret = std::env::var("JWT_HS256_SECRET").ok();
+    let public_key_file = std::env::var("JWT_RS256_PUBLIC_KEY_FILE").ok();
+    let jwks_file = std::env::var("JWT_JWKS_FILE").ok();
+    if secret.is_none() && public_key_file.is_none() && jwks_file.is_none() {
+        info!("Authentication is disabled");
+        return Box::new(NoAuthenticator);
+    }
+
+    let mut auth = JwtAuthenticator::new();
+    if let Some(secret) = secret {
+        info!("Accepting HS256 tokens");
+        auth = auth.with_hs256_secret(secret.as_bytes());
+    }
+    if let Some(path) = public_key_file {
+        info!("Accepting RS256 tokens verified with: {}", path);
+        let pem = std::fs::read(&path).expect("failed to read JWT_RS256_PUBLIC_KEY_FILE");
+        auth = auth
+            .with_rs256_pem(&pem)
+            .expect("invalid JWT_RS256_PUBLIC_KEY_FILE");
+    }
+    if let Some(path) = jwks_file {
+        info!("Accepting tokens signed with the keys of: {}", path);
+        let jwks = std::fs::read_to_string(&path).expect("failed to read JWT_JWKS_FILE");
+        auth = auth.with_jwks(&jwks).expect("invalid JWT_JWKS_FILE");
+    }
+    if let Ok(issuer) = std::env::var("JWT_ISSUER") {
+        auth = auth.with_issuer(issuer);
+    }
+    if let Ok(audience) = std::env::var("JWT_AUDIENCE") {
+        auth = auth.with_audience(audience);
+    }
+
+    Box::new(auth)
 }
 
 /// Create an event service
+///
+/// The backend is selected with the `EVENT_BUS_BACKEND` environment variable:
+///
+/// * `eventbridge` (default): EventBridge bus named by `EVENT_BUS_NAME`
+/// * `sns`: SNS topic with the ARN in `SNS_TOPIC_ARN`
+/// * `sqs`: SQS queue at `SQS_QUEUE_URL`
+///
+/// `EVENT_BUS_ENDPOINT` sends SNS and SQS requests to a stand-in such as
+/// LocalStack. `EVENT_DETAIL` (`full` by default, or `slim`) sets how much of a
+/// testrun events carry.
 #[instrument]
-pub async fn get_event_bus() -> impl events::EventBus<E = model::Event> {
+pub async fn get_event_bus() -> Box<dyn events::EventBus<E = model::Event>> {
     // Get AWS Configuration
     let config = aws_config::load_from_env().await;
 
-    // Initialize an EventBridge if the environment variable is set
-    let event_bus_name = std::env::var("EVENT_BUS_NAME").expect("EVENT_BUS_NAME must be set");
+    let detail = match std::env::var("EVENT_DETAIL") {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("Unsupported EVENT_DETAIL: {}", value)),
+        Err(_) => events::EventDetail::Full,
+    };
+    let endpoint = std::env::var("EVENT_BUS_ENDPOINT").ok();
+
+    let backend = std::env::var("EVENT_BUS_BACKEND").unwrap_or_else(|_| "eventbridge".to_owned());
+    match backend.as_str() {
+        "eventbridge" => {
+            let event_bus_name =
+                std::env::var("EVENT_BUS_NAME").expect("EVENT_BUS_NAME must be set");
+            info!("Initializing EventBridge bus with name: {}", event_bus_name);
+
+            let client = aws_sdk_eventbridge::Client::new(&config);
+            Box::new(eventbridge::EventBridgeBus::new(client, event_bus_name).with_detail(detail))
+        }
+        "sns" => {
+            let topic_arn = std::env::var("SNS_TOPIC_ARN").expect("SNS_TOPIC_ARN must be set");
+            info!("Initializing SNS bus with topic: {}", topic_arn);
 
-    info!("Initializing EventBridge bus with name: {}", event_bus_name);
+            let mut builder = aws_sdk_sns::config::Builder::from(&config);
+            if let Some(endpoint) = endpoint {
+                builder = builder.endpoint_url(endpoint);
+            }
+            let client = aws_sdk_sns::Client::from_conf(builder.build());
+            Box::new(events::sns::SnsBus::new(client, topic_arn).with_detail(detail))
+        }
+        "sqs" => {
+            let queue_url = std::env::var("SQS_QUEUE_URL").expect("SQS_QUEUE_URL must be set");
+            info!("Initializing SQS bus with queue: {}", queue_url);
 
-    let client = aws_sdk_eventbridge::Client::new(&config);
-    eventbridge::EventBridgeBus::new(client, event_bus_name)
+            let mut builder = aws_sdk_sqs::config::Builder::from(&config);
+            if let Some(endpoint) = endpoint {
+                builder = builder.endpoint_url(endpoint);
+            }
+            let client = aws_sdk_sqs::Client::from_conf(builder.build());
+            Box::new(events::sqs::SqsBus::new(client, queue_url).with_detail(detail))
+        }
+        _ => panic!("Unsupported EVENT_BUS_BACKEND: {}", backend),
+    }
 }
@@ -0,0 +1,283 @@
+//! # Multipart bodies
+//!
+//! Minimal parser for `multipart/form-data` bodies (RFC 7578), as sent by
+//! browsers uploading files. The whole body is expected to be in memory, and
+//! parts borrow their data from it.
+
+use crate::error::Error;
+
+/// Part of a `multipart/form-data` body
+#[derive(Debug, PartialEq)]
+pub struct Part<'a> {
+    /// Name of the form field
+    pub name: String,
+    /// File name, for file fields
+    pub filename: Option<String>,
+    pub data: &'a [u8],
+}
+
+/// Extract the boundary from a `multipart/form-data` content type
+///
+/// Returns `None` for any other content type.
+pub fn boundary(content_type: &str) -> Option<String> {
+    let mut params = split_params(content_type).into_iter();
+    let mime = params.next()?;
+    if !mime.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+
+    params
+        .filter_map(|param| parse_param(&param))
+        .find(|(key, _)| key.eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value)
+        .filter(|boundary| !boundary.is_empty())
+}
+
+/// Split a `multipart/form-data` body into its parts
+pub fn parse<'a>(body: &'a [u8], boundary: &str) -> Result<Vec<Part<'a>>, Error> {
+    let delimiter = format!("--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+
+    // Skip the preamble
+    let start = find(body, delimiter).ok_or(Error::ClientError("Malformed multipart body"))?;
+    let mut rest = &body[start + delimiter.len()..];
+
+    let mut parts = Vec::new();
+    loop {
+        // Closing delimiter, anything after it is an epilogue
+        if rest.starts_with(b"--") {
+            return Ok(parts);
+        }
+        rest = rest
+            .strip_prefix(b"\r\n")
+            .ok_or(Error::ClientError("Malformed multipart body"))?;
+
+        let headers_end = find(rest, b"\r\n\r\n")
+            .ok_or(Error::ClientError("Malformed multipart part headers"))?;
+        let headers = std::str::from_utf8(&rest[..headers_end])
+            .map_err(|_| Error::ClientError("Malformed multipart part headers"))?;
+        rest = &rest[headers_end + 4..];
+
+        // The data ends right before the CRLF preceding the next delimiter
+        let data_end = find_delimiter(rest, delimiter)
+            .ok_or(Error::ClientError("Unterminated multipart body"))?;
+        let data = &rest[..data_end];
+        rest = &rest[data_end + 2 + delimiter.len()..];
+
+        let (name, filename) = parse_disposition(headers)?;
+        parts.push(Part {
+            name,
+            filename,
+            data,
+        });
+    }
+}
+
+/// Name and file name from the `Content-Disposition` header of a part
+fn parse_disposition(headers: &str) -> Result<(String, Option<String>), Error> {
+    let disposition = headers
+        .split("\r\n")
+        .filter_map(|header| header.split_once(':'))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("content-disposition"))
+        .map(|(_, value)| value)
+        .ok_or(Error::ClientError(
+            "Missing Content-Disposition in multipart part",
+        ))?;
+
+    let mut params = split_params(disposition).into_iter();
+    if !params
+        .next()
+        .is_some_and(|kind| kind.trim().eq_ignore_ascii_case("form-data"))
+    {
+        return Err(Error::ClientError("Multipart part is not form data"));
+    }
+
+    let mut name = None;
+    let mut filename = None;
+    for (key, value) in params.filter_map(|param| parse_param(&param)) {
+        match key.to_ascii_lowercase().as_str() {
+            "name" => name = Some(value),
+            "filename" => filename = Some(value),
+            _ => (),
+        }
+    }
+
+    let name = name.ok_or(Error::ClientError("Missing name in multipart part"))?;
+    Ok((name, filename))
+}
+
+/// Split a header value on `;`, ignoring separators in quoted strings
+fn split_params(value: &str) -> Vec<String> {
+    let mut params = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for c in value.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                params.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => (),
+        }
+        current.push(c);
+    }
+    params.push(current);
+
+    params
+}
+
+/// Parse a `key=value` parameter, unquoting the value if needed
+fn parse_param(param: &str) -> Option<(String, String)> {
+    let (key, value) = param.split_once('=')?;
+    let value = value.trim();
+    let value = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(quoted) => quoted.replace("\\\"", "\"").replace("\\\\", "\\"),
+        None => value.to_owned(),
+    };
+
+    Some((key.trim().to_owned(), value))
+}
+
+/// Position of the CRLF before the next delimiter
+fn find_delimiter(haystack: &[u8], delimiter: &[u8]) -> Option<usize> {
+    let mut offset = 0;
+    while let Some(position) = find(&haystack[offset..], b"\r\n") {
+        let position = offset + position;
+        if haystack[position + 2..].starts_with(delimiter) {
+            return Some(position);
+        }
+        offset = position + 2;
+    }
+
+    None
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) const BOUNDARY: &str = "----boundary";
+
+    /// Build a `multipart/form-data` body from a list of name, file name and
+    /// data
+    pub(crate) fn get_body(parts: &[(&str, Option<&str>, &[u8])]) -> Vec<u8> {
+        let mut body = b"preamble\r\n".to_vec();
+        for (name, filename, data) in parts {
+            body.extend(format!("--{}\r\n", BOUNDARY).as_bytes());
+            let disposition = match filename {
+                Some(filename) => format!(
+                    "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n",
+                    name, filename
+                ),
+                None => format!("Content-Disposition: form-data; name=\"{}\"\r\n", name),
+            };
+            body.extend(disposition.as_bytes());
+            body.extend(b"Content-Type: application/octet-stream\r\n\r\n");
+            body.extend(*data);
+            body.extend(b"\r\n");
+        }
+        body.extend(format!("--{}--\r\n", BOUNDARY).as_bytes());
+
+        body
+    }
+
+    #[test]
+    fn multipart_boundary() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=abc"),
+            Some("abc".to_owned())
+        );
+        assert_eq!(
+            boundary(r#"Multipart/Form-Data; charset=utf-8; boundary="a;b c""#),
+            Some("a;b c".to_owned())
+        );
+        assert_eq!(boundary("multipart/form-data"), None);
+        assert_eq!(boundary("application/json"), None);
+    }
+
+    #[test]
+    fn multipart_parse() {
+        let body = get_body(&[
+            ("testrun", None, br#"{"id": "1"}"#),
+            ("files", Some("src/main.py"), b"print('hello')\r\n"),
+            ("files", Some("empty.py"), b""),
+        ]);
+
+        let parts = parse(&body, BOUNDARY).unwrap();
+
+        assert_eq!(
+            parts,
+            vec![
+                Part {
+                    name: "testrun".to_owned(),
+                    filename: None,
+                    data: br#"{"id": "1"}"#,
+                },
+                Part {
+                    name: "files".to_owned(),
+                    filename: Some("src/main.py".to_owned()),
+                    data: b"print('hello')\r\n",
+                },
+                Part {
+                    name: "files".to_owned(),
+                    filename: Some("empty.py".to_owned()),
+                    data: b"",
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn multipart_binary_data() {
+        let data = [0x00, 0xff, b'\r', b'\n', b'-', b'-', 0x1f, 0x8b];
+        let body = get_body(&[("archive", Some("src.tar.gz"), &data)]);
+
+        let parts = parse(&body, BOUNDARY).unwrap();
+
+        assert_eq!(parts[0].data, data);
+    }
+
+    #[test]
+    fn multipart_quoted_filename() {
+        let body = format!(
+            "--{0}\r\nContent-Disposition: form-data; name=\"files\"; filename=\"a; \\\"b\\\".py\"\r\n\r\n\r\n--{0}--",
+            BOUNDARY
+        );
+
+        let parts = parse(body.as_bytes(), BOUNDARY).unwrap();
+
+        assert_eq!(parts[0].filename.as_deref(), Some(r#"a; "b".py"#));
+    }
+
+    #[test]
+    fn multipart_unterminated() {
+        let mut body = get_body(&[("files", Some("main.py"), b"print('hello')")]);
+        body.truncate(body.len() - BOUNDARY.len() - 8);
+
+        assert!(matches!(parse(&body, BOUNDARY), Err(Error::ClientError(_))));
+    }
+
+    #[test]
+    fn multipart_missing_name() {
+        let body = format!(
+            "--{0}\r\nContent-Disposition: form-data\r\n\r\n\r\n--{0}--",
+            BOUNDARY
+        );
+
+        assert!(matches!(
+            parse(body.as_bytes(), BOUNDARY),
+            Err(Error::ClientError(_))
+        ));
+    }
+}
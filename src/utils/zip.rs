@@ -1,45 +1,253 @@
-use std::{
-    collections::HashMap,
-    io::{Cursor, Read},
-};
-
-use base64::{
-    alphabet,
-    engine::{self, general_purpose},
-    Engine as _,
-};
+use std::{collections::HashMap, io::Cursor};
+
+use base64::Engine as _;
 use zip::{read::ZipFile, ZipArchive};
 
-fn process_zip_string(
-    zip_string: String,
-) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
-    let mut files: HashMap<String, String> = HashMap::new();
+use super::archive::{ArchiveLimits, Files, URL_SAFE_INDIFFERENT};
+use crate::{error::ArchiveError, model::FileEntry};
+
+/// File type bits of a Unix mode, and the value for symbolic links
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
 
+/// Expand a URL-safe base64 ZIP archive into a map of path to contents
+///
+/// Use `archive::process_archive_string` to accept any supported format.
+pub fn process_zip_string(
+    zip_string: &str,
+    limits: &ArchiveLimits,
+) -> Result<HashMap<String, FileEntry>, ArchiveError> {
     // Decode the base64 ZIP string to an array of bytes
-    let decoded_zip_bytes: Vec<u8> =
-        engine::GeneralPurpose::new(&alphabet::URL_SAFE, general_purpose::NO_PAD)
-            .decode(zip_string.as_str())
-            .map_err(|err| format!("Couldn't decode ZIP string: {}", err))?;
+    let decoded_zip_bytes: Vec<u8> = URL_SAFE_INDIFFERENT
+        .decode(zip_string.trim())
+        .map_err(|_| ArchiveError::InvalidEncoding)?;
+
+    expand_zip(&decoded_zip_bytes, limits)
+}
+
+/// Expand a ZIP archive into a map of path to contents
+///
+/// Directory entries are skipped and symbolic links are refused.
+pub(crate) fn expand_zip(
+    bytes: &[u8],
+    limits: &ArchiveLimits,
+) -> Result<HashMap<String, FileEntry>, ArchiveError> {
+    let invalid = |err: zip::result::ZipError| ArchiveError::InvalidArchive(err.to_string());
+    let mut files = Files::new(limits);
 
     // Create a ZIP from the decoded bytes
-    let decoded_zip: ZipArchive<Cursor<Vec<u8>>> = ZipArchive::new(Cursor::new(decoded_zip_bytes))
-        .map_err(|err| format!("Couldn't open ZIP archive: {}", err))?;
-    let decoded_zip_length: usize = decoded_zip.len();
+    let mut decoded_zip: ZipArchive<Cursor<&[u8]>> =
+        ZipArchive::new(Cursor::new(bytes)).map_err(invalid)?;
 
-    for i in 0..decoded_zip_length {
-        let mut decoded_zip = decoded_zip.clone();
-        let mut file: ZipFile = decoded_zip
-            .by_index(i)
-            .map_err(|err| format!("Couldn't open file in ZIP archive: {}", err))?;
+    for i in 0..decoded_zip.len() {
+        files.entry()?;
+        let file: ZipFile = decoded_zip.by_index(i).map_err(invalid)?;
 
         let filename = file.name().to_owned();
-        let mut contents: String = String::new();
+        if file
+            .unix_mode()
+            .is_some_and(|mode| mode & S_IFMT == S_IFLNK)
+        {
+            return Err(ArchiveError::Symlink(filename));
+        }
 
-        file.read_to_string(&mut contents)
-            .map_err(|e| format!("Couldn't read file in ZIP archive: {}", e))?;
+        if file.is_dir() {
+            continue;
+        }
 
-        files.insert(filename.clone(), contents.clone());
+        let size = file.size();
+        let executable = file.unix_mode().is_some_and(|mode| mode & 0o111 != 0);
+        files.add(filename, Some(size), executable, file)?;
     }
 
-    Ok(files)
+    Ok(files.into_inner())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use base64::engine::general_purpose;
+    use std::io::Write;
+    use zip::{write::FileOptions, ZipWriter};
+
+    /// Build a ZIP archive from a list of path and contents
+    ///
+    /// Paths ending with `/` are added as directories.
+    pub(crate) fn get_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (path, contents) in files {
+            if path.ends_with('/') {
+                writer.add_directory(*path, FileOptions::default()).unwrap();
+            } else {
+                writer.start_file(*path, FileOptions::default()).unwrap();
+                writer.write_all(contents).unwrap();
+            }
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    /// Build a URL-safe base64 ZIP archive from a list of path and contents
+    pub(crate) fn get_zip_string(files: &[(&str, &[u8])]) -> String {
+        general_purpose::URL_SAFE_NO_PAD.encode(get_zip(files))
+    }
+
+    fn expand(zip_string: &str) -> Result<HashMap<String, FileEntry>, ArchiveError> {
+        process_zip_string(zip_string, &ArchiveLimits::default())
+    }
+
+    #[test]
+    fn zip_expand() {
+        let zip_string = get_zip_string(&[
+            ("src/", b""),
+            ("src/main.py", b"print('hello')\n"),
+            ("README.md", b""),
+        ]);
+
+        let files = expand(&zip_string).unwrap();
+
+        assert_eq!(
+            files,
+            HashMap::from([
+                ("src/main.py".to_owned(), "print('hello')\n".into()),
+                ("README.md".to_owned(), "".into()),
+            ])
+        );
+    }
+
+    #[test]
+    fn zip_padded_base64() {
+        let zip_string = get_zip_string(&[("main.py", b"")]);
+        let padding = "=".repeat((4 - zip_string.len() % 4) % 4);
+
+        let files = expand(&format!("{}{}", zip_string, padding)).unwrap();
+
+        assert_eq!(files.len(), 1);
+    }
+
+    #[test]
+    fn zip_invalid_base64() {
+        assert_eq!(expand("not base64!"), Err(ArchiveError::InvalidEncoding));
+    }
+
+    #[test]
+    fn zip_invalid_archive() {
+        let res = expand(&general_purpose::URL_SAFE_NO_PAD.encode("not a zip"));
+
+        assert!(matches!(res, Err(ArchiveError::InvalidArchive(_))));
+    }
+
+    #[test]
+    fn zip_binary_file() {
+        let zip_string = get_zip_string(&[("main.wasm", &[0xff, 0xfe])]);
+
+        let files = expand(&zip_string).unwrap();
+
+        assert_eq!(
+            files,
+            HashMap::from([("main.wasm".to_owned(), FileEntry::binary(vec![0xff, 0xfe]))])
+        );
+    }
+
+    #[test]
+    fn zip_executable_file() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .start_file("run.sh", FileOptions::default().unix_permissions(0o755))
+            .unwrap();
+        writer.write_all(b"#!/bin/sh\n").unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+
+        let files = expand(&general_purpose::URL_SAFE_NO_PAD.encode(bytes)).unwrap();
+
+        assert_eq!(
+            files,
+            HashMap::from([(
+                "run.sh".to_owned(),
+                FileEntry::text("#!/bin/sh\n").with_executable(true)
+            )])
+        );
+    }
+
+    #[test]
+    fn zip_too_many_entries() {
+        let zip_string = get_zip_string(&[("a.py", b""), ("b.py", b""), ("c.py", b"")]);
+        let limits = ArchiveLimits {
+            max_entries: 2,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            process_zip_string(&zip_string, &limits),
+            Err(ArchiveError::TooManyEntries { limit: 2 })
+        );
+    }
+
+    #[test]
+    fn zip_file_too_large() {
+        // A highly compressible file, as in a zip bomb
+        let contents = vec![b'a'; 4096];
+        let zip_string = get_zip_string(&[("main.py", &contents)]);
+        let limits = ArchiveLimits {
+            max_file_size: 1024,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            process_zip_string(&zip_string, &limits),
+            Err(ArchiveError::FileTooLarge {
+                path: "main.py".to_owned(),
+                limit: 1024
+            })
+        );
+    }
+
+    #[test]
+    fn zip_archive_too_large() {
+        let contents = vec![b'a'; 600];
+        let zip_string = get_zip_string(&[("a.py", &contents), ("b.py", &contents)]);
+        let limits = ArchiveLimits {
+            max_file_size: 1024,
+            max_total_size: 1024,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            process_zip_string(&zip_string, &limits),
+            Err(ArchiveError::ArchiveTooLarge { limit: 1024 })
+        );
+    }
+
+    #[test]
+    fn zip_unsafe_paths() {
+        for path in [
+            "../main.py",
+            "src/../../main.py",
+            "/etc/passwd",
+            "..\\main.py",
+            "C:\\main.py",
+        ] {
+            let zip_string = get_zip_string(&[(path, b"")]);
+
+            assert_eq!(
+                expand(&zip_string),
+                Err(ArchiveError::UnsafePath(path.to_owned())),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn zip_symlink() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .add_symlink("main.py", "/etc/passwd", FileOptions::default())
+            .unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+
+        assert_eq!(
+            expand(&general_purpose::URL_SAFE_NO_PAD.encode(bytes)),
+            Err(ArchiveError::Symlink("main.py".to_owned()))
+        );
+    }
 }